@@ -0,0 +1,17 @@
+-- Refresh tokens: each user_sessions row pairs a short-lived access token
+-- (token_jti) with an opaque refresh token, stored hashed. Rotating a refresh
+-- token creates a new row in the same family; presenting an already rotated
+-- token revokes the whole family.
+ALTER TABLE user_sessions
+ADD COLUMN refresh_token_hash VARCHAR(64) UNIQUE,
+ADD COLUMN family_id UUID,
+ADD COLUMN rotated_at TIMESTAMPTZ,
+ADD COLUMN revoked_at TIMESTAMPTZ;
+
+UPDATE user_sessions SET family_id = id WHERE family_id IS NULL;
+
+ALTER TABLE user_sessions
+ALTER COLUMN family_id SET NOT NULL,
+ALTER COLUMN family_id SET DEFAULT uuid_generate_v4 ();
+
+CREATE INDEX idx_user_sessions_family_id ON user_sessions (family_id);
@@ -0,0 +1,8 @@
+-- Device metadata for the active sessions list
+ALTER TABLE user_sessions
+ADD COLUMN device_label VARCHAR(100),
+ADD COLUMN ip_address INET,
+ADD COLUMN user_agent TEXT,
+ADD COLUMN last_used_at TIMESTAMPTZ DEFAULT NOW();
+
+UPDATE user_sessions SET last_used_at = created_at;
@@ -0,0 +1,5 @@
+-- Distinguish phone verification codes from SMS login codes
+ALTER TABLE phone_verification_codes
+ADD COLUMN purpose VARCHAR(20) NOT NULL DEFAULT 'verification';
+
+CREATE INDEX idx_phone_verification_phone_purpose ON phone_verification_codes (phone, purpose);
@@ -0,0 +1,23 @@
+-- Single-use tokens for passwordless email sign-in
+CREATE TABLE magic_link_tokens (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE INDEX idx_magic_link_tokens_user_id ON magic_link_tokens (user_id);
+
+CREATE INDEX idx_magic_link_tokens_expires_at ON magic_link_tokens (expires_at);
+
+CREATE OR REPLACE FUNCTION cleanup_expired_tokens()
+RETURNS void AS $$
+BEGIN
+    DELETE FROM email_verification_tokens WHERE expires_at < NOW();
+    DELETE FROM phone_verification_codes WHERE expires_at < NOW();
+    DELETE FROM password_reset_tokens WHERE expires_at < NOW();
+    DELETE FROM magic_link_tokens WHERE expires_at < NOW();
+END;
+$$ LANGUAGE plpgsql;
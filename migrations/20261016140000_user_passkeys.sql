@@ -0,0 +1,16 @@
+-- WebAuthn passkeys
+CREATE TABLE user_passkeys (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    credential_id TEXT NOT NULL UNIQUE,
+    name VARCHAR(100) NOT NULL,
+    passkey JSONB NOT NULL,
+    last_used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT NOW(),
+    updated_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE INDEX idx_user_passkeys_user_id ON user_passkeys (user_id);
+
+CREATE TRIGGER update_user_passkeys_updated_at BEFORE UPDATE ON user_passkeys
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
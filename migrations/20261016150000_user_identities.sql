@@ -0,0 +1,29 @@
+-- External login identities (Google, Apple, ...) linked to an account. A user
+-- can have several, alongside a password, passkeys or a verified phone.
+CREATE TABLE user_identities (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    provider VARCHAR(50) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    email VARCHAR(255),
+    last_used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT NOW(),
+    UNIQUE (provider, subject)
+);
+
+CREATE INDEX idx_user_identities_user_id ON user_identities (user_id);
+
+-- Carry over the single identity OAuth accounts were created with
+INSERT INTO user_identities (user_id, provider, subject, email, last_used_at, created_at)
+SELECT id, auth_provider::TEXT, oauth_id, email, last_login_at, created_at
+FROM users
+WHERE auth_provider IN ('google', 'apple') AND oauth_id IS NOT NULL;
+
+-- OAuth accounts are now backed by user_identities rather than users.oauth_id
+ALTER TABLE users DROP CONSTRAINT users_auth_check;
+
+ALTER TABLE users ADD CONSTRAINT users_auth_check CHECK (
+    (auth_provider = 'email' AND email IS NOT NULL AND password_hash IS NOT NULL) OR
+    (auth_provider = 'phone' AND phone IS NOT NULL AND password_hash IS NOT NULL) OR
+    (auth_provider IN ('google', 'apple'))
+);
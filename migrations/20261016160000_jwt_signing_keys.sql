@@ -0,0 +1,15 @@
+-- Asymmetric (Ed25519) keys for signing access tokens. A key is published in
+-- the JWKS from creation, used for signing between activates_at and
+-- retires_at, and accepted for verification until expires_at.
+CREATE TABLE jwt_signing_keys (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    algorithm VARCHAR(20) NOT NULL DEFAULT 'EdDSA',
+    private_key BYTEA NOT NULL,
+    public_key TEXT NOT NULL,
+    activates_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    retires_at TIMESTAMPTZ,
+    expires_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE INDEX idx_jwt_signing_keys_activates_at ON jwt_signing_keys (activates_at);
@@ -0,0 +1,22 @@
+-- Sign-in history: every completed login and every failed attempt we can
+-- attribute, used for lockouts, new device detection and the activity API
+CREATE TABLE login_attempts (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    user_id UUID REFERENCES users (id) ON DELETE CASCADE,
+    identifier VARCHAR(255),
+    method VARCHAR(30) NOT NULL,
+    success BOOLEAN NOT NULL,
+    failure_reason VARCHAR(50),
+    ip_address INET,
+    user_agent TEXT,
+    device_label VARCHAR(100),
+    new_device BOOLEAN NOT NULL DEFAULT FALSE,
+    new_ip BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE INDEX idx_login_attempts_user_id ON login_attempts (user_id, created_at DESC);
+
+CREATE INDEX idx_login_attempts_ip_address ON login_attempts (ip_address, created_at DESC);
+
+CREATE INDEX idx_login_attempts_created_at ON login_attempts (created_at);
@@ -0,0 +1,29 @@
+-- Pending email / phone changes. The account keeps its current address until
+-- the new one is confirmed; one outstanding request per user and channel.
+CREATE TABLE contact_change_requests (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    channel VARCHAR(10) NOT NULL CHECK (channel IN ('email', 'phone')),
+    new_value VARCHAR(255) NOT NULL,
+    secret_hash VARCHAR(64) NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW(),
+    UNIQUE (user_id, channel)
+);
+
+CREATE INDEX idx_contact_change_requests_secret_hash ON contact_change_requests (secret_hash);
+
+CREATE INDEX idx_contact_change_requests_expires_at ON contact_change_requests (expires_at);
+
+-- Include expired change requests in the periodic token cleanup
+CREATE OR REPLACE FUNCTION cleanup_expired_tokens()
+RETURNS void AS $$
+BEGIN
+    DELETE FROM email_verification_tokens WHERE expires_at < NOW();
+    DELETE FROM phone_verification_codes WHERE expires_at < NOW();
+    DELETE FROM password_reset_tokens WHERE expires_at < NOW();
+    DELETE FROM magic_link_tokens WHERE expires_at < NOW();
+    DELETE FROM contact_change_requests WHERE expires_at < NOW();
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,23 @@
+-- Self-service account deletion. A scheduled account keeps working until
+-- deletion_scheduled_at; signing in before then cancels the deletion.
+ALTER TABLE users ADD COLUMN deletion_scheduled_at TIMESTAMPTZ;
+
+CREATE INDEX idx_users_deletion_scheduled_at ON users (deletion_scheduled_at)
+WHERE
+    deletion_scheduled_at IS NOT NULL;
+
+-- Scrubbed accounts keep their row (votes and thread structure still point
+-- at it) but no longer hold any credentials
+ALTER TABLE users DROP CONSTRAINT users_auth_check;
+
+ALTER TABLE users ADD CONSTRAINT users_auth_check CHECK (
+    (auth_provider = 'email' AND email IS NOT NULL AND password_hash IS NOT NULL) OR
+    (auth_provider = 'phone' AND phone IS NOT NULL AND password_hash IS NOT NULL) OR
+    (auth_provider IN ('google', 'apple')) OR
+    (status = 'deleted')
+);
+
+-- Placeholder author for posts and comments of deleted accounts
+INSERT INTO users (id, username, auth_provider, status)
+VALUES ('00000000-0000-0000-0000-000000000000', '[deleted]', 'email', 'deleted')
+ON CONFLICT (id) DO NOTHING;
@@ -0,0 +1,24 @@
+-- Personal data export archives, generated in the background and kept for
+-- download until expires_at
+CREATE TABLE data_exports (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (
+        status IN ('pending', 'processing', 'completed', 'failed')
+    ),
+    file_path VARCHAR(500),
+    file_size BIGINT,
+    error TEXT,
+    started_at TIMESTAMPTZ,
+    completed_at TIMESTAMPTZ,
+    expires_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE INDEX idx_data_exports_user_id ON data_exports (user_id, created_at DESC);
+
+CREATE INDEX idx_data_exports_status ON data_exports (status, created_at);
+
+CREATE INDEX idx_data_exports_expires_at ON data_exports (expires_at)
+WHERE
+    expires_at IS NOT NULL;
@@ -0,0 +1,74 @@
+-- Third-party applications that users can authorize through our OAuth2 /
+-- OpenID Connect provider. The client secret is stored hashed; public clients
+-- (SPAs, mobile apps) have none and rely on PKCE alone.
+CREATE TABLE oauth_clients (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    client_id VARCHAR(64) NOT NULL UNIQUE,
+    client_secret_hash VARCHAR(64),
+    owner_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    redirect_uris TEXT[] NOT NULL,
+    allowed_scopes TEXT[] NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW(),
+    updated_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE INDEX idx_oauth_clients_owner_id ON oauth_clients (owner_id);
+
+CREATE TRIGGER update_oauth_clients_updated_at
+    BEFORE UPDATE ON oauth_clients
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
+
+-- Single-use authorization codes, bound to the PKCE challenge and redirect URI
+CREATE TABLE oauth_authorization_codes (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    code_hash VARCHAR(64) NOT NULL UNIQUE,
+    client_id UUID NOT NULL REFERENCES oauth_clients (id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    redirect_uri TEXT NOT NULL,
+    scopes TEXT[] NOT NULL,
+    code_challenge VARCHAR(128) NOT NULL,
+    nonce VARCHAR(255),
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE INDEX idx_oauth_authorization_codes_expires_at ON oauth_authorization_codes (expires_at);
+
+-- Scopes each user has consented to per application
+CREATE TABLE oauth_grants (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    client_id UUID NOT NULL REFERENCES oauth_clients (id) ON DELETE CASCADE,
+    scopes TEXT[] NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW(),
+    updated_at TIMESTAMPTZ DEFAULT NOW(),
+    UNIQUE (user_id, client_id)
+);
+
+CREATE TRIGGER update_oauth_grants_updated_at
+    BEFORE UPDATE ON oauth_grants
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
+
+-- Tokens issued to an application are ordinary sessions restricted to the
+-- granted scopes
+ALTER TABLE user_sessions
+ADD COLUMN oauth_client_id UUID REFERENCES oauth_clients (id) ON DELETE CASCADE,
+ADD COLUMN scopes TEXT[];
+
+CREATE INDEX idx_user_sessions_oauth_client_id ON user_sessions (oauth_client_id)
+WHERE
+    oauth_client_id IS NOT NULL;
+
+-- Include expired authorization codes in the periodic token cleanup
+CREATE OR REPLACE FUNCTION cleanup_expired_tokens()
+RETURNS void AS $$
+BEGIN
+    DELETE FROM email_verification_tokens WHERE expires_at < NOW();
+    DELETE FROM phone_verification_codes WHERE expires_at < NOW();
+    DELETE FROM password_reset_tokens WHERE expires_at < NOW();
+    DELETE FROM magic_link_tokens WHERE expires_at < NOW();
+    DELETE FROM contact_change_requests WHERE expires_at < NOW();
+    DELETE FROM oauth_authorization_codes WHERE expires_at < NOW();
+END;
+$$ LANGUAGE plpgsql;
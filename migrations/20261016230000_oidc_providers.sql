@@ -0,0 +1,14 @@
+-- Accounts created through a generic OpenID Connect provider (corporate SSO,
+-- GitLab, Keycloak, ...). The provider name is kept on user_identities.
+ALTER TYPE auth_provider ADD VALUE IF NOT EXISTS 'oidc';
+
+-- External provider accounts are backed by user_identities; spelled without
+-- the new enum value, which can't be used in the transaction that adds it
+ALTER TABLE users DROP CONSTRAINT users_auth_check;
+
+ALTER TABLE users ADD CONSTRAINT users_auth_check CHECK (
+    (auth_provider = 'email' AND email IS NOT NULL AND password_hash IS NOT NULL) OR
+    (auth_provider = 'phone' AND phone IS NOT NULL AND password_hash IS NOT NULL) OR
+    (auth_provider NOT IN ('email', 'phone')) OR
+    (status = 'deleted')
+);
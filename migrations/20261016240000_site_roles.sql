@@ -0,0 +1,40 @@
+-- Site-wide roles, separate from per-community membership roles. The first
+-- admin has to be promoted by hand:
+--   UPDATE users SET site_role = 'admin' WHERE username = '...';
+CREATE TYPE site_role AS ENUM ('user', 'staff', 'admin');
+
+ALTER TABLE users ADD COLUMN site_role site_role NOT NULL DEFAULT 'user';
+
+CREATE INDEX idx_users_site_role ON users (site_role) WHERE site_role != 'user';
+
+-- Every action taken through the admin API. Rows are never updated or deleted;
+-- the target is kept as a bare id so the entry outlives whatever it points at.
+CREATE TABLE admin_audit_log (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    actor_id UUID NOT NULL REFERENCES users (id),
+    action VARCHAR(50) NOT NULL,
+    target_type VARCHAR(20) NOT NULL,
+    target_id UUID NOT NULL,
+    details JSONB NOT NULL DEFAULT '{}',
+    ip_address INET,
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE INDEX idx_admin_audit_log_created_at ON admin_audit_log (created_at DESC);
+
+CREATE INDEX idx_admin_audit_log_actor_id ON admin_audit_log (actor_id);
+
+CREATE INDEX idx_admin_audit_log_target ON admin_audit_log (target_type, target_id);
+
+CREATE OR REPLACE FUNCTION prevent_admin_audit_log_changes()
+RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'admin_audit_log is append-only';
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER admin_audit_log_immutable BEFORE UPDATE OR DELETE ON admin_audit_log
+    FOR EACH ROW EXECUTE FUNCTION prevent_admin_audit_log_changes();
+
+CREATE TRIGGER admin_audit_log_no_truncate BEFORE TRUNCATE ON admin_audit_log
+    FOR EACH STATEMENT EXECUTE FUNCTION prevent_admin_audit_log_changes();
@@ -0,0 +1,24 @@
+-- Suspensions issued by staff. A user has at most one open suspension; ended
+-- ones are kept as history. lifted_by is NULL when a suspension simply ran out.
+CREATE TABLE user_suspensions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    issued_by UUID NOT NULL REFERENCES users (id),
+    reason TEXT NOT NULL,
+    ends_at TIMESTAMPTZ,
+    lifted_at TIMESTAMPTZ,
+    lifted_by UUID REFERENCES users (id),
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_user_suspensions_open ON user_suspensions (user_id)
+WHERE
+    lifted_at IS NULL;
+
+CREATE INDEX idx_user_suspensions_ends_at ON user_suspensions (ends_at)
+WHERE
+    lifted_at IS NULL AND ends_at IS NOT NULL;
+
+-- Reinstatement notices (and data export notices) are system notifications,
+-- which the enum was missing
+ALTER TYPE notification_type ADD VALUE IF NOT EXISTS 'system_announcement';
@@ -0,0 +1,25 @@
+-- Invite codes for invite-only registration. max_uses and expires_at are NULL
+-- when unlimited; only staff can create such codes.
+CREATE TABLE invite_codes (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    code VARCHAR(32) NOT NULL UNIQUE,
+    created_by UUID NOT NULL REFERENCES users (id),
+    note VARCHAR(200),
+    max_uses INTEGER CHECK (max_uses > 0),
+    use_count INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE INDEX idx_invite_codes_created_by ON invite_codes (created_by, created_at DESC);
+
+-- Who registered with which code, and so who invited whom
+CREATE TABLE invite_redemptions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    invite_code_id UUID NOT NULL REFERENCES invite_codes (id),
+    user_id UUID NOT NULL UNIQUE REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE INDEX idx_invite_redemptions_invite_code_id ON invite_redemptions (invite_code_id);
@@ -0,0 +1,13 @@
+-- The home feed reads each followed author's top posts per sort order, the
+-- same way it reads each joined community's through the community indexes
+CREATE INDEX idx_posts_author_hot_active ON posts (author_id, hot_score DESC)
+WHERE
+    status = 'active';
+
+CREATE INDEX idx_posts_author_created_active ON posts (author_id, created_at DESC)
+WHERE
+    status = 'active';
+
+CREATE INDEX idx_posts_author_score_active ON posts (author_id, score DESC)
+WHERE
+    status = 'active';
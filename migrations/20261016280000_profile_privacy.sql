@@ -0,0 +1,15 @@
+-- Whether other users can see a profile's post and comment history. The
+-- owner always sees their own.
+ALTER TABLE user_preferences
+ADD COLUMN show_profile_posts BOOLEAN NOT NULL DEFAULT TRUE,
+ADD COLUMN show_profile_comments BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- Profile comment listings and the overview read one author's comments in
+-- date or score order
+CREATE INDEX idx_comments_author_created_active ON comments (author_id, created_at DESC)
+WHERE
+    status = 'active';
+
+CREATE INDEX idx_comments_author_score_active ON comments (author_id, score DESC)
+WHERE
+    status = 'active';
@@ -0,0 +1,56 @@
+-- Follower counts kept on the user row, the same way communities keep
+-- subscriber_count, so profiles and user lists don't have to count follows
+ALTER TABLE users
+ADD COLUMN follower_count INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN following_count INTEGER NOT NULL DEFAULT 0;
+
+UPDATE users u
+SET
+    follower_count = (
+        SELECT COUNT(*) FROM user_follows f WHERE f.following_id = u.id
+    ),
+    following_count = (
+        SELECT COUNT(*) FROM user_follows f WHERE f.follower_id = u.id
+    );
+
+CREATE OR REPLACE FUNCTION update_user_follow_counts()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        UPDATE users SET follower_count = follower_count + 1 WHERE id = NEW.following_id;
+        UPDATE users SET following_count = following_count + 1 WHERE id = NEW.follower_id;
+        RETURN NEW;
+    ELSIF TG_OP = 'DELETE' THEN
+        UPDATE users SET follower_count = follower_count - 1 WHERE id = OLD.following_id;
+        UPDATE users SET following_count = following_count - 1 WHERE id = OLD.follower_id;
+        RETURN OLD;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER update_user_follow_counts_trigger
+    AFTER INSERT OR DELETE ON user_follows
+    FOR EACH ROW EXECUTE FUNCTION update_user_follow_counts();
+
+-- Follower and following lists, newest first
+CREATE INDEX idx_user_follows_following_created ON user_follows (following_id, created_at DESC);
+
+CREATE INDEX idx_user_follows_follower_created ON user_follows (follower_id, created_at DESC);
+
+-- hide_followers keeps both of a user's lists to themselves; with
+-- require_follow_approval new follows wait in follow_requests
+ALTER TABLE user_preferences
+ADD COLUMN hide_followers BOOLEAN NOT NULL DEFAULT FALSE,
+ADD COLUMN require_follow_approval BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE follow_requests (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    requester_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    target_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ DEFAULT NOW(),
+    UNIQUE (requester_id, target_id),
+    CHECK (requester_id != target_id)
+);
+
+CREATE INDEX idx_follow_requests_target_created ON follow_requests (target_id, created_at DESC);
@@ -0,0 +1,2 @@
+-- Karma breakdowns and bucketed history read one user's history in date order
+CREATE INDEX idx_user_karma_history_user_created ON user_karma_history (user_id, created_at);
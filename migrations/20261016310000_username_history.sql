@@ -0,0 +1,16 @@
+-- Every username a user has changed away from. Old names keep resolving to
+-- the user, and nobody else can take them until reserved_until passes.
+CREATE TABLE username_history (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4 (),
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    old_username VARCHAR(50) NOT NULL,
+    new_username VARCHAR(50) NOT NULL,
+    reserved_until TIMESTAMPTZ NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Counting a user's recent changes and listing their history
+CREATE INDEX idx_username_history_user_changed ON username_history (user_id, changed_at DESC);
+
+-- Resolving an old name to whoever had it most recently
+CREATE INDEX idx_username_history_old_username ON username_history (old_username, changed_at DESC);
@@ -0,0 +1,4 @@
+-- Only one export can be pending or processing per user at a time
+CREATE UNIQUE INDEX idx_data_exports_user_in_progress ON data_exports (user_id)
+WHERE
+    status IN ('pending', 'processing');
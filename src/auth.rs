@@ -1,18 +1,33 @@
-use axum::{RequestPartsExt, extract::FromRequestParts, http::request::Parts};
+use axum::{
+    RequestPartsExt,
+    extract::{ConnectInfo, FromRequestParts, MatchedPath},
+    http::{header::USER_AGENT, request::Parts},
+};
 use axum_extra::{
     TypedHeader,
     headers::{Authorization, authorization::Bearer},
 };
+use base64::Engine;
 use chrono::{Duration, Utc};
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, SocketAddr};
 use uuid::Uuid;
 
 use crate::{
     AppState,
     error::{AppError, Result},
+    models::{SiteRole, User},
+    services::{
+        api_token_service, api_token_service::API_TOKEN_PREFIX, oauth_provider_service,
+        session_service, signing_key_service::SigningKeys, user_service,
+    },
 };
 
+/// How recent a sign-in must be to stand in for the current password.
+const REAUTH_WINDOW_MINUTES: i64 = 10;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String, // user_id
@@ -20,12 +35,34 @@ pub struct Claims {
     pub exp: i64,
     pub iat: i64,
     pub jti: String, // JWT ID for session management
+    // Set on tokens issued to third-party applications
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
 }
 
 impl Claims {
-    pub fn new(user_id: Uuid, username: String, jwt_secret: &str) -> Result<(String, Self)> {
+    pub fn new(
+        user_id: Uuid,
+        username: String,
+        signing_keys: &SigningKeys,
+        ttl: Duration,
+    ) -> Result<(String, Self)> {
+        Self::new_delegated(user_id, username, None, signing_keys, ttl)
+    }
+
+    /// Claims for a token restricted to the scopes a user granted an application
+    /// (`client_id`, space separated `scope`), or a full session when `None`.
+    pub fn new_delegated(
+        user_id: Uuid,
+        username: String,
+        delegation: Option<(&str, &[String])>,
+        signing_keys: &SigningKeys,
+        ttl: Duration,
+    ) -> Result<(String, Self)> {
         let now = Utc::now();
-        let exp = now + Duration::hours(24);
+        let exp = now + ttl;
         let jti = Uuid::new_v4().to_string();
 
         let claims = Self {
@@ -34,25 +71,17 @@ impl Claims {
             exp: exp.timestamp(),
             iat: now.timestamp(),
             jti: jti.clone(),
+            scope: delegation.map(|(_, scopes)| scopes.join(" ")),
+            client_id: delegation.map(|(client_id, _)| client_id.to_string()),
         };
 
-        let token = encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(jwt_secret.as_ref()),
-        )?;
+        let token = signing_keys.sign(&claims)?;
 
         Ok((token, claims))
     }
 
-    pub fn verify(token: &str, jwt_secret: &str) -> Result<Self> {
-        let token_data = decode::<Claims>(
-            token,
-            &DecodingKey::from_secret(jwt_secret.as_ref()),
-            &Validation::default(),
-        )?;
-
-        Ok(token_data.claims)
+    pub fn verify(token: &str, signing_keys: &SigningKeys) -> Result<Self> {
+        signing_keys.verify(token)
     }
 }
 
@@ -61,6 +90,34 @@ pub struct AuthUser {
     pub user_id: Uuid,
     pub username: String,
     pub jti: String,
+    pub site_role: SiteRole,
+    /// Scopes of a token issued to a third-party application or of a personal
+    /// API token; `None` for the user's own sessions, which can do everything.
+    pub scopes: Option<Vec<String>>,
+    pub client_id: Option<String>,
+}
+
+impl AuthUser {
+    pub fn is_delegated(&self) -> bool {
+        self.scopes.is_some()
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes
+            .as_ref()
+            .is_none_or(|scopes| scopes.iter().any(|granted| granted == scope))
+    }
+
+    /// Reject the request unless the user holds at least `role` site-wide.
+    pub fn require_role(&self, role: SiteRole) -> Result<()> {
+        if self.site_role < role {
+            return Err(AppError::Authorization(
+                "You don't have permission to do this".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 impl FromRequestParts<AppState> for AuthUser {
@@ -72,25 +129,90 @@ impl FromRequestParts<AppState> for AuthUser {
             .await
             .map_err(|_| AppError::Authentication("Missing authorization header".to_string()))?;
 
-        let claims = Claims::verify(bearer.token(), &state.config.jwt_secret)?;
+        // Personal API tokens are opaque; anything else is a session JWT
+        let auth_user = if bearer.token().starts_with(API_TOKEN_PREFIX) {
+            let owner = api_token_service::authenticate(&state.db, bearer.token())
+                .await?
+                .ok_or_else(|| AppError::Authentication("Invalid API token".to_string()))?;
+
+            let client = ClientInfo::from_request_parts(parts, state).await?;
+
+            // Bookkeeping only, a failure here shouldn't reject the request
+            if let Err(e) = api_token_service::touch_token(&state.db, owner.token_id, &client).await
+            {
+                tracing::warn!("Failed to update API token last-used time: {}", e);
+            }
 
-        // Check if session is still valid in Redis
-        if let Some(stored_user_id) = state.redis.get_session(&claims.jti).await? {
-            if stored_user_id != claims.sub {
-                return Err(AppError::Authentication("Invalid session".to_string()));
+            AuthUser {
+                user_id: owner.user_id,
+                username: owner.username,
+                jti: owner.token_id.to_string(),
+                site_role: owner.site_role,
+                scopes: Some(owner.scopes),
+                client_id: None,
             }
         } else {
-            return Err(AppError::Authentication("Session expired".to_string()));
-        }
+            let claims = Claims::verify(bearer.token(), &state.signing_keys)?;
 
-        let user_id = Uuid::parse_str(&claims.sub)
-            .map_err(|_| AppError::Authentication("Invalid user ID in token".to_string()))?;
+            // Check if session is still valid in Redis
+            if let Some(stored_user_id) = state.redis.get_session(&claims.jti).await? {
+                if stored_user_id != claims.sub {
+                    return Err(AppError::Authentication("Invalid session".to_string()));
+                }
+            } else {
+                return Err(AppError::Authentication("Session expired".to_string()));
+            }
 
-        Ok(AuthUser {
-            user_id,
-            username: claims.username,
-            jti: claims.jti,
-        })
+            let user_id = Uuid::parse_str(&claims.sub)
+                .map_err(|_| AppError::Authentication("Invalid user ID in token".to_string()))?;
+
+            // Looked up on every request so role changes and suspensions apply at once
+            let site_role = user_service::get_active_site_role(&state.db, user_id)
+                .await?
+                .ok_or_else(|| AppError::Authentication("Account is not active".to_string()))?;
+
+            // Bookkeeping only, a failure here shouldn't reject the request
+            if let Err(e) = session_service::touch_session(&state.db, &claims.jti).await {
+                tracing::warn!("Failed to update session last-used time: {}", e);
+            }
+
+            AuthUser {
+                user_id,
+                username: claims.username,
+                jti: claims.jti,
+                site_role,
+                scopes: claims
+                    .scope
+                    .map(|scope| scope.split_whitespace().map(str::to_string).collect()),
+                client_id: claims.client_id,
+            }
+        };
+
+        // Application and API tokens only reach routes covered by one of their scopes
+        if auth_user.is_delegated() {
+            let route = parts
+                .extensions
+                .get::<MatchedPath>()
+                .map(|path| path.as_str().to_string())
+                .unwrap_or_default();
+
+            match oauth_provider_service::required_scope(&parts.method, &route) {
+                Some(scope) if auth_user.has_scope(scope) => {}
+                Some(scope) => {
+                    return Err(AppError::Authorization(format!(
+                        "This token is missing the '{}' scope",
+                        scope
+                    )));
+                }
+                None => {
+                    return Err(AppError::Authorization(
+                        "This endpoint is not available to applications".to_string(),
+                    ));
+                }
+            }
+        }
+
+        Ok(auth_user)
     }
 }
 
@@ -109,6 +231,95 @@ impl FromRequestParts<AppState> for OptionalAuthUser {
     }
 }
 
+// Client metadata (IP address, user agent) recorded against sessions
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip_address: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+impl FromRequestParts<AppState> for ClientInfo {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self> {
+        let header = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+
+        let user_agent = header(USER_AGENT.as_str()).map(|ua| ua.chars().take(512).collect());
+
+        // Forwarded headers are client-controlled unless a proxy in front of us sets them
+        let forwarded_ip = if state.config.trust_proxy_headers {
+            header("x-forwarded-for")
+                .and_then(|value| {
+                    value
+                        .split(',')
+                        .next()
+                        .and_then(|ip| ip.trim().parse().ok())
+                })
+                .or_else(|| header("x-real-ip").and_then(|ip| ip.parse().ok()))
+        } else {
+            None
+        };
+
+        let ip_address = forwarded_ip.or_else(|| {
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        });
+
+        Ok(ClientInfo {
+            ip_address,
+            user_agent,
+        })
+    }
+}
+
+/// Sensitive account changes need proof the account owner is present: the
+/// current password, or for accounts without one (or when it's omitted) a
+/// sign-in within the last few minutes.
+pub async fn confirm_identity(
+    state: &AppState,
+    auth_user: &AuthUser,
+    user: &User,
+    current_password: Option<&str>,
+) -> Result<()> {
+    if let Some(password) = current_password {
+        let password_matches = match &user.password_hash {
+            Some(password_hash) => verify_password(password, password_hash)?,
+            None => false,
+        };
+
+        if !password_matches {
+            return Err(AppError::Authentication(
+                "Current password is incorrect".to_string(),
+            ));
+        }
+
+        return Ok(());
+    }
+
+    if session_service::is_recent_sign_in(
+        &state.db,
+        &auth_user.jti,
+        Duration::minutes(REAUTH_WINDOW_MINUTES),
+    )
+    .await?
+    {
+        return Ok(());
+    }
+
+    Err(AppError::Authentication(
+        "Please confirm your current password or sign in again".to_string(),
+    ))
+}
+
 // Password hashing utilities
 pub fn hash_password(password: &str) -> Result<String> {
     let cost = 12;
@@ -119,6 +330,44 @@ pub fn verify_password(password: &str, hash: &str) -> Result<bool> {
     bcrypt::verify(password, hash).map_err(AppError::from)
 }
 
+/// SHA-256 hex digest for high-entropy secrets (recovery codes, opaque tokens)
+/// that only ever need to be looked up, never recovered.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hmac_signature(value: &str, secret: &str) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(value.as_bytes());
+    mac
+}
+
+/// Append an HMAC-SHA256 signature to a value: `{value}.{signature}`.
+pub fn sign_value(value: &str, secret: &str) -> String {
+    let signature = hmac_signature(value, secret).finalize().into_bytes();
+    format!(
+        "{}.{}",
+        value,
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(signature)
+    )
+}
+
+/// Return the original value if the signature produced by `sign_value` checks out.
+pub fn verify_signed_value<'a>(signed: &'a str, secret: &str) -> Option<&'a str> {
+    let (value, signature) = signed.rsplit_once('.')?;
+    let signature = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(signature)
+        .ok()?;
+
+    hmac_signature(value, secret)
+        .verify_slice(&signature)
+        .ok()
+        .map(|_| value)
+}
+
 // OAuth providers
 #[derive(Debug, Deserialize)]
 pub struct GoogleUserInfo {
@@ -129,6 +378,13 @@ pub struct GoogleUserInfo {
     pub verified_email: bool,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct GoogleTokenInfo {
+    /// The client the token was issued to
+    pub aud: String,
+    pub sub: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct AppleUserInfo {
     pub sub: String,
@@ -151,3 +407,32 @@ pub async fn get_google_user_info(access_token: &str) -> Result<GoogleUserInfo>
     let user_info: GoogleUserInfo = response.json().await?;
     Ok(user_info)
 }
+
+/// Check a Google access token was issued to this app. Userinfo accepts a
+/// token obtained by any app, so on its own it would let another site that
+/// signed the user in pass their token off as ours.
+pub async fn verify_google_access_token(
+    access_token: &str,
+    client_id: &str,
+) -> Result<GoogleTokenInfo> {
+    let client = reqwest::Client::new();
+    let response = client
+        .get("https://oauth2.googleapis.com/tokeninfo")
+        .query(&[("access_token", access_token)])
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(AppError::Authentication("Invalid Google token".to_string()));
+    }
+
+    let token_info: GoogleTokenInfo = response.json().await?;
+
+    if client_id.is_empty() || token_info.aud != client_id {
+        return Err(AppError::Authentication(
+            "Google token was not issued to this app".to_string(),
+        ));
+    }
+
+    Ok(token_info)
+}
@@ -1,17 +1,63 @@
 use crate::models::UploadConfig;
 
+use serde::Serialize;
 use std::env;
 
+/// Who can create an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationMode {
+    Open,
+    /// Only with an invite code
+    InviteOnly,
+    Closed,
+}
+
+impl RegistrationMode {
+    /// Unrecognised values close registration rather than leave a private
+    /// instance open because of a typo.
+    fn from_env() -> Self {
+        match env::var("REGISTRATION_MODE").as_deref() {
+            Err(_) | Ok("open") => Self::Open,
+            Ok("invite_only") => Self::InviteOnly,
+            Ok(_) => Self::Closed,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub database_url: String,
     pub redis_url: String,
     pub jwt_secret: String,
+    pub access_token_ttl_minutes: i64,
+    pub refresh_token_ttl_days: i64,
+    pub jwt_key_rotation_days: i64,
+    pub jwt_key_grace_hours: i64,
     pub host: String,
     pub port: u16,
     pub upload_dir: String,
     pub max_file_size: usize,
     pub allowed_origins: Vec<String>,
+    pub trust_proxy_headers: bool,
+
+    // Password policy
+    pub password_min_length: usize,
+    pub password_require_uppercase: bool,
+    pub password_require_lowercase: bool,
+    pub password_require_digit: bool,
+    pub password_require_symbol: bool,
+    pub password_breach_list_dir: Option<String>,
+
+    // Accounts
+    pub account_deletion_grace_days: i64,
+    pub data_export_dir: String,
+    pub data_export_ttl_days: i64,
+    pub registration_mode: RegistrationMode,
+    pub user_invite_limit: i64,
+    pub username_change_limit: i64,
+    pub username_change_period_days: i64,
+    pub username_reservation_days: i64,
 
     // OAuth
     pub google_client_id: String,
@@ -20,6 +66,12 @@ pub struct Config {
     pub apple_team_id: Option<String>,
     pub apple_key_id: Option<String>,
     pub apple_private_key: Option<String>,
+    pub oidc_providers: Vec<OidcProviderConfig>,
+    pub oauth_client_redirect_uris: Vec<String>,
+
+    // WebAuthn
+    pub webauthn_rp_id: String,
+    pub webauthn_rp_origin: String,
 
     // Email - SendGrid
     pub sendgrid_api_key: String,
@@ -61,6 +113,28 @@ impl Config {
             database_url: env::var("DATABASE_URL")?,
             redis_url: env::var("REDIS_URL")?,
             jwt_secret: env::var("JWT_SECRET")?,
+            access_token_ttl_minutes: env::var("ACCESS_TOKEN_TTL_MINUTES")
+                .unwrap_or_else(|_| "15".to_string())
+                .parse()
+                .unwrap_or(15),
+            refresh_token_ttl_days: env::var("REFRESH_TOKEN_TTL_DAYS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .unwrap_or(30),
+            data_export_dir: env::var("DATA_EXPORT_DIR")
+                .unwrap_or_else(|_| "./exports".to_string()),
+            data_export_ttl_days: env::var("DATA_EXPORT_TTL_DAYS")
+                .unwrap_or_else(|_| "7".to_string())
+                .parse()
+                .unwrap_or(7),
+            jwt_key_rotation_days: env::var("JWT_KEY_ROTATION_DAYS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .unwrap_or(30),
+            jwt_key_grace_hours: env::var("JWT_KEY_GRACE_HOURS")
+                .unwrap_or_else(|_| "24".to_string())
+                .parse()
+                .unwrap_or(24),
             host: env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string()),
             port: env::var("PORT")
                 .unwrap_or_else(|_| "3000".to_string())
@@ -76,6 +150,51 @@ impl Config {
                 .split(',')
                 .map(|s| s.trim().to_string())
                 .collect(),
+            trust_proxy_headers: env::var("TRUST_PROXY_HEADERS")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+
+            // Password policy
+            password_min_length: env::var("PASSWORD_MIN_LENGTH")
+                .unwrap_or_else(|_| "8".to_string())
+                .parse()
+                .unwrap_or(8),
+            password_require_uppercase: env::var("PASSWORD_REQUIRE_UPPERCASE")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            password_require_lowercase: env::var("PASSWORD_REQUIRE_LOWERCASE")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            password_require_digit: env::var("PASSWORD_REQUIRE_DIGIT")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            password_require_symbol: env::var("PASSWORD_REQUIRE_SYMBOL")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            password_breach_list_dir: env::var("PASSWORD_BREACH_LIST_DIR").ok(),
+
+            // Accounts
+            account_deletion_grace_days: env::var("ACCOUNT_DELETION_GRACE_DAYS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .unwrap_or(30),
+            registration_mode: RegistrationMode::from_env(),
+            user_invite_limit: env::var("USER_INVITE_LIMIT")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()
+                .unwrap_or(5),
+            username_change_limit: env::var("USERNAME_CHANGE_LIMIT")
+                .unwrap_or_else(|_| "1".to_string())
+                .parse()
+                .unwrap_or(1),
+            username_change_period_days: env::var("USERNAME_CHANGE_PERIOD_DAYS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .unwrap_or(30),
+            username_reservation_days: env::var("USERNAME_RESERVATION_DAYS")
+                .unwrap_or_else(|_| "90".to_string())
+                .parse()
+                .unwrap_or(90),
 
             // OAuth
             google_client_id: env::var("GOOGLE_CLIENT_ID").unwrap_or_default(),
@@ -84,6 +203,18 @@ impl Config {
             apple_team_id: env::var("APPLE_TEAM_ID").ok(),
             apple_key_id: env::var("APPLE_KEY_ID").ok(),
             apple_private_key: env::var("APPLE_PRIVATE_KEY").ok(),
+            oidc_providers: OidcProviderConfig::all_from_env()?,
+            oauth_client_redirect_uris: env::var("OAUTH_CLIENT_REDIRECT_URIS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+
+            // WebAuthn
+            webauthn_rp_id: env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| "localhost".to_string()),
+            webauthn_rp_origin: env::var("WEBAUTHN_RP_ORIGIN")
+                .unwrap_or_else(|_| "http://localhost:3000".to_string()),
 
             // Email
             sendgrid_api_key: env::var("SENDGRID_API_KEY").unwrap_or_default(),
@@ -104,3 +235,72 @@ impl Config {
         })
     }
 }
+
+/// Which claims of a provider's ID token (or userinfo response) hold the
+/// profile fields. Dotted names reach into nested objects.
+#[derive(Debug, Clone)]
+pub struct OidcClaimMapping {
+    pub subject: String,
+    pub email: String,
+    pub email_verified: String,
+    pub name: String,
+    pub picture: String,
+}
+
+/// A generic OpenID Connect login provider, listed by name in OIDC_PROVIDERS
+/// and configured through OIDC_<NAME>_* variables.
+#[derive(Debug, Clone)]
+pub struct OidcProviderConfig {
+    pub name: String,
+    pub display_name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub scopes: Vec<String>,
+    pub redirect_uri: Option<String>,
+    pub claims: OidcClaimMapping,
+    // Treat emails as verified even without an email_verified claim, for
+    // providers that only hand out addresses they own (e.g. corporate SSO)
+    pub trust_email: bool,
+}
+
+impl OidcProviderConfig {
+    fn all_from_env() -> Result<Vec<Self>, env::VarError> {
+        let names = env::var("OIDC_PROVIDERS").unwrap_or_default();
+
+        names
+            .split(',')
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .map(|name| Self::from_env(&name))
+            .collect()
+    }
+
+    fn from_env(name: &str) -> Result<Self, env::VarError> {
+        let prefix = format!("OIDC_{}_", name.to_uppercase().replace('-', "_"));
+        let var = |key: &str| env::var(format!("{}{}", prefix, key));
+        let claim = |key: &str, default: &str| var(key).unwrap_or_else(|_| default.to_string());
+
+        Ok(Self {
+            name: name.to_string(),
+            display_name: var("DISPLAY_NAME").unwrap_or_else(|_| name.to_string()),
+            issuer: var("ISSUER")?,
+            client_id: var("CLIENT_ID")?,
+            client_secret: var("CLIENT_SECRET").ok(),
+            scopes: var("SCOPES")
+                .unwrap_or_else(|_| "openid email profile".to_string())
+                .split_whitespace()
+                .map(|scope| scope.to_string())
+                .collect(),
+            redirect_uri: var("REDIRECT_URI").ok(),
+            claims: OidcClaimMapping {
+                subject: claim("SUBJECT_CLAIM", "sub"),
+                email: claim("EMAIL_CLAIM", "email"),
+                email_verified: claim("EMAIL_VERIFIED_CLAIM", "email_verified"),
+                name: claim("NAME_CLAIM", "name"),
+                picture: claim("PICTURE_CLAIM", "picture"),
+            },
+            trust_email: var("TRUST_EMAIL").map(|v| v == "true").unwrap_or(false),
+        })
+    }
+}
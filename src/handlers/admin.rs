@@ -0,0 +1,301 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    AppState,
+    auth::{AuthUser, ClientInfo},
+    error::Result,
+    models::{CommunityStatus, SiteRole, UserStatus},
+    services::{
+        admin_service,
+        admin_service::{Actor, AuditLogFilters, UserSearchFilters},
+        invite_service,
+        invite_service::NewInvite,
+        suspension_service,
+    },
+};
+
+#[derive(Debug, Deserialize)]
+pub struct AdminUserSearchQuery {
+    pub q: Option<String>,
+    pub status: Option<UserStatus>,
+    pub site_role: Option<SiteRole>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditLogQuery {
+    pub actor_id: Option<Uuid>,
+    pub target_id: Option<Uuid>,
+    pub action: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SuspendUserRequest {
+    #[validate(length(min = 1, max = 1000))]
+    pub reason: String,
+    /// Leave out for an indefinite suspension
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct AdminActionRequest {
+    #[validate(length(max = 1000))]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminInviteQuery {
+    pub created_by: Option<Uuid>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateInviteRequest {
+    #[validate(length(max = 200))]
+    pub note: Option<String>,
+    /// Leave out for unlimited uses
+    #[validate(range(min = 1))]
+    pub max_uses: Option<i32>,
+    /// Leave out for a code that doesn't expire
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetSiteRoleRequest {
+    pub site_role: SiteRole,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SetCommunityStatusRequest {
+    pub status: CommunityStatus,
+    #[validate(length(max = 1000))]
+    pub reason: Option<String>,
+}
+
+pub async fn get_stats(State(state): State<AppState>, auth_user: AuthUser) -> Result<Json<Value>> {
+    auth_user.require_role(SiteRole::Staff)?;
+
+    let stats = admin_service::get_site_stats(&state.db).await?;
+
+    Ok(Json(json!({
+        "stats": stats
+    })))
+}
+
+pub async fn search_users(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<AdminUserSearchQuery>,
+) -> Result<Json<Value>> {
+    auth_user.require_role(SiteRole::Staff)?;
+
+    let limit = params.limit.unwrap_or(25).min(100);
+    let offset = params.offset.unwrap_or(0);
+
+    let filters = UserSearchFilters {
+        query: params.q.as_deref(),
+        status: params.status,
+        site_role: params.site_role,
+    };
+
+    let (users, total) = admin_service::search_users(&state.db, &filters, limit, offset).await?;
+
+    Ok(Json(json!({
+        "users": users,
+        "total": total,
+        "limit": limit,
+        "offset": offset
+    })))
+}
+
+pub async fn suspend_user(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    client: ClientInfo,
+    Path(user_id): Path<Uuid>,
+    Json(payload): Json<SuspendUserRequest>,
+) -> Result<Json<Value>> {
+    auth_user.require_role(SiteRole::Staff)?;
+    payload.validate()?;
+
+    let actor = Actor::new(&auth_user, &client);
+    admin_service::suspend_user(&state, &actor, user_id, &payload.reason, payload.ends_at).await?;
+
+    Ok(Json(json!({
+        "message": "User suspended successfully"
+    })))
+}
+
+pub async fn unsuspend_user(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    client: ClientInfo,
+    Path(user_id): Path<Uuid>,
+    Json(payload): Json<AdminActionRequest>,
+) -> Result<Json<Value>> {
+    auth_user.require_role(SiteRole::Staff)?;
+    payload.validate()?;
+
+    let actor = Actor::new(&auth_user, &client);
+    admin_service::unsuspend_user(&state, &actor, user_id, payload.reason.as_deref()).await?;
+
+    Ok(Json(json!({
+        "message": "User unsuspended successfully"
+    })))
+}
+
+pub async fn get_user_suspensions(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<Value>> {
+    auth_user.require_role(SiteRole::Staff)?;
+
+    let suspensions = suspension_service::list_suspensions(&state.db, user_id).await?;
+
+    Ok(Json(json!({
+        "suspensions": suspensions
+    })))
+}
+
+pub async fn set_site_role(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    client: ClientInfo,
+    Path(user_id): Path<Uuid>,
+    Json(payload): Json<SetSiteRoleRequest>,
+) -> Result<Json<Value>> {
+    auth_user.require_role(SiteRole::Admin)?;
+
+    let actor = Actor::new(&auth_user, &client);
+    admin_service::set_site_role(&state.db, &actor, user_id, payload.site_role).await?;
+
+    Ok(Json(json!({
+        "message": "Role updated successfully"
+    })))
+}
+
+pub async fn set_community_status(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    client: ClientInfo,
+    Path(name): Path<String>,
+    Json(payload): Json<SetCommunityStatusRequest>,
+) -> Result<Json<Value>> {
+    auth_user.require_role(SiteRole::Staff)?;
+    payload.validate()?;
+
+    let actor = Actor::new(&auth_user, &client);
+    let community = admin_service::set_community_status(
+        &state.db,
+        &actor,
+        &name,
+        payload.status,
+        payload.reason.as_deref(),
+    )
+    .await?;
+
+    Ok(Json(json!({
+        "message": "Community status updated successfully",
+        "community": community
+    })))
+}
+
+pub async fn get_invites(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<AdminInviteQuery>,
+) -> Result<Json<Value>> {
+    auth_user.require_role(SiteRole::Staff)?;
+
+    let limit = params.limit.unwrap_or(25).min(100);
+    let offset = params.offset.unwrap_or(0);
+
+    let invites = invite_service::list_invites(&state.db, params.created_by, limit, offset).await?;
+
+    Ok(Json(json!({
+        "invites": invites
+    })))
+}
+
+pub async fn create_invite(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    client: ClientInfo,
+    Json(payload): Json<CreateInviteRequest>,
+) -> Result<(StatusCode, Json<Value>)> {
+    auth_user.require_role(SiteRole::Staff)?;
+    payload.validate()?;
+
+    let actor = Actor::new(&auth_user, &client);
+    let invite = admin_service::create_invite(
+        &state.db,
+        &actor,
+        &NewInvite {
+            note: payload.note.as_deref(),
+            max_uses: payload.max_uses,
+            expires_at: payload.expires_at,
+        },
+    )
+    .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "message": "Invite code created",
+            "invite": invite
+        })),
+    ))
+}
+
+pub async fn revoke_invite(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    client: ClientInfo,
+    Path(invite_id): Path<Uuid>,
+) -> Result<Json<Value>> {
+    auth_user.require_role(SiteRole::Staff)?;
+
+    let actor = Actor::new(&auth_user, &client);
+    admin_service::revoke_invite(&state.db, &actor, invite_id).await?;
+
+    Ok(Json(json!({
+        "message": "Invite code revoked successfully"
+    })))
+}
+
+pub async fn get_audit_log(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<AuditLogQuery>,
+) -> Result<Json<Value>> {
+    auth_user.require_role(SiteRole::Admin)?;
+
+    let limit = params.limit.unwrap_or(50).min(100);
+    let offset = params.offset.unwrap_or(0);
+
+    let filters = AuditLogFilters {
+        actor_id: params.actor_id,
+        target_id: params.target_id,
+        action: params.action.as_deref(),
+    };
+
+    let entries = admin_service::list_audit_log(&state.db, &filters, limit, offset).await?;
+
+    Ok(Json(json!({
+        "entries": entries
+    })))
+}
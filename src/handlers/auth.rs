@@ -1,23 +1,38 @@
 use axum::{
     Form,
-    extract::{Query, State},
-    http::StatusCode,
-    response::{Json, Redirect},
+    extract::{Path, Query, State},
+    http::{
+        HeaderMap, StatusCode,
+        header::{CACHE_CONTROL, SET_COOKIE},
+    },
+    response::{IntoResponse, Json, Redirect, Response},
 };
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use tokio::join;
 use uuid::Uuid;
 use validator::Validate;
+use webauthn_rs::prelude::{PublicKeyCredential, RegisterPublicKeyCredential};
 
 use sqlx::Row;
 
 use crate::{
     AppState,
-    auth::{AuthUser, Claims, get_google_user_info, hash_password, verify_password},
+    auth::{
+        AuthUser, ClientInfo, confirm_identity, get_google_user_info, hash_password,
+        verify_password,
+    },
     error::{AppError, Result},
-    models::{AuthProvider, PasswordResetToken, PhoneVerificationCode, User, UserStatus},
-    services::auth_service,
+    models::{AuthProvider, PasswordResetToken, User, UserStatus},
+    services::{
+        account_deletion_service, auth_service, contact_change_service, identity_service,
+        identity_service::ExternalProfile,
+        invite_service, login_security_service, oidc_service,
+        oidc_service::{OidcProvider, PendingAuthorization},
+        passkey_service, password_service, session_service, social_login_service,
+        social_login_service::PendingLogin,
+        suspension_service, two_factor_service, user_service, username_service,
+    },
 };
 
 #[derive(Debug, Deserialize, Validate)]
@@ -28,8 +43,10 @@ pub struct RegisterRequest {
     pub email: Option<String>,
     #[validate(length(min = 10, max = 20))]
     pub phone: Option<String>,
-    #[validate(length(min = 8))]
     pub password: String,
+    /// Required when registration is invite-only
+    #[validate(length(max = 32))]
+    pub invite_code: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -38,14 +55,78 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+#[derive(Debug, Deserialize, Validate)]
+pub struct FinishPasskeyRegistrationRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: Option<String>,
+    pub credential: RegisterPublicKeyCredential,
+    pub current_password: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FinishPasskeyLoginRequest {
+    pub challenge_id: String,
+    pub credential: PublicKeyCredential,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct MagicLinkRequest {
+    #[validate(email)]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyMagicLinkRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct PhoneLoginCodeRequest {
+    #[validate(length(min = 10, max = 20))]
+    pub phone: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct PhoneLoginVerifyRequest {
+    #[validate(length(min = 10, max = 20))]
+    pub phone: String,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorLoginRequest {
+    pub challenge_token: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisableTwoFactorRequest {
+    pub password: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct GoogleOAuthRequest {
     pub access_token: String,
+    pub current_password: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct AppleOAuthRequest {
     pub id_token: String,
+    pub current_password: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -57,7 +138,12 @@ pub struct ForgotPasswordRequest {
 #[derive(Debug, Deserialize, Validate)]
 pub struct ResetPasswordRequest {
     pub token: String,
-    #[validate(length(min = 8))]
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
     pub new_password: String,
 }
 
@@ -66,6 +152,11 @@ pub struct VerifyEmailRequest {
     pub token: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ConfirmEmailChangeRequest {
+    pub token: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct VerifyPhoneRequest {
     pub phone: String,
@@ -86,6 +177,8 @@ pub struct UserResponse {
     pub display_name: Option<String>,
     pub avatar_url: Option<String>,
     pub karma_points: i32,
+    pub follower_count: i32,
+    pub following_count: i32,
     pub is_verified: bool,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
@@ -99,6 +192,8 @@ impl From<User> for UserResponse {
             display_name: user.display_name,
             avatar_url: user.avatar_url,
             karma_points: user.karma_points,
+            follower_count: user.follower_count,
+            following_count: user.following_count,
             is_verified: user.is_verified,
             created_at: user.created_at,
         }
@@ -107,11 +202,14 @@ impl From<User> for UserResponse {
 
 pub async fn register(
     State(state): State<AppState>,
+    client: ClientInfo,
     Json(payload): Json<RegisterRequest>,
 ) -> Result<(StatusCode, Json<Value>)> {
     // Validate input
     payload.validate()?;
 
+    invite_service::ensure_registration_allowed(&state.config, payload.invite_code.as_deref())?;
+
     // Check if email or phone is provided
     if payload.email.is_none() && payload.phone.is_none() {
         return Err(AppError::BadRequest(
@@ -119,6 +217,14 @@ pub async fn register(
         ));
     }
 
+    password_service::validate_password(
+        &state.config,
+        &payload.password,
+        Some(&payload.username),
+        payload.email.as_deref(),
+    )
+    .await?;
+
     // Rate limiting
     let rate_limit_key = format!(
         "register_attempt:{}",
@@ -178,6 +284,14 @@ pub async fn register(
         return Err(AppError::Conflict("Username already exists".to_string()));
     }
 
+    if username_service::is_reserved(&mut *state.db.acquire().await?, &payload.username, None)
+        .await?
+    {
+        return Err(AppError::Conflict(
+            "This username was recently used by someone else and is reserved".to_string(),
+        ));
+    }
+
     if existing_email.is_some() {
         return Err(AppError::Conflict("Email already exists".to_string()));
     }
@@ -195,6 +309,8 @@ pub async fn register(
     let user_id = Uuid::new_v4();
     let now = chrono::Utc::now();
 
+    let mut tx = state.db.begin().await?;
+
     let user = sqlx::query_as::<_, User>(
         r#"
         INSERT INTO users (
@@ -214,7 +330,7 @@ pub async fn register(
     .bind(UserStatus::Active)
     .bind(now)
     .bind(now)
-    .fetch_one(&state.db)
+    .fetch_one(&mut *tx)
     .await?;
 
     // Create user preferences with defaults
@@ -234,17 +350,20 @@ pub async fn register(
     .bind(user_id)
     .bind(now)
     .bind(now)
-    .execute(&state.db)
+    .execute(&mut *tx)
     .await?;
 
-    // Generate JWT token
-    let (token, claims) = Claims::new(user.id, user.username.clone(), &state.config.jwt_secret)?;
+    let invite_code = payload
+        .invite_code
+        .as_deref()
+        .filter(|code| !code.trim().is_empty());
+    if let Some(invite_code) = invite_code {
+        invite_service::redeem(&mut tx, invite_code, user_id).await?;
+    }
+
+    tx.commit().await?;
 
-    // Store session in Redis
-    state
-        .redis
-        .store_session(&claims.jti, &user.id.to_string(), 86400)
-        .await?;
+    let tokens = session_service::create_session(&state, &user, &client).await?;
 
     // Send verification email and SMS in parallel if provided
     let email_task = async {
@@ -346,16 +465,19 @@ pub async fn register(
         StatusCode::CREATED,
         Json(json!({
             "message": "User registered successfully",
-            "token": token,
+            "token": tokens.access_token,
+            "refresh_token": tokens.refresh_token,
+            "expires_in": tokens.expires_in,
             "user": UserResponse::from(user)
         })),
     ))
 }
 pub async fn login(
     State(state): State<AppState>,
+    client: ClientInfo,
     Json(payload): Json<LoginRequest>,
 ) -> Result<(StatusCode, Json<Value>)> {
-    // Rate limiting
+    // Rate limiting, on top of the per-account lockout
     let rate_limit_key = format!("login_attempt:{}", payload.username_or_email);
     if !state
         .redis
@@ -370,7 +492,7 @@ pub async fn login(
         r#"
         SELECT * FROM users 
         WHERE (username = $1 OR email = $1) 
-        AND status = 'active'
+        AND status != 'deleted'
         AND auth_provider = 'email'
         "#,
     )
@@ -378,338 +500,1204 @@ pub async fn login(
     .fetch_optional(&state.db)
     .await?;
 
-    let user = user.ok_or_else(|| AppError::Authentication("Invalid credentials".to_string()))?;
+    let identifier = payload.username_or_email.as_str();
+    login_security_service::check_lockout(
+        &state,
+        user.as_ref().map(|user| user.id),
+        Some(identifier),
+        &client,
+    )
+    .await?;
+
+    let Some(user) = user else {
+        login_security_service::record_failure(
+            &state,
+            None,
+            Some(identifier),
+            "password",
+            "unknown_account",
+            &client,
+        )
+        .await?;
+        return Err(AppError::Authentication("Invalid credentials".to_string()));
+    };
 
     // Verify password
-    let password_hash = user
-        .password_hash
-        .clone()
-        .ok_or_else(|| AppError::Authentication("Invalid credentials".to_string()))?;
+    let password_matches = match &user.password_hash {
+        Some(password_hash) => verify_password(&payload.password, password_hash)?,
+        None => false,
+    };
 
-    if !verify_password(&payload.password, &password_hash)? {
+    if !password_matches {
+        login_security_service::record_failure(
+            &state,
+            Some(user.id),
+            Some(identifier),
+            "password",
+            "invalid_password",
+            &client,
+        )
+        .await?;
         return Err(AppError::Authentication("Invalid credentials".to_string()));
     }
 
-    // Update last login
-    sqlx::query("UPDATE users SET last_login_at = $1 WHERE id = $2")
-        .bind(chrono::Utc::now())
-        .bind(user.id)
-        .execute(&state.db)
-        .await?;
-
-    // Generate JWT token
-    let (token, claims) = Claims::new(user.id, user.username.clone(), &state.config.jwt_secret)?;
+    complete_first_factor(&state, user, &client, "password").await
+}
 
-    // Store session in Redis
-    state
-        .redis
-        .store_session(&claims.jti, &user.id.to_string(), 86400)
-        .await?;
+/// Finish a successful first-factor login (password, SMS code, ...). With 2FA
+/// enabled this only earns a short-lived challenge token, not a session.
+async fn complete_first_factor(
+    state: &AppState,
+    user: User,
+    client: &ClientInfo,
+    method: &str,
+) -> Result<(StatusCode, Json<Value>)> {
+    // Only after the first factor checks out, so this doesn't reveal suspensions
+    suspension_service::ensure_not_suspended(state, &user).await?;
+
+    if two_factor_service::is_enabled(&state.db, user.id).await? {
+        let challenge_token =
+            two_factor_service::create_login_challenge(&state.redis, user.id).await?;
+
+        return Ok((
+            StatusCode::OK,
+            Json(json!({
+                "message": "Two-factor authentication required",
+                "two_factor_required": true,
+                "challenge_token": challenge_token
+            })),
+        ));
+    }
 
-    Ok((
-        StatusCode::OK,
-        Json(json!({
-            "message": "Login successful",
-            "token": token,
-            "user": UserResponse::from(user)
-        })),
-    ))
+    complete_login(state, user, client, method).await
 }
 
-pub async fn logout(
+pub async fn start_passkey_registration(
     State(state): State<AppState>,
     auth_user: AuthUser,
-) -> Result<(StatusCode, Json<Value>)> {
-    // Remove session from Redis
-    state.redis.delete_session(&auth_user.jti).await?;
+) -> Result<Json<Value>> {
+    let user = user_service::get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
 
-    // Invalidate session in database
-    sqlx::query("UPDATE user_sessions SET expires_at = NOW() WHERE token_jti = $1")
-        .bind(&auth_user.jti)
-        .execute(&state.db)
-        .await?;
+    let options = passkey_service::start_registration(&state, &user).await?;
 
-    Ok((
-        StatusCode::OK,
-        Json(json!({
-            "message": "Logout successful"
-        })),
-    ))
+    Ok(Json(json!({
+        "options": options
+    })))
 }
 
-pub async fn refresh_token(
+pub async fn finish_passkey_registration(
     State(state): State<AppState>,
     auth_user: AuthUser,
+    Json(payload): Json<FinishPasskeyRegistrationRequest>,
 ) -> Result<(StatusCode, Json<Value>)> {
-    // Get user from database
-    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1 AND status = 'active'")
-        .bind(auth_user.user_id)
-        .fetch_optional(&state.db)
-        .await?;
+    payload.validate()?;
 
-    let user = user.ok_or_else(|| AppError::Authentication("User not found".to_string()))?;
+    // A passkey signs in without a password or second factor, so a stolen
+    // session alone mustn't be enough to add one
+    let user = user_service::get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
 
-    // Generate new JWT token
-    let (token, claims) = Claims::new(user.id, user.username.clone(), &state.config.jwt_secret)?;
+    confirm_identity(
+        &state,
+        &auth_user,
+        &user,
+        payload.current_password.as_deref(),
+    )
+    .await?;
 
-    // Remove old session and store new one
-    state.redis.delete_session(&auth_user.jti).await?;
-    state
-        .redis
-        .store_session(&claims.jti, &user.id.to_string(), 86400)
-        .await?;
+    let name = payload.name.as_deref().unwrap_or("Passkey");
+    let passkey =
+        passkey_service::finish_registration(&state, user.id, name, &payload.credential).await?;
 
     Ok((
-        StatusCode::OK,
+        StatusCode::CREATED,
         Json(json!({
-            "token": token,
-            "user": UserResponse::from(user)
+            "message": "Passkey registered successfully",
+            "passkey": passkey
         })),
     ))
 }
 
-pub async fn initiate_apple_oauth(State(state): State<AppState>) -> Result<Redirect> {
-    let (auth_url, csrf_token) = state.apple_service.get_authorization_url();
+pub async fn start_passkey_login(State(state): State<AppState>) -> Result<Json<Value>> {
+    let (challenge_id, options) = passkey_service::start_authentication(&state).await?;
 
-    let state_key = format!("apple-oauth-state-{}", csrf_token.secret());
+    Ok(Json(json!({
+        "challenge_id": challenge_id,
+        "options": options
+    })))
+}
 
-    state
-        .redis
-        .cache_set(&state_key, csrf_token.secret(), 10 * 60)
-        .await?;
+/// Passkeys require user verification on the authenticator, so they already
+/// count as two factors and skip the TOTP challenge.
+pub async fn finish_passkey_login(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<FinishPasskeyLoginRequest>,
+) -> Result<(StatusCode, Json<Value>)> {
+    let user_id =
+        passkey_service::finish_authentication(&state, &payload.challenge_id, &payload.credential)
+            .await?;
 
-    Ok(Redirect::to(auth_url.as_str()))
-}
+    let user =
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1 AND status != 'deleted'")
+            .bind(user_id)
+            .fetch_optional(&state.db)
+            .await?
+            .ok_or_else(|| AppError::Authentication("Passkey not recognised".to_string()))?;
 
-#[derive(Debug, Deserialize)]
-pub struct AppleOAuthCallbackForm {
-    pub code: String,
-    pub state: String,
-    pub id_token: Option<String>,
+    complete_login(&state, user, &client, "passkey").await
 }
 
-pub async fn apple_oauth(
+pub async fn request_magic_link(
     State(state): State<AppState>,
-    Form(form): Form<AppleOAuthCallbackForm>,
+    client: ClientInfo,
+    Json(payload): Json<MagicLinkRequest>,
 ) -> Result<(StatusCode, Json<Value>)> {
-    let state_key = format!("apple-oauth-state-{}", form.state);
-    let csrf_token = match state.redis.cache_get(&state_key).await {
-        Ok(Some(state)) => state,
-        Ok(None) => {
-            return Err(AppError::Authorization("Invalid OAuth state".to_string()));
-        }
-        Err(e) => {
-            tracing::error!("Failed to retrieve OAuth state: {}", e);
-            return Err(AppError::Internal("OAuth verification failed".to_string()));
-        }
-    };
+    payload.validate()?;
 
-    if !csrf_token.eq(&form.state) {
-        return Err(AppError::Authorization("Invalid OAuth state".to_string()));
+    let rate_limit_key = format!("magic_link:{}", payload.email);
+    if !state
+        .redis
+        .check_rate_limit(&rate_limit_key, 3, 3600)
+        .await?
+    {
+        return Err(AppError::RateLimit);
     }
 
-    let _ = state.redis.cache_delete(&state_key).await;
-
-    let apple_user = match state.apple_service.get_user_data(form.id_token).await {
-        Ok(user) => user,
-        Err(e) => {
-            tracing::error!("Apple OAuth service {}", e);
-            return Err(AppError::Authorization("No ID token provided".to_string()));
+    if let Some(ip) = client.ip_address {
+        let ip_rate_limit_key = format!("magic_link_ip:{}", ip);
+        if !state
+            .redis
+            .check_rate_limit(&ip_rate_limit_key, 10, 3600)
+            .await?
+        {
+            return Err(AppError::RateLimit);
         }
-    };
+    }
 
-    let existing_user = sqlx::query_as::<_, User>(
-        "SELECT * FROM users WHERE oauth_id = $1 AND auth_provider = 'apple' AND status != 'deleted'"
-    )
-    .bind(&apple_user.user_id)
-    .fetch_optional(&state.db)
-    .await?;
+    // Same response whether or not the email belongs to an account
+    let response = Ok((
+        StatusCode::OK,
+        Json(json!({
+            "message": "If an account exists with this email, a sign-in link has been sent"
+        })),
+    ));
 
-    let user = if let Some(user) = existing_user {
-        sqlx::query("UPDATE users SET last_login_at = $1 WHERE id = $2")
-            .bind(chrono::Utc::now())
-            .bind(user.id)
-            .execute(&state.db)
+    let user =
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1 AND status = 'active'")
+            .bind(&payload.email)
+            .fetch_optional(&state.db)
             .await?;
-        user
-    } else {
-        todo!("User creation logic")
+
+    let Some(user) = user else {
+        return response;
     };
 
-    let (token, claims) = Claims::new(user.id, user.username.clone(), &state.config.jwt_secret)?;
+    let token = auth_service::create_magic_link_token(&state, &user).await?;
 
-    state
-        .redis
-        .store_session(&claims.jti, &user.id.to_string(), 86400)
-        .await?;
+    if let Err(e) = state
+        .email_service
+        .send_magic_link_email(
+            &payload.email,
+            &user.username,
+            &token,
+            &state.config.base_url,
+        )
+        .await
+    {
+        tracing::error!("Failed to send magic link to user {}: {}", user.id, e);
+    }
 
-    Ok((
-        StatusCode::OK,
-        Json(json!({
-            "message": "Apple OAuth successful",
-            "token": token,
-            "user": UserResponse::from(user)
-        })),
-    ))
+    response
 }
 
-pub async fn initiate_google_oauth(State(state): State<AppState>) -> Result<Redirect> {
-    let (auth_url, csrf_token) = state.google_service.get_authorization_url();
-
-    let state_key = format!("oauth-state-{}", csrf_token.secret());
+pub async fn verify_magic_link(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<VerifyMagicLinkRequest>,
+) -> Result<(StatusCode, Json<Value>)> {
+    let user_id = auth_service::consume_magic_link_token(&state, &payload.token)
+        .await?
+        .ok_or_else(|| AppError::Authentication("Invalid or expired sign-in link".to_string()))?;
 
-    state
-        .redis
-        .cache_set(&state_key, csrf_token.secret(), 10 * 60)
-        .await?;
+    let user =
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1 AND status != 'deleted'")
+            .bind(user_id)
+            .fetch_optional(&state.db)
+            .await?
+            .ok_or_else(|| {
+                AppError::Authentication("Invalid or expired sign-in link".to_string())
+            })?;
+
+    // Following the link proves the user controls the mailbox
+    if !user.email_verified {
+        sqlx::query("UPDATE users SET email_verified = true, updated_at = $1 WHERE id = $2")
+            .bind(chrono::Utc::now())
+            .bind(user.id)
+            .execute(&state.db)
+            .await?;
+    }
 
-    Ok(Redirect::to(auth_url.as_str()))
+    complete_first_factor(&state, user, &client, "magic_link").await
 }
 
-#[derive(Debug, Deserialize)]
-pub struct OAuthCallbackQuery {
-    pub code: String,
-    pub state: String,
-}
+const PHONE_LOGIN_PURPOSE: &str = "login";
 
-pub async fn google_oauth(
+pub async fn request_phone_login_code(
     State(state): State<AppState>,
-    Query(query): Query<OAuthCallbackQuery>,
+    client: ClientInfo,
+    Json(payload): Json<PhoneLoginCodeRequest>,
 ) -> Result<(StatusCode, Json<Value>)> {
-    let state_key = format!("oauth-state-{}", query.state);
-    let csrf_token = match state.redis.cache_get(&state_key).await {
-        Ok(Some(state)) => state,
-        Ok(None) => {
-            return Err(AppError::Authorization("Invalid OAuth state".to_string()));
-        }
-        Err(e) => {
-            tracing::error!("Failed to retrieve OAuth state: {}", e);
-            return Err(AppError::Internal("OAuth verification failed".to_string()));
-        }
-    };
+    payload.validate()?;
 
-    if !csrf_token.eq(&query.state) {
-        return Err(AppError::Authorization("Invalid OAuth state".to_string()));
-    }
+    check_phone_login_rate_limit(&state, "phone_login_code", &payload.phone, &client, 3, 10)
+        .await?;
+
+    // Same response whether or not the number belongs to an account
+    let response = Ok((
+        StatusCode::OK,
+        Json(json!({
+            "message": "If an account exists for this phone number, a login code has been sent"
+        })),
+    ));
 
-    let _ = state.redis.cache_delete(&state_key).await;
+    let Some(user) = find_phone_login_user(&state, &payload.phone).await? else {
+        return response;
+    };
 
-    let access_token = match state
-        .google_service
-        .exchange_code_for_token(&query.code)
+    let code =
+        auth_service::create_phone_code(&state.db, &payload.phone, PHONE_LOGIN_PURPOSE).await?;
+
+    if let Err(e) = state
+        .sms_service
+        .send_login_code(&payload.phone, &code, &state.config.app_name)
         .await
     {
-        Ok(token) => token,
-        Err(e) => {
-            tracing::error!("Failed to exchange code for token: {}", e);
-            return Err(AppError::Authorization(
-                "Failed to authenticate with Google".to_string(),
-            ));
-        }
-    };
+        tracing::error!("Failed to send login code to user {}: {}", user.id, e);
+    }
+
+    response
+}
 
-    let google_user = get_google_user_info(access_token.secret()).await?;
+pub async fn verify_phone_login_code(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<PhoneLoginVerifyRequest>,
+) -> Result<(StatusCode, Json<Value>)> {
+    payload.validate()?;
 
-    // Check if user already exists
-    let existing_user = sqlx::query_as::<_, User>(
-        "SELECT * FROM users WHERE oauth_id = $1 AND auth_provider = 'google' AND status != 'deleted'"
+    check_phone_login_rate_limit(
+        &state,
+        "phone_login_verify",
+        &payload.phone,
+        &client,
+        10,
+        30,
     )
-    .bind(&google_user.id)
-    .fetch_optional(&state.db)
     .await?;
 
-    let user = if let Some(user) = existing_user {
-        // Update last login
-        sqlx::query("UPDATE users SET last_login_at = $1 WHERE id = $2")
-            .bind(chrono::Utc::now())
-            .bind(user.id)
-            .execute(&state.db)
-            .await?;
-        user
-    } else {
-        // Create new user
-        let user_id = Uuid::new_v4();
-        let now = chrono::Utc::now();
-
-        // Generate unique username from Google name
-        let base_username = google_user
-            .name
-            .to_lowercase()
-            .chars()
-            .filter(|c| c.is_alphanumeric())
-            .collect::<String>();
+    let user = find_phone_login_user(&state, &payload.phone).await?;
+    let user_id = user.as_ref().map(|user| user.id);
 
-        let username = auth_service::generate_unique_username(&state.db, &base_username).await?;
+    login_security_service::check_lockout(&state, user_id, Some(&payload.phone), &client).await?;
 
-        let user = sqlx::query_as::<_, User>(
-            r#"
-            INSERT INTO users (
-                id, username, email, display_name, avatar_url,
-                auth_provider, oauth_id, email_verified, status,
-                created_at, updated_at, last_login_at
-            )
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
-            RETURNING *
-            "#,
-        )
-        .bind(user_id)
-        .bind(&username)
-        .bind(&google_user.email)
-        .bind(&google_user.name)
-        .bind(&google_user.picture)
-        .bind(AuthProvider::Google)
-        .bind(&google_user.id)
-        .bind(google_user.verified_email)
-        .bind(UserStatus::Active)
-        .bind(now)
-        .bind(now)
-        .bind(now)
-        .fetch_one(&state.db)
-        .await?;
+    let verified = auth_service::check_phone_code(
+        &state.db,
+        &payload.phone,
+        PHONE_LOGIN_PURPOSE,
+        &payload.code,
+    )
+    .await?;
 
-        // Create user preferences
-        sqlx::query(
-            r#"
-            INSERT INTO user_preferences (
-                id, user_id, email_notifications, push_notifications,
-                comment_reply_notifications, post_reply_notifications,
-                mention_notifications, upvote_notifications,
-                community_notifications, nsfw_content,
-                created_at, updated_at
-            )
-            VALUES ($1, $2, true, true, true, true, true, false, true, false, $3, $4)
-            "#,
+    let (true, Some(user)) = (verified, user) else {
+        login_security_service::record_failure(
+            &state,
+            user_id,
+            Some(&payload.phone),
+            "phone",
+            "invalid_code",
+            &client,
         )
-        .bind(Uuid::new_v4())
-        .bind(user_id)
-        .bind(now)
-        .bind(now)
-        .execute(&state.db)
         .await?;
-
-        user
+        return Err(AppError::Authentication(
+            "Invalid or expired login code".to_string(),
+        ));
     };
 
-    // Generate JWT token
-    let (token, claims) = Claims::new(user.id, user.username.clone(), &state.config.jwt_secret)?;
+    complete_first_factor(&state, user, &client, "phone").await
+}
 
-    // Store session in Redis
-    state
-        .redis
-        .store_session(&claims.jti, &user.id.to_string(), 86400)
-        .await?;
+/// Users who may sign in by SMS: the number must be the account's sign-up
+/// number or one they've verified, otherwise whoever holds it could take over.
+async fn find_phone_login_user(state: &AppState, phone: &str) -> Result<Option<User>> {
+    let user = sqlx::query_as::<_, User>(
+        r#"
+        SELECT * FROM users
+        WHERE phone = $1
+        AND status = 'active'
+        AND (auth_provider = 'phone' OR phone_verified = true)
+        "#,
+    )
+    .bind(phone)
+    .fetch_optional(&state.db)
+    .await?;
 
-    Ok((
-        StatusCode::OK,
-        Json(json!({
-            "message": "Google OAuth successful",
-            "token": token,
-            "user": UserResponse::from(user)
-        })),
-    ))
+    Ok(user)
 }
 
-pub async fn forgot_password(
+async fn check_phone_login_rate_limit(
+    state: &AppState,
+    action: &str,
+    phone: &str,
+    client: &ClientInfo,
+    phone_limit: u32,
+    ip_limit: u32,
+) -> Result<()> {
+    let phone_key = format!("{}:{}", action, phone);
+    if !state
+        .redis
+        .check_rate_limit(&phone_key, phone_limit, 900)
+        .await?
+    {
+        return Err(AppError::RateLimit);
+    }
+
+    if let Some(ip) = client.ip_address {
+        let ip_key = format!("{}_ip:{}", action, ip);
+        if !state
+            .redis
+            .check_rate_limit(&ip_key, ip_limit, 3600)
+            .await?
+        {
+            return Err(AppError::RateLimit);
+        }
+    }
+
+    Ok(())
+}
+
+/// Second step of a 2FA login: exchange the challenge token plus a TOTP or
+/// recovery code for a session.
+pub async fn verify_two_factor_login(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<TwoFactorLoginRequest>,
+) -> Result<(StatusCode, Json<Value>)> {
+    let rate_limit_key = format!("2fa_attempt:{}", payload.challenge_token);
+    if !state
+        .redis
+        .check_rate_limit(&rate_limit_key, 5, 300)
+        .await?
+    {
+        return Err(AppError::RateLimit);
+    }
+
+    let user_id = two_factor_service::get_login_challenge(&state.redis, &payload.challenge_token)
+        .await?
+        .ok_or_else(|| {
+            AppError::Authentication("Invalid or expired challenge token".to_string())
+        })?;
+
+    // Per-user limit so fresh challenges can't be used to keep guessing
+    let user_rate_limit_key = format!("2fa_attempt_user:{}", user_id);
+    if !state
+        .redis
+        .check_rate_limit(&user_rate_limit_key, 10, 900)
+        .await?
+    {
+        return Err(AppError::RateLimit);
+    }
+
+    login_security_service::check_lockout(&state, Some(user_id), None, &client).await?;
+
+    let totp_secret = two_factor_service::get_totp_secret(&state.db, user_id)
+        .await?
+        .filter(|secret| secret.confirmed_at.is_some())
+        .ok_or_else(|| {
+            AppError::Authentication("Invalid or expired challenge token".to_string())
+        })?;
+
+    let verified = two_factor_service::verify_second_factor(
+        &state.db,
+        &totp_secret,
+        payload.code.as_deref(),
+        payload.recovery_code.as_deref(),
+    )
+    .await?;
+
+    if !verified {
+        login_security_service::record_failure(
+            &state,
+            Some(user_id),
+            None,
+            "two_factor",
+            "invalid_two_factor_code",
+            &client,
+        )
+        .await?;
+        return Err(AppError::Authentication(
+            "Invalid two-factor code".to_string(),
+        ));
+    }
+
+    two_factor_service::clear_login_challenge(&state.redis, &payload.challenge_token).await?;
+
+    let user =
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1 AND status != 'deleted'")
+            .bind(user_id)
+            .fetch_optional(&state.db)
+            .await?
+            .ok_or_else(|| AppError::Authentication("Invalid credentials".to_string()))?;
+
+    complete_login(&state, user, &client, "two_factor").await
+}
+
+async fn complete_login(
+    state: &AppState,
+    user: User,
+    client: &ClientInfo,
+    method: &str,
+) -> Result<(StatusCode, Json<Value>)> {
+    suspension_service::ensure_not_suspended(state, &user).await?;
+
+    // Update last login
+    sqlx::query("UPDATE users SET last_login_at = $1 WHERE id = $2")
+        .bind(chrono::Utc::now())
+        .bind(user.id)
+        .execute(&state.db)
+        .await?;
+
+    // Signing in during the grace period keeps the account
+    let deletion_cancelled = user.deletion_scheduled_at.is_some()
+        && account_deletion_service::cancel_deletion(&state.db, user.id).await?;
+
+    record_login_success(state, &user, method, client).await;
+
+    let tokens = session_service::create_session(state, &user, client).await?;
+
+    let message = if deletion_cancelled {
+        "Login successful. Your account deletion has been cancelled"
+    } else {
+        "Login successful"
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "message": message,
+            "token": tokens.access_token,
+            "refresh_token": tokens.refresh_token,
+            "expires_in": tokens.expires_in,
+            "user": UserResponse::from(user)
+        })),
+    ))
+}
+
+// Bookkeeping and alerting only, a failure here shouldn't fail the login
+async fn record_login_success(state: &AppState, user: &User, method: &str, client: &ClientInfo) {
+    if let Err(e) = login_security_service::record_success(state, user, method, client).await {
+        tracing::error!("Failed to record login for user {}: {}", user.id, e);
+    }
+}
+
+pub async fn get_two_factor_status(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Value>> {
+    let enabled = two_factor_service::is_enabled(&state.db, auth_user.user_id).await?;
+    let recovery_codes_remaining = if enabled {
+        two_factor_service::count_unused_recovery_codes(&state.db, auth_user.user_id).await?
+    } else {
+        0
+    };
+
+    Ok(Json(json!({
+        "enabled": enabled,
+        "recovery_codes_remaining": recovery_codes_remaining
+    })))
+}
+
+/// Start 2FA enrollment. Returns the secret and an otpauth URI; 2FA is not
+/// enforced until the user confirms with a first code.
+pub async fn enroll_two_factor(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Value>> {
+    let user = user_service::get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    if user.password_hash.is_none() {
+        return Err(AppError::BadRequest(
+            "Two-factor authentication is only available for password-based accounts".to_string(),
+        ));
+    }
+
+    let secret = two_factor_service::begin_enrollment(&state.db, user.id).await?;
+    let otpauth_uri =
+        two_factor_service::otpauth_uri(&secret, &state.config.app_name, &user.username)?;
+
+    Ok(Json(json!({
+        "secret": secret,
+        "otpauth_uri": otpauth_uri
+    })))
+}
+
+pub async fn confirm_two_factor(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<TwoFactorCodeRequest>,
+) -> Result<(StatusCode, Json<Value>)> {
+    check_two_factor_rate_limit(&state, auth_user.user_id).await?;
+
+    let totp_secret = two_factor_service::get_totp_secret(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::BadRequest("No two-factor enrollment in progress".to_string()))?;
+
+    if totp_secret.confirmed_at.is_some() {
+        return Err(AppError::Conflict(
+            "Two-factor authentication is already enabled".to_string(),
+        ));
+    }
+
+    if !two_factor_service::verify_totp(&state.db, &totp_secret, &payload.code).await? {
+        return Err(AppError::BadRequest("Invalid two-factor code".to_string()));
+    }
+
+    two_factor_service::confirm_enrollment(&state.db, auth_user.user_id).await?;
+    let recovery_codes =
+        two_factor_service::generate_recovery_codes(&state.db, auth_user.user_id).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "message": "Two-factor authentication enabled",
+            "recovery_codes": recovery_codes
+        })),
+    ))
+}
+
+pub async fn disable_two_factor(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<DisableTwoFactorRequest>,
+) -> Result<(StatusCode, Json<Value>)> {
+    check_two_factor_rate_limit(&state, auth_user.user_id).await?;
+
+    let user = user_service::get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    let password_hash = user
+        .password_hash
+        .as_deref()
+        .ok_or_else(|| AppError::Authentication("Invalid credentials".to_string()))?;
+
+    if !verify_password(&payload.password, password_hash)? {
+        return Err(AppError::Authentication("Invalid credentials".to_string()));
+    }
+
+    let totp_secret = two_factor_service::get_totp_secret(&state.db, user.id)
+        .await?
+        .filter(|secret| secret.confirmed_at.is_some())
+        .ok_or_else(|| {
+            AppError::BadRequest("Two-factor authentication is not enabled".to_string())
+        })?;
+
+    let verified = two_factor_service::verify_second_factor(
+        &state.db,
+        &totp_secret,
+        payload.code.as_deref(),
+        payload.recovery_code.as_deref(),
+    )
+    .await?;
+
+    if !verified {
+        return Err(AppError::Authentication(
+            "Invalid two-factor code".to_string(),
+        ));
+    }
+
+    two_factor_service::disable(&state.db, user.id).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "message": "Two-factor authentication disabled"
+        })),
+    ))
+}
+
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<TwoFactorCodeRequest>,
+) -> Result<(StatusCode, Json<Value>)> {
+    check_two_factor_rate_limit(&state, auth_user.user_id).await?;
+
+    let totp_secret = two_factor_service::get_totp_secret(&state.db, auth_user.user_id)
+        .await?
+        .filter(|secret| secret.confirmed_at.is_some())
+        .ok_or_else(|| {
+            AppError::BadRequest("Two-factor authentication is not enabled".to_string())
+        })?;
+
+    if !two_factor_service::verify_totp(&state.db, &totp_secret, &payload.code).await? {
+        return Err(AppError::Authentication(
+            "Invalid two-factor code".to_string(),
+        ));
+    }
+
+    let recovery_codes =
+        two_factor_service::generate_recovery_codes(&state.db, auth_user.user_id).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "recovery_codes": recovery_codes
+        })),
+    ))
+}
+
+async fn check_two_factor_rate_limit(state: &AppState, user_id: Uuid) -> Result<()> {
+    let rate_limit_key = format!("2fa_attempt_user:{}", user_id);
+    if !state
+        .redis
+        .check_rate_limit(&rate_limit_key, 10, 900)
+        .await?
+    {
+        return Err(AppError::RateLimit);
+    }
+
+    Ok(())
+}
+
+pub async fn logout(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<(StatusCode, Json<Value>)> {
+    // Revoke the access token and its refresh token family
+    session_service::revoke_session_by_jti(&state, &auth_user.jti).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "message": "Logout successful"
+        })),
+    ))
+}
+
+/// Change the password of a signed-in user. Other sessions are signed out;
+/// the one making the change stays signed in.
+pub async fn change_password(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<(StatusCode, Json<Value>)> {
+    let rate_limit_key = format!("change_password:{}", auth_user.user_id);
+    if !state
+        .redis
+        .check_rate_limit(&rate_limit_key, 5, 900)
+        .await?
+    {
+        return Err(AppError::RateLimit);
+    }
+
+    let user = user_service::get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    let current_hash = user
+        .password_hash
+        .as_deref()
+        .ok_or_else(|| AppError::BadRequest("This account doesn't use a password".to_string()))?;
+
+    if !verify_password(&payload.current_password, current_hash)? {
+        return Err(AppError::Authentication(
+            "Current password is incorrect".to_string(),
+        ));
+    }
+
+    if payload.new_password == payload.current_password {
+        return Err(AppError::BadRequest(
+            "New password must be different from the current password".to_string(),
+        ));
+    }
+
+    password_service::validate_password(
+        &state.config,
+        &payload.new_password,
+        Some(&user.username),
+        user.email.as_deref(),
+    )
+    .await?;
+
+    let password_hash = hash_password(&payload.new_password)?;
+
+    sqlx::query("UPDATE users SET password_hash = $1, updated_at = $2 WHERE id = $3")
+        .bind(&password_hash)
+        .bind(chrono::Utc::now())
+        .bind(user.id)
+        .execute(&state.db)
+        .await?;
+
+    session_service::revoke_other_sessions(&state, user.id, &auth_user.jti).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "message": "Password changed successfully"
+        })),
+    ))
+}
+
+pub async fn refresh_token(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<RefreshTokenRequest>,
+) -> Result<(StatusCode, Json<Value>)> {
+    let (user, tokens) =
+        session_service::refresh_session(&state, &payload.refresh_token, &client, None).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "token": tokens.access_token,
+            "refresh_token": tokens.refresh_token,
+            "expires_in": tokens.expires_in,
+            "user": UserResponse::from(user)
+        })),
+    ))
+}
+
+/// Public keys for verifying access tokens, so other services don't need a shared secret.
+pub async fn jwks(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(CACHE_CONTROL, "public, max-age=300")],
+        Json(state.signing_keys.jwks()),
+    )
+}
+
+/// Sign in with a provider identity: the account it is linked to, or a brand
+/// new account if it isn't linked to anyone yet and the registration mode
+/// allows it. An existing account with 2FA only gets a challenge token, as
+/// with a password.
+async fn sign_in_with_identity(
+    state: &AppState,
+    auth_provider: AuthProvider,
+    profile: &ExternalProfile,
+    invite_code: Option<&str>,
+    client: &ClientInfo,
+) -> Result<Value> {
+    let existing_user =
+        identity_service::find_user_by_identity(&state.db, &profile.provider, &profile.subject)
+            .await?;
+
+    if let Some(user) = existing_user {
+        let (_, Json(body)) = complete_first_factor(state, user, client, &profile.provider).await?;
+        return Ok(body);
+    }
+
+    invite_service::ensure_registration_allowed(&state.config, invite_code)?;
+
+    let user =
+        identity_service::create_user_with_identity(&state.db, auth_provider, profile, invite_code)
+            .await?;
+
+    let (_, Json(body)) = complete_login(state, user, client, &profile.provider).await?;
+
+    Ok(body)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StartSocialLoginQuery {
+    /// Client page or app deep link to send the result to, instead of
+    /// returning it from the provider callback
+    pub redirect_uri: Option<String>,
+    /// Used if the sign-in creates a new account
+    pub invite_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExchangeLoginCodeRequest {
+    pub code: String,
+}
+
+/// Finish a Google or Apple callback. Without a client redirect the result is
+/// returned as JSON; with one, the user is sent there with a login code to
+/// exchange for the tokens, or with the error.
+async fn social_login_response(
+    state: &AppState,
+    provider: &str,
+    redirect_uri: Option<&str>,
+    result: Result<Value>,
+) -> Result<Response> {
+    let clear_cookie = [(
+        SET_COOKIE,
+        social_login_service::clear_binding_cookie(provider),
+    )];
+
+    let Some(redirect_uri) = redirect_uri else {
+        return Ok(match result {
+            Ok(body) => (clear_cookie, Json(body)).into_response(),
+            Err(e) => (clear_cookie, e).into_response(),
+        });
+    };
+
+    let location = match result {
+        Ok(body) => {
+            let code = social_login_service::create_login_code(&state.redis, &body).await?;
+            social_login_service::client_redirect(redirect_uri, &[("code", &code)])?
+        }
+        Err(e) => social_login_service::client_redirect(
+            redirect_uri,
+            &[
+                ("error", "login_failed"),
+                (
+                    "error_description",
+                    &social_login_service::client_error_message(&e),
+                ),
+            ],
+        )?,
+    };
+
+    Ok((clear_cookie, Redirect::to(&location)).into_response())
+}
+
+/// Redeem the login code a Google or Apple sign-in redirected to the client
+/// with for the session tokens, or the 2FA challenge if the account has it.
+pub async fn exchange_login_code(
+    State(state): State<AppState>,
+    Json(payload): Json<ExchangeLoginCodeRequest>,
+) -> Result<Json<Value>> {
+    let result = social_login_service::take_login_code(&state.redis, &payload.code).await?;
+
+    Ok(Json(result))
+}
+
+pub async fn initiate_apple_oauth(
+    State(state): State<AppState>,
+    Query(query): Query<StartSocialLoginQuery>,
+) -> Result<impl IntoResponse> {
+    // Apple posts back from its own origin, so the cookie has to be SameSite=None
+    let login = social_login_service::start_login(
+        &state,
+        "apple",
+        query.redirect_uri.as_deref(),
+        query.invite_code.as_deref(),
+        true,
+    )
+    .await?;
+
+    let auth_url =
+        state
+            .apple_service
+            .get_authorization_url(&login.state, &login.nonce, &login.code_verifier);
+
+    Ok((
+        [(SET_COOKIE, login.cookie)],
+        Redirect::to(auth_url.as_str()),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AppleOAuthCallbackForm {
+    pub code: Option<String>,
+    pub state: String,
+    pub error: Option<String>,
+}
+
+pub async fn apple_oauth(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Form(form): Form<AppleOAuthCallbackForm>,
+) -> Result<Response> {
+    let pending =
+        social_login_service::take_pending_login(&state.redis, "apple", &form.state, &headers)
+            .await?;
+
+    let result = complete_apple_login(&state, &client, &pending, &form).await;
+
+    social_login_response(&state, "apple", pending.redirect_uri.as_deref(), result).await
+}
+
+async fn complete_apple_login(
+    state: &AppState,
+    client: &ClientInfo,
+    pending: &PendingLogin,
+    form: &AppleOAuthCallbackForm,
+) -> Result<Value> {
+    if let Some(error) = &form.error {
+        tracing::info!("Apple sign-in returned error: {}", error);
+        return Err(AppError::Authentication(
+            "Sign-in with Apple was cancelled or failed".to_string(),
+        ));
+    }
+
+    let code = form
+        .code
+        .as_deref()
+        .ok_or_else(|| AppError::BadRequest("Missing authorization code".to_string()))?;
+
+    let apple_user = state
+        .apple_service
+        .authenticate(code, &pending.code_verifier, &pending.nonce)
+        .await
+        .map_err(|e| {
+            tracing::error!("Apple OAuth service {}", e);
+            AppError::Authentication("Failed to authenticate with Apple".to_string())
+        })?;
+
+    let profile = ExternalProfile {
+        provider: "apple".to_string(),
+        subject: apple_user.user_id,
+        email: apple_user.email,
+        email_verified: apple_user.email_verified,
+        display_name: None,
+        avatar_url: None,
+    };
+
+    sign_in_with_identity(
+        state,
+        AuthProvider::Apple,
+        &profile,
+        pending.invite_code.as_deref(),
+        client,
+    )
+    .await
+}
+
+pub async fn initiate_google_oauth(
+    State(state): State<AppState>,
+    Query(query): Query<StartSocialLoginQuery>,
+) -> Result<impl IntoResponse> {
+    let login = social_login_service::start_login(
+        &state,
+        "google",
+        query.redirect_uri.as_deref(),
+        query.invite_code.as_deref(),
+        false,
+    )
+    .await?;
+
+    let auth_url = state.google_service.get_authorization_url(
+        &login.state,
+        &login.nonce,
+        &login.code_verifier,
+    );
+
+    Ok((
+        [(SET_COOKIE, login.cookie)],
+        Redirect::to(auth_url.as_str()),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OAuthCallbackQuery {
+    pub code: Option<String>,
+    pub state: String,
+    pub error: Option<String>,
+}
+
+pub async fn google_oauth(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Query(query): Query<OAuthCallbackQuery>,
+) -> Result<Response> {
+    let pending =
+        social_login_service::take_pending_login(&state.redis, "google", &query.state, &headers)
+            .await?;
+
+    let result = complete_google_login(&state, &client, &pending, &query).await;
+
+    social_login_response(&state, "google", pending.redirect_uri.as_deref(), result).await
+}
+
+async fn complete_google_login(
+    state: &AppState,
+    client: &ClientInfo,
+    pending: &PendingLogin,
+    query: &OAuthCallbackQuery,
+) -> Result<Value> {
+    if let Some(error) = &query.error {
+        tracing::info!("Google sign-in returned error: {}", error);
+        return Err(AppError::Authentication(
+            "Sign-in with Google was cancelled or failed".to_string(),
+        ));
+    }
+
+    let code = query
+        .code
+        .as_deref()
+        .ok_or_else(|| AppError::BadRequest("Missing authorization code".to_string()))?;
+
+    let sign_in = state
+        .google_service
+        .exchange_code_for_token(code, &pending.code_verifier, &pending.nonce)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to exchange code for token: {}", e);
+            AppError::Authorization("Failed to authenticate with Google".to_string())
+        })?;
+
+    let google_user = get_google_user_info(sign_in.access_token.secret()).await?;
+
+    if google_user.id != sign_in.subject {
+        return Err(AppError::Authentication(
+            "Google account mismatch".to_string(),
+        ));
+    }
+
+    let profile = ExternalProfile {
+        provider: "google".to_string(),
+        subject: google_user.id,
+        email: Some(google_user.email),
+        email_verified: google_user.verified_email,
+        display_name: Some(google_user.name),
+        avatar_url: Some(google_user.picture),
+    };
+
+    sign_in_with_identity(
+        state,
+        AuthProvider::Google,
+        &profile,
+        pending.invite_code.as_deref(),
+        client,
+    )
+    .await
+}
+
+/// Whether new accounts can be created, so clients know to ask for an invite code.
+pub async fn get_registration_settings(State(state): State<AppState>) -> Json<Value> {
+    Json(json!({
+        "registration_mode": state.config.registration_mode
+    }))
+}
+
+/// Generic OpenID Connect providers users can sign in with.
+pub async fn get_oidc_providers(State(state): State<AppState>) -> Json<Value> {
+    let providers: Vec<Value> = state
+        .oidc_providers
+        .list()
+        .into_iter()
+        .map(|provider| {
+            json!({
+                "name": provider.name(),
+                "display_name": provider.display_name(),
+                "login_url": format!("/api/auth/oauth/{}", provider.name())
+            })
+        })
+        .collect();
+
+    Json(json!({
+        "providers": providers
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StartOidcLoginQuery {
+    /// Used if the sign-in creates a new account
+    pub invite_code: Option<String>,
+}
+
+pub async fn initiate_oidc_login(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    Query(query): Query<StartOidcLoginQuery>,
+) -> Result<impl IntoResponse> {
+    let provider = state.oidc_providers.get(&provider)?;
+
+    let start =
+        oidc_service::start_authorization(&state, provider, None, query.invite_code).await?;
+
+    Ok((
+        [(SET_COOKIE, start.cookie)],
+        Redirect::to(&start.authorization_url),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: String,
+    pub error: Option<String>,
+}
+
+/// Where a generic provider sends the user back to: signs them in, or links
+/// the provider if the flow was started from account settings.
+pub async fn oidc_callback(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Path(provider): Path<String>,
+    Query(query): Query<OidcCallbackQuery>,
+) -> Result<Response> {
+    let provider = state.oidc_providers.get(&provider)?;
+
+    let pending =
+        oidc_service::take_pending_authorization(&state.redis, provider, &query.state, &headers)
+            .await?;
+
+    let clear_cookie = [(
+        SET_COOKIE,
+        social_login_service::clear_binding_cookie(provider.name()),
+    )];
+
+    Ok(
+        match complete_oidc_callback(&state, &client, provider, &pending, &query).await {
+            Ok(body) => (clear_cookie, Json(body)).into_response(),
+            Err(e) => (clear_cookie, e).into_response(),
+        },
+    )
+}
+
+async fn complete_oidc_callback(
+    state: &AppState,
+    client: &ClientInfo,
+    provider: &OidcProvider,
+    pending: &PendingAuthorization,
+    query: &OidcCallbackQuery,
+) -> Result<Value> {
+    if let Some(error) = &query.error {
+        tracing::info!("{} sign-in returned error: {}", provider.name(), error);
+        return Err(AppError::Authentication(format!(
+            "Sign-in with {} was cancelled or failed",
+            provider.display_name()
+        )));
+    }
+
+    let code = query
+        .code
+        .as_deref()
+        .ok_or_else(|| AppError::BadRequest("Missing authorization code".to_string()))?;
+
+    let profile = provider.authenticate(code, pending).await?;
+
+    if let Some(user_id) = pending.link_user_id {
+        let identity = identity_service::link_identity(&state.db, user_id, &profile).await?;
+
+        return Ok(json!({
+            "message": format!("{} account linked successfully", provider.display_name()),
+            "identity": identity
+        }));
+    }
+
+    sign_in_with_identity(
+        state,
+        AuthProvider::Oidc,
+        &profile,
+        pending.invite_code.as_deref(),
+        client,
+    )
+    .await
+}
+
+pub async fn forgot_password(
     State(state): State<AppState>,
     Json(payload): Json<ForgotPasswordRequest>,
 ) -> Result<(StatusCode, Json<Value>)> {
@@ -786,6 +1774,19 @@ pub async fn reset_password(
     let reset_token = reset_token
         .ok_or_else(|| AppError::BadRequest("Invalid or expired reset token".to_string()))?;
 
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(reset_token.user_id)
+        .fetch_one(&state.db)
+        .await?;
+
+    password_service::validate_password(
+        &state.config,
+        &payload.new_password,
+        Some(&user.username),
+        user.email.as_deref(),
+    )
+    .await?;
+
     // Hash new password
     let password_hash = hash_password(&payload.new_password)?;
 
@@ -805,10 +1806,7 @@ pub async fn reset_password(
         .await?;
 
     // Invalidate all user sessions
-    sqlx::query("UPDATE user_sessions SET expires_at = NOW() WHERE user_id = $1")
-        .bind(reset_token.user_id)
-        .execute(&state.db)
-        .await?;
+    session_service::revoke_all_sessions(&state, reset_token.user_id).await?;
 
     Ok((
         StatusCode::OK,
@@ -859,24 +1857,40 @@ pub async fn verify_email(
     ))
 }
 
+pub async fn confirm_email_change(
+    State(state): State<AppState>,
+    Json(payload): Json<ConfirmEmailChangeRequest>,
+) -> Result<(StatusCode, Json<Value>)> {
+    let change = contact_change_service::confirm_email_change(&state.db, &payload.token).await?;
+
+    contact_change_service::notify_previous_contact(
+        &state,
+        &change,
+        contact_change_service::EMAIL_CHANNEL,
+    );
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "message": "Email address changed successfully",
+            "email": change.user.email
+        })),
+    ))
+}
+
 pub async fn verify_phone(
     State(state): State<AppState>,
     Json(payload): Json<VerifyPhoneRequest>,
 ) -> Result<(StatusCode, Json<Value>)> {
-    // Find valid verification code
-    let verification = sqlx::query_as::<_, PhoneVerificationCode>(
-        r#"
-        SELECT * FROM phone_verification_codes 
-        WHERE phone = $1 AND code = $2 AND expires_at > NOW() AND used_at IS NULL
-        "#,
-    )
-    .bind(&payload.phone)
-    .bind(&payload.code)
-    .fetch_optional(&state.db)
-    .await?;
+    let verified =
+        auth_service::check_phone_code(&state.db, &payload.phone, "verification", &payload.code)
+            .await?;
 
-    let verification = verification
-        .ok_or_else(|| AppError::BadRequest("Invalid or expired verification code".to_string()))?;
+    if !verified {
+        return Err(AppError::BadRequest(
+            "Invalid or expired verification code".to_string(),
+        ));
+    }
 
     // Update user as phone verified
     sqlx::query("UPDATE users SET phone_verified = true, updated_at = $1 WHERE phone = $2")
@@ -885,13 +1899,6 @@ pub async fn verify_phone(
         .execute(&state.db)
         .await?;
 
-    // Mark code as used
-    sqlx::query("UPDATE phone_verification_codes SET used_at = $1 WHERE id = $2")
-        .bind(chrono::Utc::now())
-        .bind(verification.id)
-        .execute(&state.db)
-        .await?;
-
     Ok((
         StatusCode::OK,
         Json(json!({
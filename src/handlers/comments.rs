@@ -15,7 +15,7 @@ use crate::{
         CommentResponse, CommentSort, CreateCommentRequest, UpdateCommentRequest, VoteRequest,
         VoteResponse,
     },
-    services::{comment_service, post_service},
+    services::{comment_service, post_service, profile_service},
 };
 
 #[derive(Debug, Deserialize)]
@@ -306,28 +306,28 @@ pub async fn get_user_comments(
     Query(params): Query<GetCommentsQuery>,
     auth_user: OptionalAuthUser,
 ) -> Result<Json<Value>> {
-    // Get user by username
-    let user = sqlx::query!(
-        "SELECT id FROM users WHERE username = $1 AND status = 'active'",
-        username
-    )
-    .fetch_optional(&state.db)
-    .await?
-    .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    let viewer_id = auth_user.0.as_ref().map(|user| user.user_id);
+
+    let profile = profile_service::get_profile(&state.db, &username, viewer_id).await?;
+    profile.ensure_can_view_comments()?;
 
     let sort = params.sort.unwrap_or(CommentSort::New);
     let limit = params.limit.unwrap_or(25).min(100);
     let offset = params.offset.unwrap_or(0);
 
-    let viewer_id = auth_user.0.as_ref().map(|user| user.user_id);
-
-    let comments =
-        comment_service::get_user_comments(&state.db, user.id, viewer_id, sort, limit, offset)
-            .await?;
+    let comments = comment_service::get_user_comments(
+        &state.db,
+        profile.user.id,
+        viewer_id,
+        sort,
+        limit,
+        offset,
+    )
+    .await?;
 
     Ok(Json(json!({
         "comments": comments,
-        "username": username
+        "username": profile.user.username
     })))
 }
 
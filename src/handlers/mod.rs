@@ -1,7 +1,9 @@
+pub mod admin;
 pub mod auth;
 pub mod comments;
 pub mod communities;
 pub mod notifications;
+pub mod oauth;
 pub mod posts;
 pub mod search;
 pub mod upload;
@@ -0,0 +1,298 @@
+use axum::{
+    Form,
+    extract::{Path, Query, State},
+    http::{StatusCode, header::CACHE_CONTROL},
+    response::{IntoResponse, Json},
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    AppState,
+    auth::{AuthUser, ClientInfo},
+    error::{AppError, Result},
+    services::{
+        oauth_provider_service,
+        oauth_provider_service::{AuthorizationRequest, OAuthError, SCOPES},
+        user_service,
+    },
+};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateOAuthClientRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    /// Public clients (SPAs, mobile apps) get no secret and must use PKCE alone
+    #[serde(default)]
+    pub public: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizationDecision {
+    #[serde(flatten)]
+    pub request: AuthorizationRequest,
+    pub approve: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeTokenRequest {
+    pub token: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+}
+
+pub async fn create_client(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<CreateOAuthClientRequest>,
+) -> Result<(StatusCode, Json<Value>)> {
+    payload.validate()?;
+
+    let rate_limit_key = format!("oauth_client_create:{}", auth_user.user_id);
+    if !state
+        .redis
+        .check_rate_limit(&rate_limit_key, 10, 86400)
+        .await?
+    {
+        return Err(AppError::RateLimit);
+    }
+
+    let (client, client_secret) = oauth_provider_service::create_client(
+        &state.db,
+        auth_user.user_id,
+        &payload.name,
+        &payload.redirect_uris,
+        &payload.scopes,
+        !payload.public,
+    )
+    .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "message": "Application registered. Store the client secret now, it won't be shown again",
+            "client": client,
+            "client_secret": client_secret
+        })),
+    ))
+}
+
+pub async fn get_clients(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Value>> {
+    let clients = oauth_provider_service::list_clients(&state.db, auth_user.user_id).await?;
+
+    let clients: Vec<Value> = clients
+        .into_iter()
+        .map(|client| {
+            let confidential = client.is_confidential();
+            let mut value = json!(client);
+            value["confidential"] = json!(confidential);
+            value
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "clients": clients
+    })))
+}
+
+pub async fn delete_client(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(client_id): Path<Uuid>,
+) -> Result<Json<Value>> {
+    if !oauth_provider_service::delete_client(&state, auth_user.user_id, client_id).await? {
+        return Err(AppError::NotFound("Application not found".to_string()));
+    }
+
+    Ok(Json(json!({
+        "message": "Application deleted successfully"
+    })))
+}
+
+/// Validate an authorization request and describe it for the consent screen.
+pub async fn get_authorization(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(request): Query<AuthorizationRequest>,
+) -> Result<Json<Value>> {
+    let (client, scopes) =
+        oauth_provider_service::validate_authorization_request(&state.db, &request).await?;
+
+    let already_authorized =
+        oauth_provider_service::is_already_granted(&state.db, auth_user.user_id, &client, &scopes)
+            .await?;
+
+    let scopes: Vec<Value> = SCOPES
+        .iter()
+        .filter(|(scope, _)| scopes.iter().any(|requested| requested == scope))
+        .map(|(scope, description)| json!({ "name": scope, "description": description }))
+        .collect();
+
+    Ok(Json(json!({
+        "client": {
+            "client_id": client.client_id,
+            "name": client.name
+        },
+        "redirect_uri": request.redirect_uri,
+        "scopes": scopes,
+        "already_authorized": already_authorized
+    })))
+}
+
+/// Approve or deny an authorization request. Returns the URL to send the
+/// user back to the application with.
+pub async fn decide_authorization(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<AuthorizationDecision>,
+) -> Result<Json<Value>> {
+    let request = payload.request;
+
+    let (client, scopes) =
+        oauth_provider_service::validate_authorization_request(&state.db, &request).await?;
+
+    let redirect_to = if payload.approve {
+        oauth_provider_service::approve_authorization(
+            &state.db,
+            auth_user.user_id,
+            &client,
+            &request,
+            &scopes,
+        )
+        .await?
+    } else {
+        oauth_provider_service::deny_authorization(&request)?
+    };
+
+    Ok(Json(json!({
+        "redirect_to": redirect_to
+    })))
+}
+
+pub async fn token(
+    State(state): State<AppState>,
+    client_info: ClientInfo,
+    Form(payload): Form<TokenRequest>,
+) -> std::result::Result<impl IntoResponse, OAuthError> {
+    let client = oauth_provider_service::authenticate_client(
+        &state.db,
+        &payload.client_id,
+        payload.client_secret.as_deref(),
+    )
+    .await?;
+
+    let missing =
+        |parameter: &str| OAuthError::InvalidRequest(format!("Missing parameter: {}", parameter));
+
+    let response = match payload.grant_type.as_str() {
+        "authorization_code" => {
+            oauth_provider_service::exchange_authorization_code(
+                &state,
+                &client,
+                payload.code.as_deref().ok_or_else(|| missing("code"))?,
+                payload
+                    .redirect_uri
+                    .as_deref()
+                    .ok_or_else(|| missing("redirect_uri"))?,
+                payload
+                    .code_verifier
+                    .as_deref()
+                    .ok_or_else(|| missing("code_verifier"))?,
+                &client_info,
+            )
+            .await?
+        }
+        "refresh_token" => {
+            oauth_provider_service::refresh_tokens(
+                &state,
+                &client,
+                payload
+                    .refresh_token
+                    .as_deref()
+                    .ok_or_else(|| missing("refresh_token"))?,
+                &client_info,
+            )
+            .await?
+        }
+        _ => return Err(OAuthError::UnsupportedGrantType),
+    };
+
+    Ok(([(CACHE_CONTROL, "no-store")], Json(response)))
+}
+
+/// Token revocation (RFC 7009). Always succeeds for an authenticated client so
+/// it can't be used to probe for valid tokens.
+pub async fn revoke(
+    State(state): State<AppState>,
+    Form(payload): Form<RevokeTokenRequest>,
+) -> std::result::Result<Json<Value>, OAuthError> {
+    let client = oauth_provider_service::authenticate_client(
+        &state.db,
+        &payload.client_id,
+        payload.client_secret.as_deref(),
+    )
+    .await?;
+
+    oauth_provider_service::revoke_token(&state, &client, &payload.token).await?;
+
+    Ok(Json(json!({})))
+}
+
+/// OpenID Connect userinfo: the claims the token's scopes allow.
+pub async fn userinfo(State(state): State<AppState>, auth_user: AuthUser) -> Result<Json<Value>> {
+    let user = user_service::get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    let claims =
+        oauth_provider_service::user_claims(&user, auth_user.scopes.as_deref().unwrap_or_default());
+
+    Ok(Json(Value::Object(claims)))
+}
+
+/// OpenID Connect discovery document.
+pub async fn openid_configuration(State(state): State<AppState>) -> impl IntoResponse {
+    let base_url = &state.config.base_url;
+    let scopes: Vec<&str> = SCOPES.iter().map(|(scope, _)| *scope).collect();
+
+    (
+        [(CACHE_CONTROL, "public, max-age=3600")],
+        Json(json!({
+            "issuer": base_url,
+            "authorization_endpoint": format!("{}/oauth/authorize", base_url),
+            "token_endpoint": format!("{}/api/oauth/token", base_url),
+            "userinfo_endpoint": format!("{}/api/oauth/userinfo", base_url),
+            "revocation_endpoint": format!("{}/api/oauth/revoke", base_url),
+            "jwks_uri": format!("{}/.well-known/jwks.json", base_url),
+            "scopes_supported": scopes,
+            "response_types_supported": ["code"],
+            "grant_types_supported": ["authorization_code", "refresh_token"],
+            "code_challenge_methods_supported": ["S256"],
+            "subject_types_supported": ["public"],
+            "id_token_signing_alg_values_supported": ["EdDSA"],
+            "token_endpoint_auth_methods_supported": ["client_secret_post", "none"],
+            "claims_supported": [
+                "sub", "iss", "aud", "exp", "iat", "nonce", "preferred_username",
+                "name", "picture", "updated_at", "email", "email_verified"
+            ]
+        })),
+    )
+}
@@ -16,7 +16,7 @@ use crate::{
         CreatePostRequest, Post, PostResponse, PostSort, PostStatus, PostType, TimeRange,
         UpdatePostRequest,
     },
-    services::{community_service, post_service},
+    services::{community_service, post_service, profile_service},
 };
 
 #[derive(Debug, Deserialize)]
@@ -28,6 +28,14 @@ pub struct GetPostsQuery {
     pub community: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct HomeFeedQuery {
+    pub page: Option<u32>,
+    pub limit: Option<u32>,
+    pub sort: Option<PostSort>,
+    pub time: Option<TimeRange>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct VoteRequest {
     pub vote_type: i16, // -1 for downvote, 1 for upvote, 0 to remove vote
@@ -176,6 +184,43 @@ pub async fn get_posts(
     })))
 }
 
+/// Posts from the viewer's communities and the people they follow. There's no
+/// total count, which would mean reading the whole feed; `has_more` says
+/// whether there's another page.
+pub async fn get_home_feed(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<HomeFeedQuery>,
+) -> Result<Json<Value>> {
+    let page = params.page.unwrap_or(1).max(1);
+    let limit = params.limit.unwrap_or(25).clamp(1, 100);
+    let offset = (page - 1) * limit;
+    let sort = params.sort.unwrap_or(PostSort::Hot);
+
+    // One extra post tells us whether there's a next page
+    let mut posts = post_service::get_home_feed(
+        &state.db,
+        auth_user.user_id,
+        sort,
+        params.time,
+        limit + 1,
+        offset,
+    )
+    .await?;
+
+    let has_more = posts.len() > limit as usize;
+    posts.truncate(limit as usize);
+
+    Ok(Json(json!({
+        "posts": posts,
+        "pagination": {
+            "page": page,
+            "limit": limit,
+            "has_more": has_more
+        }
+    })))
+}
+
 pub async fn get_post(
     State(state): State<AppState>,
     Path(post_id): Path<Uuid>,
@@ -469,25 +514,19 @@ pub async fn get_user_posts(
     Query(params): Query<GetPostsQuery>,
     auth_user: OptionalAuthUser,
 ) -> Result<Json<Value>> {
-    // Get user by username
-    let user = sqlx::query!(
-        "SELECT id FROM users WHERE username = $1 AND status = 'active'",
-        username
-    )
-    .fetch_optional(&state.db)
-    .await?
-    .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    let viewer_id = auth_user.0.as_ref().map(|user| user.user_id);
 
-    let page = params.page.unwrap_or(1);
-    let limit = params.limit.unwrap_or(25).min(100);
+    let profile = profile_service::get_profile(&state.db, &username, viewer_id).await?;
+    profile.ensure_can_view_posts()?;
+
+    let page = params.page.unwrap_or(1).max(1);
+    let limit = params.limit.unwrap_or(25).clamp(1, 100);
     let offset = (page - 1) * limit;
     let sort = params.sort.unwrap_or(PostSort::New);
 
-    let viewer_id = auth_user.0.as_ref().map(|user| user.user_id);
-
     let posts = post_service::get_user_posts(
         &state.db,
-        user.id,
+        profile.user.id,
         viewer_id,
         sort,
         params.time,
@@ -496,7 +535,8 @@ pub async fn get_user_posts(
     )
     .await?;
 
-    let total_count = post_service::get_user_posts_count(&state.db, user.id).await?;
+    let total_count =
+        post_service::get_user_posts_count(&state.db, profile.user.id, viewer_id).await?;
 
     Ok(Json(json!({
         "posts": posts,
@@ -1,18 +1,32 @@
 use axum::{
-    extract::{Path, State},
-    response::Json,
+    body::Body,
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Json, Response},
 };
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
+use tokio_util::io::ReaderStream;
 use uuid::Uuid;
 use validator::Validate;
 
 use crate::{
     AppState,
-    auth::{AuthUser, OptionalAuthUser},
+    auth::{
+        AuthUser, OptionalAuthUser, confirm_identity, get_google_user_info,
+        verify_google_access_token,
+    },
     error::{AppError, Result},
-    models::UserPreferences,
-    services::user_service,
+    handlers::auth::{AppleOAuthRequest, GoogleOAuthRequest},
+    models::{KarmaInterval, PaginationParams, ProfileSort, TimeRange, UserPreferences},
+    services::{
+        account_deletion_service, api_token_service, contact_change_service, data_export_service,
+        follow_service, follow_service::FollowOutcome, identity_service,
+        identity_service::ExternalProfile, invite_service, karma_service, login_security_service,
+        oauth_provider_service, oidc_service, passkey_service, profile_service,
+        profile_service::Trophy, session_service, social_login_service, user_service,
+        username_service,
+    },
 };
 
 #[derive(Debug, Deserialize, Validate)]
@@ -29,6 +43,87 @@ pub struct UpdateUserRequest {
     pub website: Option<String>,
 }
 
+#[derive(Debug, Deserialize, Validate)]
+pub struct ChangeEmailRequest {
+    #[validate(email)]
+    pub new_email: String,
+    pub current_password: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ChangeUsernameRequest {
+    #[validate(length(min = 3, max = 50))]
+    pub new_username: String,
+    pub current_password: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ChangePhoneRequest {
+    #[validate(length(min = 10, max = 20))]
+    pub new_phone: String,
+    pub current_password: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountRequest {
+    pub current_password: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StartIdentityLinkRequest {
+    pub current_password: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadExportQuery {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmPhoneChangeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct RenamePasskeyRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateApiTokenRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// Omit for a token that doesn't expire
+    #[validate(range(min = 1, max = 3650))]
+    pub expires_in_days: Option<i64>,
+    pub current_password: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateInviteRequest {
+    #[validate(length(max = 200))]
+    pub note: Option<String>,
+    /// Omit for the longest validity users are allowed
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProfileOverviewQuery {
+    pub page: Option<u32>,
+    pub limit: Option<u32>,
+    pub sort: Option<ProfileSort>,
+    pub time: Option<TimeRange>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KarmaHistoryQuery {
+    pub interval: Option<KarmaInterval>,
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct UpdatePreferencesRequest {
     pub email_notifications: Option<bool>,
@@ -39,9 +134,10 @@ pub struct UpdatePreferencesRequest {
     pub upvote_notifications: Option<bool>,
     pub community_notifications: Option<bool>,
     pub nsfw_content: Option<bool>,
-    pub autoplay_videos: Option<bool>,
-    pub theme: Option<String>,
-    pub language: Option<String>,
+    pub show_profile_posts: Option<bool>,
+    pub show_profile_comments: Option<bool>,
+    pub hide_followers: Option<bool>,
+    pub require_follow_approval: Option<bool>,
 }
 
 #[derive(Debug, Serialize)]
@@ -55,6 +151,9 @@ pub struct UserProfileResponse {
     // pub location: Option<String>,
     // pub website: Option<String>,
     pub karma_points: i32,
+    pub post_karma: i64,
+    pub comment_karma: i64,
+    pub trophies: Vec<Trophy>,
     pub post_count: i32,
     pub comment_count: i32,
     pub follower_count: i32,
@@ -63,6 +162,18 @@ pub struct UserProfileResponse {
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub is_following: Option<bool>,
     pub is_blocked: Option<bool>,
+    /// Whether this user follows the viewer
+    pub follows_you: Option<bool>,
+    pub is_mutual: Option<bool>,
+    /// The viewer is waiting for this user to approve their follow
+    pub follow_requested: Option<bool>,
+    /// Whether the viewer can list this user's posts and comments
+    pub can_view_posts: bool,
+    pub can_view_comments: bool,
+    pub can_view_followers: bool,
+    /// Set when the profile was requested by a name the user has since
+    /// changed away from; clients should update links to this username
+    pub redirect_to: Option<String>,
 }
 
 pub async fn get_current_user(
@@ -74,6 +185,8 @@ pub async fn get_current_user(
         .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
 
     let stats = user_service::get_user_stats(&state.db, auth_user.user_id).await?;
+    let karma = karma_service::get_breakdown(&state.db, auth_user.user_id).await?;
+    let trophies = profile_service::get_trophies(&state.db, auth_user.user_id).await?;
 
     Ok(Json(UserProfileResponse {
         id: user.id,
@@ -85,6 +198,9 @@ pub async fn get_current_user(
         // location: user.location,
         // website: user.website,
         karma_points: user.karma_points,
+        post_karma: karma.post_karma,
+        comment_karma: karma.comment_karma,
+        trophies,
         post_count: stats.post_count,
         comment_count: stats.comment_count,
         follower_count: stats.follower_count,
@@ -93,6 +209,13 @@ pub async fn get_current_user(
         created_at: user.created_at,
         is_following: None,
         is_blocked: None,
+        follows_you: None,
+        is_mutual: None,
+        follow_requested: None,
+        can_view_posts: true,
+        can_view_comments: true,
+        can_view_followers: true,
+        redirect_to: None,
     }))
 }
 
@@ -133,36 +256,100 @@ pub async fn update_current_user(
     })))
 }
 
+/// Schedule the current account for deletion. It stays recoverable by signing
+/// in until the grace period ends, then it is scrubbed by a background job.
+pub async fn delete_current_user(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<DeleteAccountRequest>,
+) -> Result<Json<Value>> {
+    let user = user_service::get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    confirm_identity(
+        &state,
+        &auth_user,
+        &user,
+        payload.current_password.as_deref(),
+    )
+    .await?;
+
+    let deletion_scheduled_at = account_deletion_service::schedule_deletion(
+        &state,
+        user.id,
+        chrono::Duration::days(state.config.account_deletion_grace_days),
+    )
+    .await?;
+
+    let deletion_date = deletion_scheduled_at
+        .format("%Y-%m-%d %H:%M UTC")
+        .to_string();
+    let notified = if let Some(email) = user.email.as_deref().filter(|_| user.email_verified) {
+        state
+            .email_service
+            .send_account_deletion_scheduled_email(email, &user.username, &deletion_date)
+            .await
+    } else if let Some(phone) = user.phone.as_deref() {
+        state
+            .sms_service
+            .send_security_alert(phone, &state.config.app_name, "An account deletion request")
+            .await
+            .map(|_| ())
+    } else {
+        Ok(())
+    };
+
+    if let Err(e) = notified {
+        tracing::error!("Failed to send deletion notice to user {}: {}", user.id, e);
+    }
+
+    Ok(Json(json!({
+        "message": "Your account is scheduled for deletion. Sign in again before then to cancel",
+        "deletion_scheduled_at": deletion_scheduled_at
+    })))
+}
+
+/// A user's public profile. Users who have blocked the viewer come back as
+/// not found.
 pub async fn get_user_by_username(
     State(state): State<AppState>,
     auth_user: OptionalAuthUser,
     Path(username): Path<String>,
 ) -> Result<Json<UserProfileResponse>> {
-    let user = user_service::get_user_by_username(&state.db, &username)
-        .await?
-        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    let viewer_id = auth_user.0.as_ref().map(|user| user.user_id);
+
+    let profile = profile_service::get_profile(&state.db, &username, viewer_id).await?;
+    let user = &profile.user;
 
     let stats = user_service::get_user_stats(&state.db, user.id).await?;
+    let karma = karma_service::get_breakdown(&state.db, user.id).await?;
+    let trophies = profile_service::get_trophies(&state.db, user.id).await?;
 
-    let (is_following, is_blocked) = if let Some(auth_user) = auth_user.0 {
-        let is_following =
-            user_service::is_following(&state.db, auth_user.user_id, user.id).await?;
-        let is_blocked = user_service::is_blocked(&state.db, auth_user.user_id, user.id).await?;
-        (Some(is_following), Some(is_blocked))
-    } else {
-        (None, None)
+    let (is_following, follows_you, follow_requested) = match viewer_id {
+        Some(viewer_id) if !profile.is_own => (
+            Some(user_service::is_following(&state.db, viewer_id, user.id).await?),
+            Some(user_service::is_following(&state.db, user.id, viewer_id).await?),
+            Some(follow_service::is_follow_requested(&state.db, viewer_id, user.id).await?),
+        ),
+        _ => (None, None, None),
     };
+    let is_mutual = is_following.zip(follows_you).map(|(a, b)| a && b);
+    let is_blocked = viewer_id.map(|_| profile.viewer_blocked);
 
     Ok(Json(UserProfileResponse {
         id: user.id,
-        username: user.username,
-        display_name: user.display_name,
-        bio: user.bio,
-        avatar_url: user.avatar_url,
-        banner_url: user.banner_url,
+        username: user.username.clone(),
+        display_name: user.display_name.clone(),
+        bio: user.bio.clone(),
+        avatar_url: user.avatar_url.clone(),
+        banner_url: user.banner_url.clone(),
         // location: user.location,
         // website: user.website,
         karma_points: user.karma_points,
+        post_karma: karma.post_karma,
+        comment_karma: karma.comment_karma,
+        trophies,
         post_count: stats.post_count,
         comment_count: stats.comment_count,
         follower_count: stats.follower_count,
@@ -171,9 +358,106 @@ pub async fn get_user_by_username(
         created_at: user.created_at,
         is_following,
         is_blocked,
+        follows_you,
+        is_mutual,
+        follow_requested,
+        can_view_posts: profile.can_view_posts(),
+        can_view_comments: profile.can_view_comments(),
+        can_view_followers: profile.can_view_followers(),
+        redirect_to: profile.renamed.then(|| user.username.clone()),
     }))
 }
 
+/// A user's posts and comments merged into one list, newest or highest
+/// scoring first. Like the home feed it pages with `has_more` rather than a
+/// total.
+pub async fn get_user_overview(
+    State(state): State<AppState>,
+    auth_user: OptionalAuthUser,
+    Path(username): Path<String>,
+    Query(params): Query<ProfileOverviewQuery>,
+) -> Result<Json<Value>> {
+    let viewer_id = auth_user.0.as_ref().map(|user| user.user_id);
+
+    let profile = profile_service::get_profile(&state.db, &username, viewer_id).await?;
+    profile.ensure_can_view_activity()?;
+
+    let page = params.page.unwrap_or(1).max(1);
+    let limit = params.limit.unwrap_or(25).clamp(1, 100);
+    let offset = (page - 1) * limit;
+    let sort = params.sort.unwrap_or(ProfileSort::New);
+
+    // One extra item tells us whether there's a next page
+    let mut items = profile_service::get_overview(
+        &state.db,
+        &profile,
+        viewer_id,
+        sort,
+        params.time,
+        limit + 1,
+        offset,
+    )
+    .await?;
+
+    let has_more = items.len() > limit as usize;
+    items.truncate(limit as usize);
+
+    Ok(Json(json!({
+        "items": items,
+        "pagination": {
+            "page": page,
+            "limit": limit,
+            "has_more": has_more
+        }
+    })))
+}
+
+/// Where a user's karma came from: posts versus comments, and the
+/// communities they earned it in.
+pub async fn get_user_karma(
+    State(state): State<AppState>,
+    auth_user: OptionalAuthUser,
+    Path(username): Path<String>,
+) -> Result<Json<Value>> {
+    let viewer_id = auth_user.0.as_ref().map(|user| user.user_id);
+
+    let profile = profile_service::get_profile(&state.db, &username, viewer_id).await?;
+
+    let karma = karma_service::get_breakdown(&state.db, profile.user.id).await?;
+    let communities =
+        karma_service::get_community_breakdown(&state.db, profile.user.id, viewer_id, 25).await?;
+
+    Ok(Json(json!({
+        "total": profile.user.karma_points,
+        "post_karma": karma.post_karma,
+        "comment_karma": karma.comment_karma,
+        "communities": communities
+    })))
+}
+
+/// Karma gained per day, week or month. Defaults to daily buckets covering
+/// the last 30 days.
+pub async fn get_user_karma_history(
+    State(state): State<AppState>,
+    auth_user: OptionalAuthUser,
+    Path(username): Path<String>,
+    Query(params): Query<KarmaHistoryQuery>,
+) -> Result<Json<Value>> {
+    let viewer_id = auth_user.0.as_ref().map(|user| user.user_id);
+
+    let profile = profile_service::get_profile(&state.db, &username, viewer_id).await?;
+
+    let interval = params.interval.unwrap_or(KarmaInterval::Day);
+    let history =
+        karma_service::get_history(&state.db, profile.user.id, interval, params.from, params.to)
+            .await?;
+
+    Ok(Json(json!({
+        "interval": interval,
+        "history": history
+    })))
+}
+
 pub async fn get_user_preferences(
     State(state): State<AppState>,
     auth_user: AuthUser,
@@ -206,11 +490,12 @@ pub async fn update_user_preferences(
             upvote_notifications = COALESCE($6, upvote_notifications),
             community_notifications = COALESCE($7, community_notifications),
             nsfw_content = COALESCE($8, nsfw_content),
-            autoplay_videos = COALESCE($9, autoplay_videos),
-            theme = COALESCE($10, theme),
-            language = COALESCE($11, language),
-            updated_at = $12
-        WHERE user_id = $13
+            show_profile_posts = COALESCE($9, show_profile_posts),
+            show_profile_comments = COALESCE($10, show_profile_comments),
+            hide_followers = COALESCE($11, hide_followers),
+            require_follow_approval = COALESCE($12, require_follow_approval),
+            updated_at = $13
+        WHERE user_id = $14
         "#,
     )
     .bind(payload.email_notifications)
@@ -221,14 +506,20 @@ pub async fn update_user_preferences(
     .bind(payload.upvote_notifications)
     .bind(payload.community_notifications)
     .bind(payload.nsfw_content)
-    .bind(payload.autoplay_videos)
-    .bind(&payload.theme)
-    .bind(&payload.language)
+    .bind(payload.show_profile_posts)
+    .bind(payload.show_profile_comments)
+    .bind(payload.hide_followers)
+    .bind(payload.require_follow_approval)
     .bind(chrono::Utc::now())
     .bind(auth_user.user_id)
     .execute(&state.db)
     .await?;
 
+    // Nobody is left waiting on approvals the user no longer gives
+    if payload.require_follow_approval == Some(false) {
+        follow_service::approve_all_follow_requests(&state.db, auth_user.user_id).await?;
+    }
+
     Ok(Json(json!({
         "message": "Preferences updated successfully"
     })))
@@ -239,81 +530,127 @@ pub async fn follow_user(
     auth_user: AuthUser,
     Path(user_id): Path<Uuid>,
 ) -> Result<Json<Value>> {
-    // Check if user exists
-    let _target_user = user_service::get_user_by_id(&state.db, user_id)
-        .await?
-        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    let outcome = follow_service::follow(&state, auth_user.user_id, user_id).await?;
 
-    // Can't follow yourself
-    if auth_user.user_id == user_id {
-        return Err(AppError::BadRequest("Cannot follow yourself".to_string()));
-    }
+    let message = match outcome {
+        FollowOutcome::Following => "User followed successfully",
+        FollowOutcome::Requested => "Follow request sent",
+    };
 
-    // Check if already following
-    let existing_follow =
-        sqlx::query("SELECT id FROM user_follows WHERE follower_id = $1 AND following_id = $2")
-            .bind(auth_user.user_id)
-            .bind(user_id)
-            .fetch_optional(&state.db)
-            .await?;
+    Ok(Json(json!({
+        "message": message,
+        "status": outcome
+    })))
+}
 
-    if existing_follow.is_some() {
-        return Err(AppError::Conflict(
-            "Already following this user".to_string(),
+/// Unfollow a user, or withdraw a follow request that hasn't been approved.
+pub async fn unfollow_user(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<Value>> {
+    if !follow_service::unfollow(&state.db, auth_user.user_id, user_id).await? {
+        return Err(AppError::NotFound(
+            "Follow relationship not found".to_string(),
         ));
     }
 
-    // Check if blocked
-    let is_blocked = user_service::is_blocked(&state.db, user_id, auth_user.user_id).await?;
-    if is_blocked {
-        return Err(AppError::Authorization(
-            "Cannot follow this user".to_string(),
-        ));
-    }
+    Ok(Json(json!({
+        "message": "User unfollowed successfully"
+    })))
+}
 
-    // Create follow relationship
-    sqlx::query(
-        r#"
-        INSERT INTO user_follows (id, follower_id, following_id, created_at)
-        VALUES ($1, $2, $3, $4)
-        "#,
-    )
-    .bind(Uuid::new_v4())
-    .bind(auth_user.user_id)
-    .bind(user_id)
-    .bind(chrono::Utc::now())
-    .execute(&state.db)
-    .await?;
+pub async fn get_followers(
+    State(state): State<AppState>,
+    auth_user: OptionalAuthUser,
+    Path(username): Path<String>,
+    Query(params): Query<PaginationParams>,
+) -> Result<Json<Value>> {
+    let viewer_id = auth_user.0.as_ref().map(|user| user.user_id);
+
+    let profile = profile_service::get_profile(&state.db, &username, viewer_id).await?;
+    profile.ensure_can_view_followers()?;
 
-    // Create notification
-    // user_service::create_follow_notification(&state, auth_user.user_id, user_id).await?;
+    let limit = params.limit.unwrap_or(25).min(100);
+    let offset = params.offset.unwrap_or(0);
+
+    let followers =
+        follow_service::list_followers(&state.db, profile.user.id, limit, offset).await?;
 
     Ok(Json(json!({
-        "message": "User followed successfully"
+        "followers": followers,
+        "total": profile.user.follower_count,
+        "limit": limit,
+        "offset": offset
     })))
 }
 
-pub async fn unfollow_user(
+pub async fn get_following(
+    State(state): State<AppState>,
+    auth_user: OptionalAuthUser,
+    Path(username): Path<String>,
+    Query(params): Query<PaginationParams>,
+) -> Result<Json<Value>> {
+    let viewer_id = auth_user.0.as_ref().map(|user| user.user_id);
+
+    let profile = profile_service::get_profile(&state.db, &username, viewer_id).await?;
+    profile.ensure_can_view_followers()?;
+
+    let limit = params.limit.unwrap_or(25).min(100);
+    let offset = params.offset.unwrap_or(0);
+
+    let following =
+        follow_service::list_following(&state.db, profile.user.id, limit, offset).await?;
+
+    Ok(Json(json!({
+        "following": following,
+        "total": profile.user.following_count,
+        "limit": limit,
+        "offset": offset
+    })))
+}
+
+pub async fn get_follow_requests(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<PaginationParams>,
+) -> Result<Json<Value>> {
+    let limit = params.limit.unwrap_or(25).min(100);
+    let offset = params.offset.unwrap_or(0);
+
+    let requests =
+        follow_service::list_follow_requests(&state.db, auth_user.user_id, limit, offset).await?;
+
+    Ok(Json(json!({
+        "requests": requests
+    })))
+}
+
+pub async fn approve_follow_request(
     State(state): State<AppState>,
     auth_user: AuthUser,
     Path(user_id): Path<Uuid>,
 ) -> Result<Json<Value>> {
-    // Remove follow relationship
-    let result =
-        sqlx::query("DELETE FROM user_follows WHERE follower_id = $1 AND following_id = $2")
-            .bind(auth_user.user_id)
-            .bind(user_id)
-            .execute(&state.db)
-            .await?;
+    if !follow_service::approve_follow_request(&state, auth_user.user_id, user_id).await? {
+        return Err(AppError::NotFound("Follow request not found".to_string()));
+    }
 
-    if result.rows_affected() == 0 {
-        return Err(AppError::NotFound(
-            "Follow relationship not found".to_string(),
-        ));
+    Ok(Json(json!({
+        "message": "Follow request approved"
+    })))
+}
+
+pub async fn reject_follow_request(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<Value>> {
+    if !follow_service::reject_follow_request(&state.db, auth_user.user_id, user_id).await? {
+        return Err(AppError::NotFound("Follow request not found".to_string()));
     }
 
     Ok(Json(json!({
-        "message": "User unfollowed successfully"
+        "message": "Follow request rejected"
     })))
 }
 
@@ -358,14 +695,8 @@ pub async fn block_user(
     .execute(&state.db)
     .await?;
 
-    // Remove any existing follow relationships
-    sqlx::query(
-        "DELETE FROM user_follows WHERE (follower_id = $1 AND following_id = $2) OR (follower_id = $2 AND following_id = $1)"
-    )
-    .bind(auth_user.user_id)
-    .bind(user_id)
-    .execute(&state.db)
-    .await?;
+    // Remove any existing follow relationships and pending requests
+    follow_service::sever(&state.db, auth_user.user_id, user_id).await?;
 
     Ok(Json(json!({
         "message": "User blocked successfully"
@@ -394,3 +725,697 @@ pub async fn unblock_user(
         "message": "User unblocked successfully"
     })))
 }
+
+pub async fn get_sessions(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Value>> {
+    let sessions =
+        session_service::list_sessions(&state.db, auth_user.user_id, &auth_user.jti).await?;
+
+    Ok(Json(json!({
+        "sessions": sessions
+    })))
+}
+
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(session_id): Path<Uuid>,
+) -> Result<Json<Value>> {
+    if !session_service::revoke_user_session(&state, auth_user.user_id, session_id).await? {
+        return Err(AppError::NotFound("Session not found".to_string()));
+    }
+
+    Ok(Json(json!({
+        "message": "Session revoked successfully"
+    })))
+}
+
+pub async fn revoke_other_sessions(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Value>> {
+    let revoked =
+        session_service::revoke_other_sessions(&state, auth_user.user_id, &auth_user.jti).await?;
+
+    Ok(Json(json!({
+        "message": "Other sessions revoked successfully",
+        "revoked": revoked
+    })))
+}
+
+pub async fn get_api_tokens(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Value>> {
+    let tokens = api_token_service::list_tokens(&state.db, auth_user.user_id).await?;
+
+    Ok(Json(json!({
+        "api_tokens": tokens
+    })))
+}
+
+pub async fn create_api_token(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<CreateApiTokenRequest>,
+) -> Result<(StatusCode, Json<Value>)> {
+    payload.validate()?;
+
+    // The token outlives sessions and password changes, so a stolen session
+    // alone mustn't be enough to mint one
+    let user = user_service::get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    confirm_identity(
+        &state,
+        &auth_user,
+        &user,
+        payload.current_password.as_deref(),
+    )
+    .await?;
+
+    let (api_token, token) = api_token_service::create_token(
+        &state.db,
+        user.id,
+        &payload.name,
+        &payload.scopes,
+        payload.expires_in_days.map(chrono::Duration::days),
+    )
+    .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "message": "API token created. Copy it now, it won't be shown again",
+            "api_token": api_token,
+            "token": token
+        })),
+    ))
+}
+
+pub async fn revoke_api_token(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(token_id): Path<Uuid>,
+) -> Result<Json<Value>> {
+    if !api_token_service::revoke_token(&state.db, auth_user.user_id, token_id).await? {
+        return Err(AppError::NotFound("API token not found".to_string()));
+    }
+
+    Ok(Json(json!({
+        "message": "API token revoked successfully"
+    })))
+}
+
+pub async fn get_invites(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<PaginationParams>,
+) -> Result<Json<Value>> {
+    let limit = params.limit.unwrap_or(25).min(100);
+    let offset = params.offset.unwrap_or(0);
+
+    let invites =
+        invite_service::list_invites(&state.db, Some(auth_user.user_id), limit, offset).await?;
+
+    Ok(Json(json!({
+        "invites": invites
+    })))
+}
+
+pub async fn create_invite(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<CreateInviteRequest>,
+) -> Result<(StatusCode, Json<Value>)> {
+    payload.validate()?;
+
+    let invite = invite_service::create_user_invite(
+        &state.db,
+        &state.config,
+        auth_user.user_id,
+        payload.note.as_deref(),
+        payload.expires_at,
+    )
+    .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "message": "Invite code created",
+            "invite": invite
+        })),
+    ))
+}
+
+pub async fn revoke_invite(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(invite_id): Path<Uuid>,
+) -> Result<Json<Value>> {
+    if !invite_service::revoke_user_invite(&state.db, auth_user.user_id, invite_id).await? {
+        return Err(AppError::NotFound("Invite code not found".to_string()));
+    }
+
+    Ok(Json(json!({
+        "message": "Invite code revoked successfully"
+    })))
+}
+
+pub async fn get_authorized_apps(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Value>> {
+    let apps = oauth_provider_service::list_authorized_apps(&state.db, auth_user.user_id).await?;
+
+    Ok(Json(json!({
+        "authorized_apps": apps
+    })))
+}
+
+pub async fn revoke_authorized_app(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(client_id): Path<String>,
+) -> Result<Json<Value>> {
+    if !oauth_provider_service::revoke_authorization(&state, auth_user.user_id, &client_id).await? {
+        return Err(AppError::NotFound("Authorized app not found".to_string()));
+    }
+
+    Ok(Json(json!({
+        "message": "App access revoked successfully"
+    })))
+}
+
+pub async fn get_passkeys(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Value>> {
+    let passkeys = passkey_service::get_user_passkeys(&state.db, auth_user.user_id).await?;
+
+    Ok(Json(json!({
+        "passkeys": passkeys
+    })))
+}
+
+pub async fn rename_passkey(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(passkey_id): Path<Uuid>,
+    Json(payload): Json<RenamePasskeyRequest>,
+) -> Result<Json<Value>> {
+    payload.validate()?;
+
+    if !passkey_service::rename_passkey(&state.db, auth_user.user_id, passkey_id, &payload.name)
+        .await?
+    {
+        return Err(AppError::NotFound("Passkey not found".to_string()));
+    }
+
+    Ok(Json(json!({
+        "message": "Passkey renamed successfully"
+    })))
+}
+
+pub async fn delete_passkey(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(passkey_id): Path<Uuid>,
+) -> Result<Json<Value>> {
+    if !passkey_service::delete_passkey(&state.db, auth_user.user_id, passkey_id).await? {
+        return Err(AppError::NotFound("Passkey not found".to_string()));
+    }
+
+    Ok(Json(json!({
+        "message": "Passkey deleted successfully"
+    })))
+}
+
+pub async fn get_identities(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Value>> {
+    let identities = identity_service::list_identities(&state.db, auth_user.user_id).await?;
+
+    Ok(Json(json!({
+        "identities": identities
+    })))
+}
+
+pub async fn link_google_identity(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<GoogleOAuthRequest>,
+) -> Result<Json<Value>> {
+    let user = user_service::get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    confirm_identity(
+        &state,
+        &auth_user,
+        &user,
+        payload.current_password.as_deref(),
+    )
+    .await?;
+
+    let token_info =
+        verify_google_access_token(&payload.access_token, &state.config.google_client_id).await?;
+    let google_user = get_google_user_info(&payload.access_token).await?;
+
+    if token_info
+        .sub
+        .is_some_and(|subject| subject != google_user.id)
+    {
+        return Err(AppError::Authentication(
+            "Google account mismatch".to_string(),
+        ));
+    }
+
+    let profile = ExternalProfile {
+        provider: "google".to_string(),
+        subject: google_user.id,
+        email: Some(google_user.email),
+        email_verified: google_user.verified_email,
+        display_name: Some(google_user.name),
+        avatar_url: Some(google_user.picture),
+    };
+
+    let identity = identity_service::link_identity(&state.db, user.id, &profile).await?;
+
+    Ok(Json(json!({
+        "message": "Google account linked successfully",
+        "identity": identity
+    })))
+}
+
+/// Start linking a generic OIDC provider. The response sets a cookie that
+/// binds the link to this browser, so the returned URL has to be opened in
+/// the same browser; anyone else finishing it is refused.
+pub async fn start_oidc_identity_link(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(provider): Path<String>,
+    Json(payload): Json<StartIdentityLinkRequest>,
+) -> Result<impl IntoResponse> {
+    let provider = state.oidc_providers.get(&provider)?;
+
+    let user = user_service::get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    confirm_identity(
+        &state,
+        &auth_user,
+        &user,
+        payload.current_password.as_deref(),
+    )
+    .await?;
+
+    let start = oidc_service::start_authorization(&state, provider, Some(user.id), None).await?;
+
+    Ok((
+        [(header::SET_COOKIE, start.cookie)],
+        Json(json!({
+            "authorization_url": start.authorization_url
+        })),
+    ))
+}
+
+/// Start linking Apple from a client that signs in with Apple itself. The
+/// returned nonce has to go in the Apple request, and the ID token that comes
+/// back is then sent to `link_apple_identity`.
+pub async fn start_apple_identity_link(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Value>> {
+    let nonce = social_login_service::start_apple_link(&state.redis, auth_user.user_id).await?;
+
+    Ok(Json(json!({
+        "nonce": nonce
+    })))
+}
+
+pub async fn link_apple_identity(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<AppleOAuthRequest>,
+) -> Result<Json<Value>> {
+    let user = user_service::get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    confirm_identity(
+        &state,
+        &auth_user,
+        &user,
+        payload.current_password.as_deref(),
+    )
+    .await?;
+
+    let nonce = social_login_service::take_apple_link_nonce(&state.redis, user.id).await?;
+
+    let apple_user = state
+        .apple_service
+        .get_user_data(&payload.id_token, &nonce)
+        .await
+        .map_err(|e| {
+            tracing::error!("Apple OAuth service {}", e);
+            AppError::Authentication("Invalid Apple ID token".to_string())
+        })?;
+
+    let profile = ExternalProfile {
+        provider: "apple".to_string(),
+        subject: apple_user.user_id,
+        email: apple_user.email,
+        email_verified: apple_user.email_verified,
+        display_name: None,
+        avatar_url: None,
+    };
+
+    let identity = identity_service::link_identity(&state.db, user.id, &profile).await?;
+
+    Ok(Json(json!({
+        "message": "Apple account linked successfully",
+        "identity": identity
+    })))
+}
+
+pub async fn unlink_identity(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(identity_id): Path<Uuid>,
+) -> Result<Json<Value>> {
+    if !identity_service::unlink_identity(&state.db, auth_user.user_id, identity_id).await? {
+        return Err(AppError::NotFound("Linked account not found".to_string()));
+    }
+
+    Ok(Json(json!({
+        "message": "Account unlinked successfully"
+    })))
+}
+
+pub async fn get_login_activity(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<PaginationParams>,
+) -> Result<Json<Value>> {
+    let limit = params.limit.unwrap_or(20).min(100);
+    let offset = params.offset.unwrap_or(0);
+
+    let activity =
+        login_security_service::get_login_activity(&state.db, auth_user.user_id, limit, offset)
+            .await?;
+
+    Ok(Json(json!({
+        "activity": activity
+    })))
+}
+
+/// The user's previous usernames and how many more changes they can make.
+pub async fn get_username_history(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Value>> {
+    let allowance =
+        username_service::get_allowance(&state.db, &state.config, auth_user.user_id).await?;
+    let history = username_service::get_history(&state.db, auth_user.user_id).await?;
+
+    Ok(Json(json!({
+        "changes_remaining": allowance.changes_remaining,
+        "next_change_at": allowance.next_change_at,
+        "history": history
+    })))
+}
+
+/// Rename the user. Their old name keeps resolving to them and is held for
+/// them for a while, so links and mentions using it don't break.
+pub async fn change_username(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<ChangeUsernameRequest>,
+) -> Result<Json<Value>> {
+    payload.validate()?;
+
+    let user = user_service::get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    confirm_identity(
+        &state,
+        &auth_user,
+        &user,
+        payload.current_password.as_deref(),
+    )
+    .await?;
+
+    let change =
+        username_service::change_username(&state.db, &state.config, user.id, &payload.new_username)
+            .await?;
+
+    Ok(Json(json!({
+        "message": "Username changed successfully",
+        "change": change
+    })))
+}
+
+pub async fn request_email_change(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<ChangeEmailRequest>,
+) -> Result<Json<Value>> {
+    payload.validate()?;
+
+    let rate_limit_key = format!("contact_change:{}", auth_user.user_id);
+    if !state
+        .redis
+        .check_rate_limit(&rate_limit_key, 5, 3600)
+        .await?
+    {
+        return Err(AppError::RateLimit);
+    }
+
+    let user = user_service::get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    confirm_identity(
+        &state,
+        &auth_user,
+        &user,
+        payload.current_password.as_deref(),
+    )
+    .await?;
+
+    let new_email = payload.new_email.trim().to_string();
+    if user.email.as_deref() == Some(new_email.as_str()) {
+        return Err(AppError::BadRequest(
+            "This is already your email address".to_string(),
+        ));
+    }
+
+    let token =
+        contact_change_service::request_email_change(&state.db, user.id, &new_email).await?;
+
+    state
+        .email_service
+        .send_email_change_verification(&new_email, &user.username, &token, &state.config.base_url)
+        .await?;
+
+    Ok(Json(json!({
+        "message": "We've sent a confirmation link to your new email address. Your current email stays active until you confirm"
+    })))
+}
+
+pub async fn request_phone_change(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<ChangePhoneRequest>,
+) -> Result<Json<Value>> {
+    payload.validate()?;
+
+    let rate_limit_key = format!("contact_change:{}", auth_user.user_id);
+    if !state
+        .redis
+        .check_rate_limit(&rate_limit_key, 5, 3600)
+        .await?
+    {
+        return Err(AppError::RateLimit);
+    }
+
+    let user = user_service::get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    confirm_identity(
+        &state,
+        &auth_user,
+        &user,
+        payload.current_password.as_deref(),
+    )
+    .await?;
+
+    let new_phone = payload.new_phone.trim().to_string();
+    if user.phone.as_deref() == Some(new_phone.as_str()) {
+        return Err(AppError::BadRequest(
+            "This is already your phone number".to_string(),
+        ));
+    }
+
+    let code = contact_change_service::request_phone_change(&state.db, user.id, &new_phone).await?;
+
+    state
+        .sms_service
+        .send_verification_code(&new_phone, &code, &state.config.app_name)
+        .await?;
+
+    Ok(Json(json!({
+        "message": "We've texted a code to your new phone number. Your current number stays active until you confirm"
+    })))
+}
+
+pub async fn confirm_phone_change(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<ConfirmPhoneChangeRequest>,
+) -> Result<Json<Value>> {
+    let change =
+        contact_change_service::confirm_phone_change(&state.db, auth_user.user_id, &payload.code)
+            .await?;
+
+    contact_change_service::notify_previous_contact(
+        &state,
+        &change,
+        contact_change_service::PHONE_CHANNEL,
+    );
+
+    Ok(Json(json!({
+        "message": "Phone number changed successfully",
+        "phone": change.user.phone
+    })))
+}
+
+pub async fn get_contact_changes(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Value>> {
+    let pending = contact_change_service::get_pending_changes(&state.db, auth_user.user_id).await?;
+
+    Ok(Json(json!({
+        "pending_changes": pending
+    })))
+}
+
+pub async fn cancel_contact_change(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(channel): Path<String>,
+) -> Result<Json<Value>> {
+    if channel != contact_change_service::EMAIL_CHANNEL
+        && channel != contact_change_service::PHONE_CHANNEL
+    {
+        return Err(AppError::NotFound("No pending change found".to_string()));
+    }
+
+    let cancelled =
+        contact_change_service::cancel_change(&state.db, auth_user.user_id, &channel).await?;
+
+    if !cancelled {
+        return Err(AppError::NotFound("No pending change found".to_string()));
+    }
+
+    Ok(Json(json!({
+        "message": "Pending change cancelled"
+    })))
+}
+
+/// Queue a personal data export. The archive is built in the background and
+/// the user is notified with a download link when it's ready.
+pub async fn request_data_export(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<(StatusCode, Json<Value>)> {
+    let rate_limit_key = format!("data_export:{}", auth_user.user_id);
+    if !state
+        .redis
+        .check_rate_limit(&rate_limit_key, 3, 86400)
+        .await?
+    {
+        return Err(AppError::RateLimit);
+    }
+
+    let export = data_export_service::request_export(&state.db, auth_user.user_id).await?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "message": "Your data export is being prepared. We'll notify you when it's ready",
+            "export": export
+        })),
+    ))
+}
+
+pub async fn get_data_exports(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Value>> {
+    let exports = data_export_service::list_exports(&state.db, auth_user.user_id).await?;
+
+    let exports: Vec<Value> = exports
+        .iter()
+        .map(|export| {
+            let mut value = json!(export);
+            value["download_url"] = json!(data_export_service::download_url(
+                export,
+                &state.config.base_url,
+                &state.config.jwt_secret,
+            ));
+            value
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "exports": exports
+    })))
+}
+
+/// Download an export archive. Authorised by the signed link alone so it can
+/// be opened straight from a notification or email.
+pub async fn download_data_export(
+    State(state): State<AppState>,
+    Query(query): Query<DownloadExportQuery>,
+) -> Result<Response> {
+    let export = data_export_service::get_export_for_download(
+        &state.db,
+        &query.token,
+        &state.config.jwt_secret,
+    )
+    .await?
+    .ok_or_else(|| AppError::NotFound("Export not found or expired".to_string()))?;
+
+    let file_path = export
+        .file_path
+        .as_deref()
+        .ok_or_else(|| AppError::NotFound("Export not found or expired".to_string()))?;
+
+    let file = tokio::fs::File::open(file_path).await?;
+    let file_name = format!(
+        "reddit-clone-export-{}.zip",
+        export.created_at.format("%Y-%m-%d")
+    );
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", file_name),
+            ),
+        ],
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response())
+}
@@ -21,13 +21,15 @@ use sqlx::PgPool;
 use std::sync::Arc;
 use tower::ServiceBuilder;
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
+use webauthn_rs::Webauthn;
 
 use crate::{
     config::Config,
     redis::RedisClient,
     services::{
         apple_service::AppleOAuthService, auth_service::GoogleOAuthService,
-        email_service::EmailService, sms_service::SmsService,
+        email_service::EmailService, oidc_service::OidcProviders, signing_key_service::SigningKeys,
+        sms_service::SmsService,
     },
 };
 
@@ -36,10 +38,13 @@ pub struct AppState {
     pub db: PgPool,
     pub google_service: Arc<GoogleOAuthService>,
     pub apple_service: Arc<AppleOAuthService>,
+    pub oidc_providers: Arc<OidcProviders>,
     pub redis: Arc<RedisClient>,
     pub config: Arc<Config>,
     pub email_service: Arc<EmailService>,
     pub sms_service: Arc<SmsService>,
+    pub webauthn: Arc<Webauthn>,
+    pub signing_keys: Arc<SigningKeys>,
 }
 
 pub fn create_app(state: AppState) -> Router {
@@ -65,6 +70,42 @@ pub fn create_app(state: AppState) -> Router {
     let public_routes = Router::new()
         .route("/api/auth/register", post(handlers::auth::register))
         .route("/api/auth/login", post(handlers::auth::login))
+        .route("/api/auth/refresh", post(handlers::auth::refresh_token))
+        .route("/.well-known/jwks.json", get(handlers::auth::jwks))
+        .route(
+            "/.well-known/openid-configuration",
+            get(handlers::oauth::openid_configuration),
+        )
+        .route("/api/oauth/token", post(handlers::oauth::token))
+        .route("/api/oauth/revoke", post(handlers::oauth::revoke))
+        .route(
+            "/api/auth/passkeys/login/start",
+            post(handlers::auth::start_passkey_login),
+        )
+        .route(
+            "/api/auth/passkeys/login/finish",
+            post(handlers::auth::finish_passkey_login),
+        )
+        .route(
+            "/api/auth/magic-link",
+            post(handlers::auth::request_magic_link),
+        )
+        .route(
+            "/api/auth/magic-link/verify",
+            post(handlers::auth::verify_magic_link),
+        )
+        .route(
+            "/api/auth/phone/request-code",
+            post(handlers::auth::request_phone_login_code),
+        )
+        .route(
+            "/api/auth/phone/verify-code",
+            post(handlers::auth::verify_phone_login_code),
+        )
+        .route(
+            "/api/auth/login/2fa",
+            post(handlers::auth::verify_two_factor_login),
+        )
         .route(
             "/api/auth/forgot-password",
             post(handlers::auth::forgot_password),
@@ -74,6 +115,14 @@ pub fn create_app(state: AppState) -> Router {
             post(handlers::auth::reset_password),
         )
         .route("/api/auth/verify-email", post(handlers::auth::verify_email))
+        .route(
+            "/api/data-exports/download",
+            get(handlers::users::download_data_export),
+        )
+        .route(
+            "/api/auth/confirm-email-change",
+            post(handlers::auth::confirm_email_change),
+        )
         .route("/api/auth/verify-phone", post(handlers::auth::verify_phone))
         .route(
             "/api/auth/oauth/google",
@@ -90,15 +139,105 @@ pub fn create_app(state: AppState) -> Router {
         .route(
             "/api/auth/oauth/apple/callback",
             post(handlers::auth::apple_oauth),
+        )
+        .route(
+            "/api/auth/oauth/exchange",
+            post(handlers::auth::exchange_login_code),
+        )
+        .route(
+            "/api/auth/registration",
+            get(handlers::auth::get_registration_settings),
+        )
+        .route(
+            "/api/auth/providers",
+            get(handlers::auth::get_oidc_providers),
+        )
+        .route(
+            "/api/auth/oauth/{provider}",
+            get(handlers::auth::initiate_oidc_login),
+        )
+        .route(
+            "/api/auth/oauth/{provider}/callback",
+            get(handlers::auth::oidc_callback),
         );
 
     // Protected routes
     let protected_routes = Router::new()
         .route("/api/auth/logout", post(handlers::auth::logout))
-        .route("/api/auth/refresh", post(handlers::auth::refresh_token))
+        .route(
+            "/api/auth/change-password",
+            post(handlers::auth::change_password),
+        )
+        .route("/api/auth/2fa", get(handlers::auth::get_two_factor_status))
+        .route(
+            "/api/auth/2fa/enroll",
+            post(handlers::auth::enroll_two_factor),
+        )
+        .route(
+            "/api/auth/2fa/confirm",
+            post(handlers::auth::confirm_two_factor),
+        )
+        .route(
+            "/api/auth/2fa/disable",
+            post(handlers::auth::disable_two_factor),
+        )
+        .route(
+            "/api/auth/2fa/recovery-codes",
+            post(handlers::auth::regenerate_recovery_codes),
+        )
+        // OAuth provider routes
+        .route(
+            "/api/oauth/authorize",
+            get(handlers::oauth::get_authorization).post(handlers::oauth::decide_authorization),
+        )
+        .route("/api/oauth/userinfo", get(handlers::oauth::userinfo))
+        .route(
+            "/api/oauth/clients",
+            get(handlers::oauth::get_clients).post(handlers::oauth::create_client),
+        )
+        .route(
+            "/api/oauth/clients/{client_id}",
+            delete(handlers::oauth::delete_client),
+        )
+        // Admin routes
+        .route("/api/admin/stats", get(handlers::admin::get_stats))
+        .route("/api/admin/users", get(handlers::admin::search_users))
+        .route(
+            "/api/admin/users/{user_id}/suspend",
+            post(handlers::admin::suspend_user),
+        )
+        .route(
+            "/api/admin/users/{user_id}/suspensions",
+            get(handlers::admin::get_user_suspensions),
+        )
+        .route(
+            "/api/admin/users/{user_id}/unsuspend",
+            post(handlers::admin::unsuspend_user),
+        )
+        .route(
+            "/api/admin/users/{user_id}/role",
+            put(handlers::admin::set_site_role),
+        )
+        .route(
+            "/api/admin/communities/{name}/status",
+            put(handlers::admin::set_community_status),
+        )
+        .route(
+            "/api/admin/invites",
+            get(handlers::admin::get_invites).post(handlers::admin::create_invite),
+        )
+        .route(
+            "/api/admin/invites/{invite_id}",
+            delete(handlers::admin::revoke_invite),
+        )
+        .route("/api/admin/audit-log", get(handlers::admin::get_audit_log))
         // User routes
         .route("/api/users/me", get(handlers::users::get_current_user))
         .route("/api/users/me", put(handlers::users::update_current_user))
+        .route(
+            "/api/users/me",
+            delete(handlers::users::delete_current_user),
+        )
         .route(
             "/api/users/me/preferences",
             get(handlers::users::get_user_preferences),
@@ -107,6 +246,120 @@ pub fn create_app(state: AppState) -> Router {
             "/api/users/me/preferences",
             put(handlers::users::update_user_preferences),
         )
+        .route(
+            "/api/auth/passkeys/register/start",
+            post(handlers::auth::start_passkey_registration),
+        )
+        .route(
+            "/api/auth/passkeys/register/finish",
+            post(handlers::auth::finish_passkey_registration),
+        )
+        .route("/api/users/me/sessions", get(handlers::users::get_sessions))
+        .route(
+            "/api/users/me/api-tokens",
+            get(handlers::users::get_api_tokens).post(handlers::users::create_api_token),
+        )
+        .route(
+            "/api/users/me/api-tokens/{token_id}",
+            delete(handlers::users::revoke_api_token),
+        )
+        .route(
+            "/api/users/me/invites",
+            get(handlers::users::get_invites).post(handlers::users::create_invite),
+        )
+        .route(
+            "/api/users/me/invites/{invite_id}",
+            delete(handlers::users::revoke_invite),
+        )
+        .route(
+            "/api/users/me/authorized-apps",
+            get(handlers::users::get_authorized_apps),
+        )
+        .route(
+            "/api/users/me/authorized-apps/{client_id}",
+            delete(handlers::users::revoke_authorized_app),
+        )
+        .route("/api/users/me/passkeys", get(handlers::users::get_passkeys))
+        .route(
+            "/api/users/me/passkeys/{passkey_id}",
+            put(handlers::users::rename_passkey),
+        )
+        .route(
+            "/api/users/me/passkeys/{passkey_id}",
+            delete(handlers::users::delete_passkey),
+        )
+        .route(
+            "/api/users/me/username",
+            get(handlers::users::get_username_history),
+        )
+        .route(
+            "/api/users/me/username",
+            post(handlers::users::change_username),
+        )
+        .route(
+            "/api/users/me/email",
+            post(handlers::users::request_email_change),
+        )
+        .route(
+            "/api/users/me/phone",
+            post(handlers::users::request_phone_change),
+        )
+        .route(
+            "/api/users/me/phone/confirm",
+            post(handlers::users::confirm_phone_change),
+        )
+        .route(
+            "/api/users/me/contact-changes",
+            get(handlers::users::get_contact_changes),
+        )
+        .route(
+            "/api/users/me/contact-changes/{channel}",
+            delete(handlers::users::cancel_contact_change),
+        )
+        .route(
+            "/api/users/me/data-exports",
+            get(handlers::users::get_data_exports),
+        )
+        .route(
+            "/api/users/me/data-exports",
+            post(handlers::users::request_data_export),
+        )
+        .route(
+            "/api/users/me/login-activity",
+            get(handlers::users::get_login_activity),
+        )
+        .route(
+            "/api/users/me/identities",
+            get(handlers::users::get_identities),
+        )
+        .route(
+            "/api/users/me/identities/google",
+            post(handlers::users::link_google_identity),
+        )
+        .route(
+            "/api/users/me/identities/apple",
+            post(handlers::users::link_apple_identity),
+        )
+        .route(
+            "/api/users/me/identities/apple/start",
+            post(handlers::users::start_apple_identity_link),
+        )
+        .route(
+            "/api/users/me/identities/oidc/{provider}",
+            post(handlers::users::start_oidc_identity_link),
+        )
+        .route(
+            "/api/users/me/identities/{identity_id}",
+            delete(handlers::users::unlink_identity),
+        )
+        .route(
+            "/api/users/me/sessions",
+            delete(handlers::users::revoke_other_sessions),
+        )
+        .route(
+            "/api/users/me/sessions/{session_id}",
+            delete(handlers::users::revoke_session),
+        )
         .route(
             "/api/users/me/follow/{user_id}",
             post(handlers::users::follow_user),
@@ -115,6 +368,18 @@ pub fn create_app(state: AppState) -> Router {
             "/api/users/me/unfollow/{user_id}",
             delete(handlers::users::unfollow_user),
         )
+        .route(
+            "/api/users/me/follow-requests",
+            get(handlers::users::get_follow_requests),
+        )
+        .route(
+            "/api/users/me/follow-requests/{user_id}/approve",
+            post(handlers::users::approve_follow_request),
+        )
+        .route(
+            "/api/users/me/follow-requests/{user_id}",
+            delete(handlers::users::reject_follow_request),
+        )
         .route(
             "/api/users/me/block/{user_id}",
             post(handlers::users::block_user),
@@ -127,6 +392,34 @@ pub fn create_app(state: AppState) -> Router {
             "/api/users/{username}",
             get(handlers::users::get_user_by_username),
         )
+        .route(
+            "/api/users/{username}/followers",
+            get(handlers::users::get_followers),
+        )
+        .route(
+            "/api/users/{username}/following",
+            get(handlers::users::get_following),
+        )
+        .route(
+            "/api/users/{username}/overview",
+            get(handlers::users::get_user_overview),
+        )
+        .route(
+            "/api/users/{username}/karma",
+            get(handlers::users::get_user_karma),
+        )
+        .route(
+            "/api/users/{username}/karma/history",
+            get(handlers::users::get_user_karma_history),
+        )
+        .route(
+            "/api/users/{username}/posts",
+            get(handlers::posts::get_user_posts),
+        )
+        .route(
+            "/api/users/{username}/comments",
+            get(handlers::comments::get_user_comments),
+        )
         // Community routes
         .route(
             "/api/communities",
@@ -207,6 +500,7 @@ pub fn create_app(state: AppState) -> Router {
             post(handlers::posts::report_post),
         )
         .route("/api/users/me/saved", get(handlers::posts::get_saved_posts))
+        .route("/api/feed/home", get(handlers::posts::get_home_feed))
         // Comment routes
         .route("/api/comments", post(handlers::comments::create_comment))
         .route(
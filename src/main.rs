@@ -5,8 +5,12 @@ use reddit_clone::services::apple_service::AppleOAuthService;
 use reddit_clone::services::auth_service::GoogleOAuthService;
 use reddit_clone::services::background_jobs::BackgroundJobsService;
 use reddit_clone::services::email_service::EmailService;
+use reddit_clone::services::oidc_service::OidcProviders;
+use reddit_clone::services::passkey_service;
+use reddit_clone::services::signing_key_service::{self, SigningKeys};
 use reddit_clone::services::sms_service::SmsService;
 use reddit_clone::{AppState, create_app};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
@@ -63,6 +67,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         ),
     )?);
 
+    // Create generic OpenID Connect providers
+    let oidc_providers = Arc::new(OidcProviders::new(&config)?);
+
+    // Create WebAuthn relying party for passkeys
+    let webauthn = Arc::new(passkey_service::build_webauthn(&config)?);
+
+    // Load access token signing keys, creating the first one if needed
+    signing_key_service::ensure_signing_key(&db).await?;
+    let signing_keys = Arc::new(SigningKeys::load(&db).await?);
+    tracing::info!("Token signing keys loaded");
+
+    let config = Arc::new(config);
+
     let email_service = Arc::new(EmailService::new(&config));
     let sms_service = Arc::new(SmsService::new(&config));
 
@@ -72,6 +89,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         redis.clone(),
         email_service.clone(),
         sms_service.clone(),
+        signing_keys.clone(),
+        config.clone(),
     );
 
     // Start background jobs
@@ -83,9 +102,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         redis,
         google_service,
         apple_service,
-        config: Arc::new(config.clone()),
+        oidc_providers,
+        config: config.clone(),
         email_service,
         sms_service,
+        webauthn,
+        signing_keys,
     };
 
     // Create application
@@ -96,7 +118,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing::info!("Server listening on {}:{}", config.host, config.port);
 
     // Start server
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
@@ -4,6 +4,8 @@ use sqlx::FromRow;
 use uuid::Uuid;
 use validator::Validate;
 
+use crate::models::PostCommunity;
+
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::Type, PartialEq, Eq)]
 #[sqlx(type_name = "comment_status", rename_all = "lowercase")]
 pub enum CommentStatus {
@@ -104,6 +106,15 @@ pub struct CommentResponse {
     pub media: Vec<CommentMediaResponse>,
 }
 
+// A comment in a user's history, with the post it was left on
+#[derive(Debug, Serialize)]
+pub struct UserCommentResponse {
+    #[serde(flatten)]
+    pub comment: CommentResponse,
+    pub post_title: String,
+    pub community: PostCommunity,
+}
+
 #[derive(Debug, Serialize)]
 pub struct CommentAuthor {
     pub id: Uuid,
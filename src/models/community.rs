@@ -12,7 +12,7 @@ pub enum CommunityType {
     Private,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "community_status", rename_all = "lowercase")]
 pub enum CommunityStatus {
     Active,
@@ -1,8 +1,9 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use sqlx::{FromRow, types::Json};
 use uuid::Uuid;
 use validator::Validate;
+use webauthn_rs::prelude::Passkey;
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "auth_provider", rename_all = "lowercase")]
@@ -11,6 +12,7 @@ pub enum AuthProvider {
     Phone,
     Google,
     Apple,
+    Oidc,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::Type)]
@@ -21,6 +23,18 @@ pub enum UserStatus {
     Deleted,
 }
 
+/// Site-wide role, independent of community membership roles. Ordered so
+/// `role >= SiteRole::Staff` reads as "at least staff".
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[sqlx(type_name = "site_role", rename_all = "lowercase")]
+pub enum SiteRole {
+    User,
+    Staff,
+    Admin,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct User {
     pub id: Uuid,
@@ -34,8 +48,11 @@ pub struct User {
     pub avatar_url: Option<String>,
     pub banner_url: Option<String>,
     pub karma_points: i32,
+    pub follower_count: i32,
+    pub following_count: i32,
     pub is_verified: bool,
     pub status: UserStatus,
+    pub site_role: SiteRole,
     pub auth_provider: AuthProvider,
     #[serde(skip_serializing)]
     pub oauth_id: Option<String>,
@@ -44,6 +61,7 @@ pub struct User {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub last_login_at: Option<DateTime<Utc>>,
+    pub deletion_scheduled_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -60,6 +78,10 @@ pub struct UserPreferences {
     pub nsfw_content: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub show_profile_posts: bool,
+    pub show_profile_comments: bool,
+    pub hide_followers: bool,
+    pub require_follow_approval: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -97,6 +119,7 @@ pub struct PhoneVerificationCode {
     pub verified: bool,
     pub attempts: i32,
     pub created_at: DateTime<Utc>,
+    pub purpose: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -106,6 +129,60 @@ pub struct UserSession {
     pub token_jti: String,
     pub expires_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing)]
+    pub refresh_token_hash: Option<String>,
+    pub family_id: Uuid,
+    pub rotated_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub oauth_client_id: Option<Uuid>,
+    pub scopes: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UserTotpSecret {
+    pub user_id: Uuid,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub last_used_step: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UserRecoveryCode {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    #[serde(skip_serializing)]
+    pub code_hash: String,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UserPasskey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    #[serde(skip_serializing)]
+    pub credential_id: String,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub passkey: Json<Passkey>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UserIdentity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub provider: String,
+    #[serde(skip_serializing)]
+    pub subject: String,
+    pub email: Option<String>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -155,6 +232,8 @@ pub struct UserResponse {
     pub avatar_url: Option<String>,
     pub banner_url: Option<String>,
     pub karma_points: i32,
+    pub follower_count: i32,
+    pub following_count: i32,
     pub is_verified: bool,
     pub created_at: DateTime<Utc>,
 }
@@ -169,8 +248,25 @@ impl From<User> for UserResponse {
             avatar_url: user.avatar_url,
             banner_url: user.banner_url,
             karma_points: user.karma_points,
+            follower_count: user.follower_count,
+            following_count: user.following_count,
             is_verified: user.is_verified,
             created_at: user.created_at,
         }
     }
 }
+
+// Sorting options for a user's profile overview
+#[derive(Debug, Deserialize)]
+pub enum ProfileSort {
+    New,
+    Top,
+}
+
+// Bucket size for a user's karma history
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum KarmaInterval {
+    Day,
+    Week,
+    Month,
+}
@@ -43,6 +43,18 @@ impl RedisClient {
         Ok(true)
     }
 
+    /// Increment a counter, starting its expiry window on the first increment.
+    pub async fn increment_counter(&self, key: &str, window_seconds: usize) -> Result<u64> {
+        let mut conn = self.manager.lock().await;
+
+        let count: u64 = conn.incr(key, 1).await?;
+        if count == 1 {
+            let _: () = conn.expire(key, window_seconds as i64).await?;
+        }
+
+        Ok(count)
+    }
+
     // Session management
     pub async fn store_session(
         &self,
@@ -86,6 +98,13 @@ impl RedisClient {
         Ok(value)
     }
 
+    /// Get a value and delete it in one step, so only one caller ever gets it.
+    pub async fn cache_take(&self, key: &str) -> Result<Option<String>> {
+        let mut conn = self.manager.lock().await;
+        let value: Option<String> = conn.get_del(key).await?;
+        Ok(value)
+    }
+
     pub async fn cache_delete(&self, key: &str) -> Result<()> {
         let mut conn = self.manager.lock().await;
         let _: () = conn.del(key).await?;
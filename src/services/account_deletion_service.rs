@@ -0,0 +1,265 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    error::{AppError, Result},
+    redis::RedisClient,
+    services::{session_service, upload_service::UploadService},
+};
+
+/// Placeholder account that posts and comments of deleted users are moved to,
+/// so they are shown as written by "[deleted]".
+pub const DELETED_USER_ID: Uuid = Uuid::nil();
+
+/// Per-user data that is dropped outright when an account is scrubbed.
+const PERSONAL_DATA: &[(&str, &str)] = &[
+    ("user_identities", "user_id"),
+    ("user_passkeys", "user_id"),
+    ("user_totp_secrets", "user_id"),
+    ("user_recovery_codes", "user_id"),
+    ("user_preferences", "user_id"),
+    ("user_community_flairs", "user_id"),
+    ("user_follows", "follower_id"),
+    ("user_follows", "following_id"),
+    ("user_blocks", "blocker_id"),
+    ("user_blocks", "blocked_id"),
+    ("follow_requests", "requester_id"),
+    ("follow_requests", "target_id"),
+    ("username_history", "user_id"),
+    ("community_memberships", "user_id"),
+    ("saved_posts", "user_id"),
+    ("saved_comments", "user_id"),
+    ("search_history", "user_id"),
+    ("notifications", "recipient_id"),
+    ("login_attempts", "user_id"),
+    ("user_suspensions", "user_id"),
+    ("invite_redemptions", "user_id"),
+    ("contact_change_requests", "user_id"),
+    ("email_verification_tokens", "user_id"),
+    ("password_reset_tokens", "user_id"),
+    ("magic_link_tokens", "user_id"),
+    ("upload_sessions", "user_id"),
+    ("api_tokens", "user_id"),
+    ("oauth_authorization_codes", "user_id"),
+    ("oauth_grants", "user_id"),
+    ("oauth_clients", "owner_id"),
+    ("comment_typing_indicators", "user_id"),
+];
+
+/// References to the user that are kept as anonymous rows.
+const ANONYMIZED_REFERENCES: &[(&str, &str)] = &[
+    ("notifications", "sender_id"),
+    ("post_views", "user_id"),
+    ("post_shares", "user_id"),
+    ("user_awards", "giver_id"),
+];
+
+/// Schedule the account for deletion after the grace period and sign it out
+/// everywhere. Signing back in before then cancels the deletion.
+pub async fn schedule_deletion(
+    state: &AppState,
+    user_id: Uuid,
+    grace: Duration,
+) -> Result<DateTime<Utc>> {
+    let scheduled_at = sqlx::query_scalar::<_, DateTime<Utc>>(
+        r#"
+        UPDATE users
+        SET deletion_scheduled_at = $2, updated_at = NOW()
+        WHERE id = $1 AND status != 'deleted'
+        RETURNING deletion_scheduled_at
+        "#,
+    )
+    .bind(user_id)
+    .bind(Utc::now() + grace)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    session_service::revoke_all_sessions(state, user_id).await?;
+
+    Ok(scheduled_at)
+}
+
+/// Cancel a scheduled deletion. Returns false if none was scheduled.
+pub async fn cancel_deletion(db: &PgPool, user_id: Uuid) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE users
+        SET deletion_scheduled_at = NULL, updated_at = NOW()
+        WHERE id = $1 AND deletion_scheduled_at IS NOT NULL AND status != 'deleted'
+        "#,
+    )
+    .bind(user_id)
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Accounts whose grace period has run out.
+pub async fn get_due_deletions(db: &PgPool, limit: i64) -> Result<Vec<Uuid>> {
+    let user_ids = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        SELECT id FROM users
+        WHERE deletion_scheduled_at <= NOW() AND status != 'deleted'
+        ORDER BY deletion_scheduled_at
+        LIMIT $1
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(db)
+    .await?;
+
+    Ok(user_ids)
+}
+
+/// Permanently delete an account whose grace period is over: strip personal
+/// data from the user row, drop credentials, sessions and personal records,
+/// move posts and comments to the "[deleted]" placeholder (leaving threads
+/// intact) and remove uploaded media and data exports. Returns false if the
+/// deletion was cancelled in the meantime.
+pub async fn scrub_account(
+    db: &PgPool,
+    redis: &RedisClient,
+    upload_service: &UploadService,
+    user_id: Uuid,
+) -> Result<bool> {
+    let mut tx = db.begin().await?;
+
+    let due = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        SELECT id FROM users
+        WHERE id = $1 AND deletion_scheduled_at <= NOW() AND status != 'deleted'
+        FOR UPDATE
+        "#,
+    )
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if due.is_none() {
+        return Ok(false);
+    }
+
+    for table in ["posts", "comments"] {
+        sqlx::query(&format!(
+            "UPDATE {} SET author_id = $2 WHERE author_id = $1",
+            table
+        ))
+        .bind(user_id)
+        .bind(DELETED_USER_ID)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    for (table, column) in ANONYMIZED_REFERENCES {
+        sqlx::query(&format!(
+            "UPDATE {} SET {} = NULL WHERE {} = $1",
+            table, column, column
+        ))
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    // Deleting the user's OAuth clients cascades to other users' sessions
+    // with those apps, which have to be dropped from Redis as well
+    let mut session_jtis = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT s.token_jti FROM user_sessions s
+        JOIN oauth_clients c ON c.id = s.oauth_client_id
+        WHERE c.owner_id = $1 AND s.revoked_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for (table, column) in PERSONAL_DATA {
+        sqlx::query(&format!("DELETE FROM {} WHERE {} = $1", table, column))
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    session_jtis.extend(
+        sqlx::query_scalar::<_, String>(
+            "DELETE FROM user_sessions WHERE user_id = $1 RETURNING token_jti",
+        )
+        .bind(user_id)
+        .fetch_all(&mut *tx)
+        .await?,
+    );
+
+    // The username is freed up; the row stays so votes and moderation
+    // records that point at it remain consistent
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET username = 'deleted_' || REPLACE(id::TEXT, '-', ''),
+            email = NULL, phone = NULL, password_hash = NULL, oauth_id = NULL,
+            display_name = NULL, bio = NULL, avatar_url = NULL, banner_url = NULL,
+            email_verified = false, phone_verified = false, is_verified = false,
+            status = 'deleted', deletion_scheduled_at = NULL, last_login_at = NULL,
+            updated_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    let export_files = sqlx::query_scalar::<_, Option<String>>(
+        "DELETE FROM data_exports WHERE user_id = $1 RETURNING file_path",
+    )
+    .bind(user_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let media_file_ids = sqlx::query_scalar::<_, Uuid>(
+        "SELECT id FROM media_files WHERE user_id = $1 AND status != 'deleted'",
+    )
+    .bind(user_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    for jti in session_jtis {
+        redis.delete_session(&jti).await?;
+    }
+
+    for file_path in export_files.iter().flatten() {
+        if let Err(e) = tokio::fs::remove_file(file_path).await {
+            tracing::error!(
+                "Failed to delete data export {} of deleted user {}: {}",
+                file_path,
+                user_id,
+                e
+            );
+        }
+    }
+
+    for media_file_id in media_file_ids {
+        if let Err(e) = upload_service
+            .delete_media_file(db, user_id, media_file_id)
+            .await
+        {
+            tracing::error!(
+                "Failed to delete media file {} of deleted user {}: {}",
+                media_file_id,
+                user_id,
+                e
+            );
+        }
+    }
+
+    sqlx::query("DELETE FROM media_files WHERE user_id = $1 AND status = 'deleted'")
+        .bind(user_id)
+        .execute(db)
+        .await?;
+
+    Ok(true)
+}
@@ -0,0 +1,497 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{Value, json};
+use sqlx::{FromRow, PgConnection, PgPool, types::ipnetwork::IpNetwork};
+use std::net::IpAddr;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    auth::{AuthUser, ClientInfo},
+    error::{AppError, Result},
+    models::{Community, CommunityStatus, SiteRole, User, UserStatus},
+    services::{
+        invite_service,
+        invite_service::{InviteCode, NewInvite},
+        notification_service::NotificationService,
+        session_service, suspension_service, user_service,
+    },
+};
+
+/// The staff member or admin behind an action, as recorded in the audit log.
+#[derive(Debug)]
+pub struct Actor {
+    pub user_id: Uuid,
+    pub site_role: SiteRole,
+    pub ip_address: Option<IpAddr>,
+}
+
+impl Actor {
+    pub fn new(auth_user: &AuthUser, client: &ClientInfo) -> Self {
+        Self {
+            user_id: auth_user.user_id,
+            site_role: auth_user.site_role,
+            ip_address: client.ip_address,
+        }
+    }
+}
+
+/// An account as shown to staff, including fields hidden from other users.
+#[derive(Debug, Serialize, FromRow)]
+pub struct AdminUserSummary {
+    pub id: Uuid,
+    pub username: String,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub display_name: Option<String>,
+    pub status: UserStatus,
+    pub site_role: SiteRole,
+    pub karma_points: i32,
+    pub email_verified: bool,
+    pub phone_verified: bool,
+    pub created_at: DateTime<Utc>,
+    pub last_login_at: Option<DateTime<Utc>>,
+    pub deletion_scheduled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default)]
+pub struct UserSearchFilters<'a> {
+    pub query: Option<&'a str>,
+    pub status: Option<UserStatus>,
+    pub site_role: Option<SiteRole>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct AuditLogEntry {
+    pub id: Uuid,
+    pub actor_id: Uuid,
+    pub actor_username: String,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: Uuid,
+    pub details: Value,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+pub struct AuditLogFilters<'a> {
+    pub actor_id: Option<Uuid>,
+    pub target_id: Option<Uuid>,
+    pub action: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct SiteStats {
+    pub total_users: i64,
+    pub active_users: i64,
+    pub suspended_users: i64,
+    pub new_users_24h: i64,
+    pub new_users_7d: i64,
+    pub active_users_24h: i64,
+    pub total_communities: i64,
+    pub quarantined_communities: i64,
+    pub banned_communities: i64,
+    pub total_posts: i64,
+    pub new_posts_24h: i64,
+    pub total_comments: i64,
+    pub new_comments_24h: i64,
+    pub pending_reports: i64,
+}
+
+/// Append an entry to the audit log. Runs on the caller's connection so the
+/// entry is committed together with the change it describes.
+pub async fn record_action(
+    conn: &mut PgConnection,
+    actor: &Actor,
+    action: &str,
+    target_type: &str,
+    target_id: Uuid,
+    details: Value,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO admin_audit_log (actor_id, action, target_type, target_id, details, ip_address)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(actor.user_id)
+    .bind(action)
+    .bind(target_type)
+    .bind(target_id)
+    .bind(details)
+    .bind(actor.ip_address.map(IpNetwork::from))
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Search accounts by username, display name, email, phone or exact id.
+/// Returns the page of matches and the total number of matches.
+pub async fn search_users(
+    db: &PgPool,
+    filters: &UserSearchFilters<'_>,
+    limit: u32,
+    offset: u32,
+) -> Result<(Vec<AdminUserSummary>, i64)> {
+    let query = filters
+        .query
+        .map(str::trim)
+        .filter(|query| !query.is_empty());
+    let pattern = query.map(|query| format!("%{}%", query));
+
+    let condition = r#"
+        status != 'deleted'
+        AND ($1::TEXT IS NULL
+            OR username ILIKE $1
+            OR display_name ILIKE $1
+            OR email ILIKE $1
+            OR phone ILIKE $1
+            OR id::TEXT = $2)
+        AND ($3::user_status IS NULL OR status = $3)
+        AND ($4::site_role IS NULL OR site_role = $4)
+    "#;
+
+    let users = sqlx::query_as::<_, AdminUserSummary>(&format!(
+        r#"
+        SELECT
+            id, username, email, phone, display_name, status, site_role, karma_points,
+            email_verified, phone_verified, created_at, last_login_at, deletion_scheduled_at
+        FROM users
+        WHERE {}
+        ORDER BY created_at DESC
+        LIMIT $5 OFFSET $6
+        "#,
+        condition
+    ))
+    .bind(&pattern)
+    .bind(query)
+    .bind(filters.status.clone())
+    .bind(filters.site_role)
+    .bind(limit as i64)
+    .bind(offset as i64)
+    .fetch_all(db)
+    .await?;
+
+    let total =
+        sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM users WHERE {}", condition))
+            .bind(&pattern)
+            .bind(query)
+            .bind(filters.status.clone())
+            .bind(filters.site_role)
+            .fetch_one(db)
+            .await?;
+
+    Ok((users, total))
+}
+
+/// Load the account an action targets. Staff can only act on accounts below
+/// their own role, and nobody can act on their own account.
+async fn get_target_user(db: &PgPool, actor: &Actor, user_id: Uuid) -> Result<User> {
+    if user_id == actor.user_id {
+        return Err(AppError::BadRequest(
+            "You can't perform this action on your own account".to_string(),
+        ));
+    }
+
+    let user = user_service::get_user_by_id(db, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    if user.site_role >= actor.site_role {
+        return Err(AppError::Authorization(
+            "You can't perform this action on an account with an equal or higher role".to_string(),
+        ));
+    }
+
+    Ok(user)
+}
+
+/// Suspend an active account, until `ends_at` or indefinitely, and sign it
+/// out everywhere.
+pub async fn suspend_user(
+    state: &AppState,
+    actor: &Actor,
+    user_id: Uuid,
+    reason: &str,
+    ends_at: Option<DateTime<Utc>>,
+) -> Result<()> {
+    let user = get_target_user(&state.db, actor, user_id).await?;
+
+    if ends_at.is_some_and(|ends_at| ends_at <= Utc::now()) {
+        return Err(AppError::Validation(
+            "ends_at: must be in the future".to_string(),
+        ));
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    suspension_service::suspend(&mut tx, user_id, actor.user_id, reason, ends_at).await?;
+
+    record_action(
+        &mut tx,
+        actor,
+        "user.suspend",
+        "user",
+        user_id,
+        json!({ "username": user.username, "reason": reason, "ends_at": ends_at }),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    session_service::revoke_all_sessions(state, user_id).await
+}
+
+/// Lift a suspension early and let the user know.
+pub async fn unsuspend_user(
+    state: &AppState,
+    actor: &Actor,
+    user_id: Uuid,
+    reason: Option<&str>,
+) -> Result<()> {
+    let user = get_target_user(&state.db, actor, user_id).await?;
+
+    let mut tx = state.db.begin().await?;
+
+    if !suspension_service::lift(&mut tx, user_id, Some(actor.user_id)).await? {
+        return Err(AppError::Conflict("User is not suspended".to_string()));
+    }
+
+    record_action(
+        &mut tx,
+        actor,
+        "user.unsuspend",
+        "user",
+        user_id,
+        json!({ "username": user.username, "reason": reason }),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    let notification_service = NotificationService::new(
+        state.db.clone(),
+        state.redis.clone(),
+        state.email_service.clone(),
+        state.sms_service.clone(),
+    );
+
+    suspension_service::notify_reinstated(
+        &state.db,
+        &notification_service,
+        &state.email_service,
+        user_id,
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Change an account's site role. The target has to rank below the actor, so
+/// admins can promote users and staff but can't change another admin.
+pub async fn set_site_role(
+    db: &PgPool,
+    actor: &Actor,
+    user_id: Uuid,
+    site_role: SiteRole,
+) -> Result<()> {
+    let user = get_target_user(db, actor, user_id).await?;
+
+    if user.site_role == site_role {
+        return Err(AppError::Conflict(format!(
+            "User already has the {:?} role",
+            site_role
+        )));
+    }
+
+    let mut tx = db.begin().await?;
+
+    sqlx::query("UPDATE users SET site_role = $2 WHERE id = $1")
+        .bind(user_id)
+        .bind(site_role)
+        .execute(&mut *tx)
+        .await?;
+
+    record_action(
+        &mut tx,
+        actor,
+        "user.set_role",
+        "user",
+        user_id,
+        json!({ "username": user.username, "from": user.site_role, "to": site_role }),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Quarantine, ban or reinstate a community. Returns the updated community.
+pub async fn set_community_status(
+    db: &PgPool,
+    actor: &Actor,
+    name: &str,
+    status: CommunityStatus,
+    reason: Option<&str>,
+) -> Result<Community> {
+    // Unlike community_service lookups this has to find communities in any state
+    let community = sqlx::query_as::<_, Community>("SELECT * FROM communities WHERE name = $1")
+        .bind(name)
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Community not found".to_string()))?;
+
+    if community.status == status {
+        return Err(AppError::Conflict(format!(
+            "Community is already {:?}",
+            status
+        )));
+    }
+
+    let mut tx = db.begin().await?;
+
+    let updated = sqlx::query_as::<_, Community>(
+        "UPDATE communities SET status = $2 WHERE id = $1 RETURNING *",
+    )
+    .bind(community.id)
+    .bind(&status)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    record_action(
+        &mut tx,
+        actor,
+        "community.set_status",
+        "community",
+        community.id,
+        json!({
+            "name": community.name,
+            "from": community.status,
+            "to": status,
+            "reason": reason
+        }),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(updated)
+}
+
+/// Create an invite code without the limits regular users have.
+pub async fn create_invite(
+    db: &PgPool,
+    actor: &Actor,
+    invite: &NewInvite<'_>,
+) -> Result<InviteCode> {
+    let mut tx = db.begin().await?;
+
+    let created = invite_service::insert_invite(&mut tx, actor.user_id, invite).await?;
+
+    record_action(
+        &mut tx,
+        actor,
+        "invite.create",
+        "invite",
+        created.id,
+        json!({
+            "code": created.code,
+            "max_uses": created.max_uses,
+            "expires_at": created.expires_at
+        }),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(created)
+}
+
+/// Revoke anyone's invite code.
+pub async fn revoke_invite(db: &PgPool, actor: &Actor, invite_id: Uuid) -> Result<()> {
+    let mut tx = db.begin().await?;
+
+    let code = invite_service::revoke_invite(&mut tx, invite_id, None)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Invite code not found".to_string()))?;
+
+    record_action(
+        &mut tx,
+        actor,
+        "invite.revoke",
+        "invite",
+        invite_id,
+        json!({ "code": code }),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn get_site_stats(db: &PgPool) -> Result<SiteStats> {
+    let stats = sqlx::query_as::<_, SiteStats>(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM users WHERE status != 'deleted') AS total_users,
+            (SELECT COUNT(*) FROM users WHERE status = 'active') AS active_users,
+            (SELECT COUNT(*) FROM users WHERE status = 'suspended') AS suspended_users,
+            (SELECT COUNT(*) FROM users
+                WHERE status != 'deleted' AND created_at > NOW() - INTERVAL '24 hours') AS new_users_24h,
+            (SELECT COUNT(*) FROM users
+                WHERE status != 'deleted' AND created_at > NOW() - INTERVAL '7 days') AS new_users_7d,
+            (SELECT COUNT(*) FROM users
+                WHERE status = 'active' AND last_login_at > NOW() - INTERVAL '24 hours') AS active_users_24h,
+            (SELECT COUNT(*) FROM communities) AS total_communities,
+            (SELECT COUNT(*) FROM communities WHERE status = 'quarantined') AS quarantined_communities,
+            (SELECT COUNT(*) FROM communities WHERE status = 'banned') AS banned_communities,
+            (SELECT COUNT(*) FROM posts WHERE status = 'active') AS total_posts,
+            (SELECT COUNT(*) FROM posts
+                WHERE status = 'active' AND created_at > NOW() - INTERVAL '24 hours') AS new_posts_24h,
+            (SELECT COUNT(*) FROM comments WHERE status = 'active') AS total_comments,
+            (SELECT COUNT(*) FROM comments
+                WHERE status = 'active' AND created_at > NOW() - INTERVAL '24 hours') AS new_comments_24h,
+            (SELECT COUNT(*) FROM post_reports WHERE status = 'pending')
+                + (SELECT COUNT(*) FROM comment_reports WHERE status = 'pending') AS pending_reports
+        "#,
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(stats)
+}
+
+pub async fn list_audit_log(
+    db: &PgPool,
+    filters: &AuditLogFilters<'_>,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<AuditLogEntry>> {
+    let entries = sqlx::query_as::<_, AuditLogEntry>(
+        r#"
+        SELECT
+            l.id, l.actor_id, u.username AS actor_username, l.action, l.target_type,
+            l.target_id, l.details, host(l.ip_address) AS ip_address, l.created_at
+        FROM admin_audit_log l
+        JOIN users u ON u.id = l.actor_id
+        WHERE ($1::UUID IS NULL OR l.actor_id = $1)
+        AND ($2::UUID IS NULL OR l.target_id = $2)
+        AND ($3::TEXT IS NULL OR l.action = $3)
+        ORDER BY l.created_at DESC
+        LIMIT $4 OFFSET $5
+        "#,
+    )
+    .bind(filters.actor_id)
+    .bind(filters.target_id)
+    .bind(filters.action)
+    .bind(limit as i64)
+    .bind(offset as i64)
+    .fetch_all(db)
+    .await?;
+
+    Ok(entries)
+}
@@ -0,0 +1,180 @@
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool, types::ipnetwork::IpNetwork};
+use uuid::Uuid;
+
+use crate::{
+    auth::{ClientInfo, hash_token},
+    error::{AppError, Result},
+    models::SiteRole,
+    services::oauth_provider_service::SCOPES,
+};
+
+/// Personal API tokens start with this so they can be told apart from session
+/// JWTs (and spotted by secret scanners).
+pub const API_TOKEN_PREFIX: &str = "rdt_";
+
+const MAX_API_TOKENS_PER_USER: i64 = 25;
+
+/// A personal API token as shown to its owner. The token itself is only
+/// returned once, when it's created.
+#[derive(Debug, Serialize, FromRow)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub name: String,
+    pub token_prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub last_used_ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The owner and scopes of a valid API token, for the `AuthUser` extractor.
+#[derive(Debug, FromRow)]
+pub struct ApiTokenOwner {
+    pub token_id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+    pub site_role: SiteRole,
+    pub scopes: Vec<String>,
+}
+
+/// Create a token for the user. Returns the stored token and the secret
+/// value to hand to the user.
+pub async fn create_token(
+    db: &PgPool,
+    user_id: Uuid,
+    name: &str,
+    scopes: &[String],
+    expires_in: Option<Duration>,
+) -> Result<(ApiToken, String)> {
+    if scopes.is_empty() {
+        return Err(AppError::Validation(
+            "scopes: at least one scope is required".to_string(),
+        ));
+    }
+
+    for scope in scopes {
+        if !SCOPES.iter().any(|(supported, _)| supported == scope) {
+            return Err(AppError::Validation(format!(
+                "scopes: '{}' is not a valid scope",
+                scope
+            )));
+        }
+    }
+
+    let token_count =
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM api_tokens WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(db)
+            .await?;
+
+    if token_count >= MAX_API_TOKENS_PER_USER {
+        return Err(AppError::BadRequest(format!(
+            "You can have at most {} API tokens, revoke one first",
+            MAX_API_TOKENS_PER_USER
+        )));
+    }
+
+    let bytes: [u8; 32] = rand::random();
+    let token = format!(
+        "{}{}",
+        API_TOKEN_PREFIX,
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+    );
+
+    let mut unique_scopes = scopes.to_vec();
+    unique_scopes.sort();
+    unique_scopes.dedup();
+
+    let api_token = sqlx::query_as::<_, ApiToken>(
+        r#"
+        INSERT INTO api_tokens (user_id, name, token_hash, token_prefix, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING
+            id, name, token_prefix, scopes, expires_at, last_used_at,
+            host(last_used_ip) AS last_used_ip, created_at
+        "#,
+    )
+    .bind(user_id)
+    .bind(name.trim())
+    .bind(hash_token(&token))
+    .bind(token.chars().take(12).collect::<String>())
+    .bind(&unique_scopes)
+    .bind(expires_in.map(|expires_in| Utc::now() + expires_in))
+    .fetch_one(db)
+    .await?;
+
+    Ok((api_token, token))
+}
+
+pub async fn list_tokens(db: &PgPool, user_id: Uuid) -> Result<Vec<ApiToken>> {
+    let tokens = sqlx::query_as::<_, ApiToken>(
+        r#"
+        SELECT
+            id, name, token_prefix, scopes, expires_at, last_used_at,
+            host(last_used_ip) AS last_used_ip, created_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(tokens)
+}
+
+/// Revoke one of the user's tokens. Returns false if the user has no such token.
+pub async fn revoke_token(db: &PgPool, user_id: Uuid, token_id: Uuid) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM api_tokens WHERE id = $1 AND user_id = $2")
+        .bind(token_id)
+        .bind(user_id)
+        .execute(db)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Look up the owner of an unexpired token. Tokens stop working while their
+/// owner's account is inactive or scheduled for deletion.
+pub async fn authenticate(db: &PgPool, token: &str) -> Result<Option<ApiTokenOwner>> {
+    let owner = sqlx::query_as::<_, ApiTokenOwner>(
+        r#"
+        SELECT t.id AS token_id, t.user_id, u.username, u.site_role, t.scopes
+        FROM api_tokens t
+        JOIN users u ON u.id = t.user_id
+        WHERE t.token_hash = $1
+        AND (t.expires_at IS NULL OR t.expires_at > NOW())
+        AND u.status = 'active'
+        AND u.deletion_scheduled_at IS NULL
+        "#,
+    )
+    .bind(hash_token(token))
+    .fetch_optional(db)
+    .await?;
+
+    Ok(owner)
+}
+
+/// Record that a token was used. Throttled like session last-used tracking so
+/// busy scripts don't each write to the database.
+pub async fn touch_token(db: &PgPool, token_id: Uuid, client: &ClientInfo) -> Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE api_tokens
+        SET last_used_at = NOW(), last_used_ip = $2
+        WHERE id = $1
+        AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '5 minutes')
+        "#,
+    )
+    .bind(token_id)
+    .bind(client.ip_address.map(IpNetwork::from))
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
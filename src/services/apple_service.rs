@@ -1,15 +1,17 @@
 use chrono::{Duration, Utc};
 use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
 use oauth2::{
-    AccessToken, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, RedirectUrl,
-    ResponseType, Scope, TokenResponse, TokenUrl, basic::BasicClient, url,
+    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, ResponseType, Scope, TokenUrl, url,
 };
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 
+use crate::services::auth_service::IdTokenFields;
+
 type AppleClient = oauth2::Client<
     oauth2::StandardErrorResponse<oauth2::basic::BasicErrorResponseType>,
-    oauth2::StandardTokenResponse<oauth2::EmptyExtraTokenFields, oauth2::basic::BasicTokenType>,
+    oauth2::StandardTokenResponse<IdTokenFields, oauth2::basic::BasicTokenType>,
     oauth2::StandardTokenIntrospectionResponse<
         oauth2::EmptyExtraTokenFields,
         oauth2::basic::BasicTokenType,
@@ -36,8 +38,10 @@ struct AppleJWTClaims {
 pub struct AppleIdToken {
     pub sub: String,
     pub email: Option<String>,
-    pub email_verified: Option<String>,
+    /// Apple sends this as either a string or a boolean
+    pub email_verified: Option<serde_json::Value>,
     pub name: Option<serde_json::Value>,
+    pub nonce: Option<String>,
     pub aud: String,
     pub iss: String,
     pub exp: i64,
@@ -69,6 +73,22 @@ pub struct AppleUserData {
     pub last_name: Option<String>,
 }
 
+impl From<AppleIdToken> for AppleUserData {
+    fn from(id_token: AppleIdToken) -> Self {
+        AppleUserData {
+            user_id: id_token.sub,
+            email: id_token.email,
+            email_verified: match id_token.email_verified {
+                Some(serde_json::Value::Bool(verified)) => verified,
+                Some(serde_json::Value::String(verified)) => verified == "true",
+                _ => false,
+            },
+            first_name: None,
+            last_name: None,
+        }
+    }
+}
+
 pub struct AppleOAuthService {
     client: AppleClient,
     client_id: String,
@@ -90,7 +110,7 @@ impl AppleOAuthService {
             .redirect(reqwest::redirect::Policy::none())
             .build()?;
 
-        let client = BasicClient::new(ClientId::new(client_id.to_string()))
+        let client = oauth2::Client::new(ClientId::new(client_id.to_string()))
             .set_auth_uri(AuthUrl::new(
                 "https://appleid.apple.com/auth/authorize".to_string(),
             )?)
@@ -130,16 +150,35 @@ impl AppleOAuthService {
         Ok(token)
     }
 
-    pub fn get_authorization_url(&self) -> (url::Url, CsrfToken) {
-        self.client
-            .authorize_url(CsrfToken::new_random)
+    pub fn get_authorization_url(&self, state: &str, nonce: &str, code_verifier: &str) -> url::Url {
+        let code_challenge = PkceCodeChallenge::from_code_verifier_sha256(&PkceCodeVerifier::new(
+            code_verifier.to_string(),
+        ));
+        let state = state.to_string();
+
+        // Apple only returns the name and email to a form_post callback
+        let (url, _) = self
+            .client
+            .authorize_url(move || CsrfToken::new(state))
             .add_scope(Scope::new("name".to_string()))
             .add_scope(Scope::new("email".to_string()))
             .set_response_type(&ResponseType::new("code".to_string()))
-            .url()
+            .set_pkce_challenge(code_challenge)
+            .add_extra_param("response_mode", "form_post")
+            .add_extra_param("nonce", nonce)
+            .url();
+
+        url
     }
 
-    pub async fn exchange_code_for_token(&self, code: &str) -> Result<AccessToken, Box<dyn Error>> {
+    /// Exchange the authorization code for an ID token and verify it was issued
+    /// for this sign-in.
+    pub async fn authenticate(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<AppleUserData, Box<dyn Error>> {
         let client_secret = self.create_client_secret()?;
 
         let client_with_secret = self
@@ -149,10 +188,23 @@ impl AppleOAuthService {
 
         let token_result = client_with_secret
             .exchange_code(AuthorizationCode::new(code.to_string()))
+            .set_pkce_verifier(PkceCodeVerifier::new(code_verifier.to_string()))
             .request_async(&self.http_client)
             .await?;
 
-        Ok(token_result.access_token().clone())
+        let id_token = token_result
+            .extra_fields()
+            .id_token
+            .as_deref()
+            .ok_or("No ID token received from Apple")?;
+
+        let apple_id_token = self.verify_apple_id_token(id_token).await?;
+
+        if apple_id_token.nonce.as_deref() != Some(nonce) {
+            return Err("ID token nonce mismatch".into());
+        }
+
+        Ok(AppleUserData::from(apple_id_token))
     }
 
     async fn fetch_apple_public_keys(&self) -> Result<ApplePublicKeys, Box<dyn Error>> {
@@ -190,27 +242,19 @@ impl AppleOAuthService {
         Ok(token_data.claims)
     }
 
+    /// Verify an ID token the client got from Apple itself, for a request the
+    /// server issued `nonce` for.
     pub async fn get_user_data(
         &self,
-        id_token: Option<String>,
+        id_token: &str,
+        nonce: &str,
     ) -> Result<AppleUserData, Box<dyn Error>> {
-        if let Some(id_token) = id_token {
-            let apple_id_token = self.verify_apple_id_token(&id_token).await?;
-
-            let user_data = AppleUserData {
-                user_id: apple_id_token.sub,
-                email: apple_id_token.email,
-                email_verified: apple_id_token
-                    .email_verified
-                    .map(|v| v == "true")
-                    .unwrap_or(false),
-                first_name: None,
-                last_name: None,
-            };
-
-            Ok(user_data)
-        } else {
-            Err("No ID token received from Apple".into())
+        let apple_id_token = self.verify_apple_id_token(id_token).await?;
+
+        if apple_id_token.nonce.as_deref() != Some(nonce) {
+            return Err("ID token nonce mismatch".into());
         }
+
+        Ok(AppleUserData::from(apple_id_token))
     }
 }
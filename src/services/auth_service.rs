@@ -1,13 +1,18 @@
 use crate::{
     AppState,
+    auth::{hash_token, sign_value, verify_signed_value},
     error::{AppError, Result},
-    models::User,
+    models::{PhoneVerificationCode, User},
+    services::username_service,
 };
+use base64::Engine;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode};
+use oauth2::url;
 use oauth2::{
-    AccessToken, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, RedirectUrl, Scope,
-    TokenResponse, TokenUrl, reqwest,
+    AccessToken, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, Scope, TokenResponse, TokenUrl, reqwest,
 };
-use oauth2::{basic::BasicClient, url};
+use serde::{Deserialize, Serialize};
 use std::error::Error;
 
 use sqlx::PgPool;
@@ -18,13 +23,8 @@ pub async fn generate_unique_username(db: &PgPool, base_username: &str) -> Resul
     let mut counter = 0;
 
     loop {
-        let existing =
-            sqlx::query("SELECT id FROM users WHERE username = $1 AND status != 'deleted'")
-                .bind(&username)
-                .fetch_optional(db)
-                .await?;
-
-        if existing.is_none() {
+        // Names held for users who changed away from them are skipped too
+        if username_service::is_available(db, &username).await? {
             return Ok(username);
         }
 
@@ -104,15 +104,150 @@ pub async fn send_password_reset_email(_state: &AppState, user: &User, token: &s
     Ok(())
 }
 
-fn generate_verification_code() -> String {
+/// Issue a signed, single-use magic sign-in token. Only its hash is stored.
+pub async fn create_magic_link_token(state: &AppState, user: &User) -> Result<String> {
+    let nonce: [u8; 32] = rand::random();
+    let token = sign_value(
+        &base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(nonce),
+        &state.config.jwt_secret,
+    );
+    let expires_at = chrono::Utc::now() + chrono::Duration::minutes(15);
+
+    sqlx::query(
+        r#"
+        INSERT INTO magic_link_tokens (id, user_id, token_hash, expires_at, created_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user.id)
+    .bind(hash_token(&token))
+    .bind(expires_at)
+    .bind(chrono::Utc::now())
+    .execute(&state.db)
+    .await?;
+
+    Ok(token)
+}
+
+/// Spend a magic sign-in token, returning the user it was issued to. Tokens with
+/// a bad signature are rejected without touching the database.
+pub async fn consume_magic_link_token(state: &AppState, token: &str) -> Result<Option<Uuid>> {
+    if verify_signed_value(token, &state.config.jwt_secret).is_none() {
+        return Ok(None);
+    }
+
+    let user_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE magic_link_tokens
+        SET used_at = NOW()
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+        RETURNING user_id
+        "#,
+    )
+    .bind(hash_token(token))
+    .fetch_optional(&state.db)
+    .await?;
+
+    Ok(user_id)
+}
+
+pub const PHONE_CODE_MAX_ATTEMPTS: i32 = 5;
+
+/// Store a fresh 6-digit code for a phone number. Any outstanding code for the
+/// same phone and purpose is expired so only the latest one works.
+pub async fn create_phone_code(db: &PgPool, phone: &str, purpose: &str) -> Result<String> {
+    let code = generate_verification_code();
+    let expires_at = chrono::Utc::now() + chrono::Duration::minutes(10);
+
+    sqlx::query(
+        r#"
+        UPDATE phone_verification_codes SET expires_at = NOW()
+        WHERE phone = $1 AND purpose = $2 AND verified = false AND expires_at > NOW()
+        "#,
+    )
+    .bind(phone)
+    .bind(purpose)
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO phone_verification_codes (id, phone, code, purpose, expires_at, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(phone)
+    .bind(&code)
+    .bind(purpose)
+    .bind(expires_at)
+    .bind(chrono::Utc::now())
+    .execute(db)
+    .await?;
+
+    Ok(code)
+}
+
+/// Check a code against the latest outstanding one for the phone and purpose.
+/// Every check counts as an attempt; after too many the code stops working.
+pub async fn check_phone_code(db: &PgPool, phone: &str, purpose: &str, code: &str) -> Result<bool> {
+    let pending = sqlx::query_as::<_, PhoneVerificationCode>(
+        r#"
+        SELECT * FROM phone_verification_codes
+        WHERE phone = $1 AND purpose = $2 AND verified = false AND expires_at > NOW()
+        ORDER BY created_at DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(phone)
+    .bind(purpose)
+    .fetch_optional(db)
+    .await?;
+
+    let Some(pending) = pending else {
+        return Ok(false);
+    };
+
+    let attempts = sqlx::query_scalar::<_, i32>(
+        "UPDATE phone_verification_codes SET attempts = attempts + 1 WHERE id = $1 RETURNING attempts",
+    )
+    .bind(pending.id)
+    .fetch_one(db)
+    .await?;
+
+    if attempts > PHONE_CODE_MAX_ATTEMPTS || pending.code != code.trim() {
+        return Ok(false);
+    }
+
+    let result = sqlx::query(
+        "UPDATE phone_verification_codes SET verified = true WHERE id = $1 AND verified = false",
+    )
+    .bind(pending.id)
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+pub fn generate_verification_code() -> String {
     use rand::Rng;
     let mut rng = rand::rng();
     format!("{:06}", rng.random_range(100000..999999))
 }
 
+/// Token response fields beyond the OAuth2 ones: the ID token OpenID Connect
+/// providers return alongside the access token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdTokenFields {
+    pub id_token: Option<String>,
+}
+
+impl oauth2::ExtraTokenFields for IdTokenFields {}
+
 type GoogleOAuthClient = oauth2::Client<
     oauth2::StandardErrorResponse<oauth2::basic::BasicErrorResponseType>,
-    oauth2::StandardTokenResponse<oauth2::EmptyExtraTokenFields, oauth2::basic::BasicTokenType>,
+    oauth2::StandardTokenResponse<IdTokenFields, oauth2::basic::BasicTokenType>,
     oauth2::StandardTokenIntrospectionResponse<
         oauth2::EmptyExtraTokenFields,
         oauth2::basic::BasicTokenType,
@@ -126,8 +261,24 @@ type GoogleOAuthClient = oauth2::Client<
     oauth2::EndpointSet,
 >;
 
+const GOOGLE_ISSUERS: &[&str] = &["https://accounts.google.com", "accounts.google.com"];
+
+#[derive(Debug, Deserialize)]
+struct GoogleIdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+}
+
+/// A completed Google sign-in: the access token for the userinfo endpoint and
+/// the account the ID token was issued for.
+pub struct GoogleSignIn {
+    pub access_token: AccessToken,
+    pub subject: String,
+}
+
 pub struct GoogleOAuthService {
     client: GoogleOAuthClient,
+    client_id: String,
     http_client: reqwest::Client,
 }
 
@@ -141,7 +292,7 @@ impl GoogleOAuthService {
             .redirect(reqwest::redirect::Policy::none())
             .build()?;
 
-        let client = BasicClient::new(ClientId::new(client_id.to_string()))
+        let client = oauth2::Client::new(ClientId::new(client_id.to_string()))
             .set_client_secret(ClientSecret::new(client_secret.to_string()))
             .set_auth_uri(AuthUrl::new(
                 "https://accounts.google.com/o/oauth2/v2/auth".to_string(),
@@ -153,32 +304,73 @@ impl GoogleOAuthService {
 
         Ok(Self {
             client,
+            client_id: client_id.to_string(),
             http_client,
         })
     }
 
-    pub fn get_authorization_url(&self) -> (url::Url, CsrfToken) {
-        self.client
-            .authorize_url(CsrfToken::new_random)
+    pub fn get_authorization_url(&self, state: &str, nonce: &str, code_verifier: &str) -> url::Url {
+        let code_challenge = PkceCodeChallenge::from_code_verifier_sha256(&PkceCodeVerifier::new(
+            code_verifier.to_string(),
+        ));
+        let state = state.to_string();
+
+        let (url, _) = self
+            .client
+            .authorize_url(move || CsrfToken::new(state))
+            .add_scope(Scope::new("openid".to_string()))
             .add_scope(Scope::new(
                 "https://www.googleapis.com/auth/userinfo.email".to_string(),
             ))
             .add_scope(Scope::new(
                 "https://www.googleapis.com/auth/userinfo.profile".to_string(),
             ))
-            .url()
+            .set_pkce_challenge(code_challenge)
+            .add_extra_param("nonce", nonce)
+            .url();
+
+        url
     }
 
+    /// Exchange the authorization code and check the ID token that comes with
+    /// it was issued to us, for this sign-in.
     pub async fn exchange_code_for_token(
         &self,
         code: &str,
-    ) -> std::result::Result<AccessToken, Box<dyn Error>> {
+        code_verifier: &str,
+        nonce: &str,
+    ) -> std::result::Result<GoogleSignIn, Box<dyn Error>> {
         let token_result = self
             .client
             .exchange_code(AuthorizationCode::new(code.to_string()))
+            .set_pkce_verifier(PkceCodeVerifier::new(code_verifier.to_string()))
             .request_async(&self.http_client)
             .await?;
 
-        Ok(token_result.access_token().clone())
+        let id_token = token_result
+            .extra_fields()
+            .id_token
+            .as_deref()
+            .ok_or("No ID token received from Google")?;
+
+        // The ID token came straight from Google's token endpoint over TLS, so
+        // its signature doesn't need checking (OIDC Core 3.1.3.7)
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.insecure_disable_signature_validation();
+        validation.set_audience(&[&self.client_id]);
+        validation.set_issuer(GOOGLE_ISSUERS);
+
+        let claims =
+            decode::<GoogleIdTokenClaims>(id_token, &DecodingKey::from_secret(&[]), &validation)?
+                .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err("ID token nonce mismatch".into());
+        }
+
+        Ok(GoogleSignIn {
+            access_token: token_result.access_token().clone(),
+            subject: claims.sub,
+        })
     }
 }
@@ -4,11 +4,19 @@ use std::sync::Arc;
 use tokio::time::{Duration, interval};
 
 use crate::{
+    config::Config,
     error::Result,
     redis::RedisClient,
     services::{
-        email_service::EmailService, notification_service::NotificationService,
-        sms_service::SmsService, typing_service::TypingService,
+        account_deletion_service, data_export_service,
+        email_service::EmailService,
+        karma_service,
+        notification_service::NotificationService,
+        signing_key_service::{self, SigningKeys},
+        sms_service::SmsService,
+        suspension_service,
+        typing_service::TypingService,
+        upload_service::UploadService,
     },
 };
 
@@ -20,6 +28,8 @@ pub struct BackgroundJobsService {
     typing_service: TypingService,
     email_service: Arc<EmailService>,
     sms_service: Arc<SmsService>,
+    signing_keys: Arc<SigningKeys>,
+    config: Arc<Config>,
 }
 
 impl BackgroundJobsService {
@@ -28,6 +38,8 @@ impl BackgroundJobsService {
         redis: Arc<RedisClient>,
         email_service: Arc<EmailService>,
         sms_service: Arc<SmsService>,
+        signing_keys: Arc<SigningKeys>,
+        config: Arc<Config>,
     ) -> Self {
         let notification_service = NotificationService::new(
             db.clone(),
@@ -45,6 +57,8 @@ impl BackgroundJobsService {
             typing_service,
             email_service,
             sms_service,
+            signing_keys,
+            config,
         }
     }
 
@@ -106,6 +120,97 @@ impl BackgroundJobsService {
             }
         });
 
+        let jobs_service = self.clone();
+
+        // Purge expired verification, reset and sign-in tokens every hour
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(3600)); // 1 hour
+            loop {
+                interval.tick().await;
+                if let Err(e) = jobs_service.cleanup_expired_tokens().await {
+                    tracing::error!("Failed to cleanup expired tokens: {}", e);
+                }
+            }
+        });
+
+        let jobs_service = self.clone();
+
+        // Purge old sign-in history once a day
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(86400)); // 24 hours
+            loop {
+                interval.tick().await;
+                if let Err(e) = jobs_service.cleanup_login_attempts().await {
+                    tracing::error!("Failed to cleanup login attempts: {}", e);
+                }
+            }
+        });
+
+        let jobs_service = self.clone();
+
+        // Build queued data exports every minute
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(60)); // 1 minute
+            loop {
+                interval.tick().await;
+                if let Err(e) = jobs_service.process_data_exports().await {
+                    tracing::error!("Failed to process data exports: {}", e);
+                }
+            }
+        });
+
+        let jobs_service = self.clone();
+
+        // Scrub accounts past their deletion grace period every hour
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(3600)); // 1 hour
+            loop {
+                interval.tick().await;
+                if let Err(e) = jobs_service.process_account_deletions().await {
+                    tracing::error!("Failed to process account deletions: {}", e);
+                }
+            }
+        });
+
+        let jobs_service = self.clone();
+
+        // Reinstate users whose suspension has run out every minute
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(60)); // 1 minute
+            loop {
+                interval.tick().await;
+                if let Err(e) = jobs_service.process_expired_suspensions().await {
+                    tracing::error!("Failed to process expired suspensions: {}", e);
+                }
+            }
+        });
+
+        let jobs_service = self.clone();
+
+        // Rotate token signing keys when due and pick up keys created by other instances
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(300)); // 5 minutes
+            loop {
+                interval.tick().await;
+                if let Err(e) = jobs_service.refresh_signing_keys().await {
+                    tracing::error!("Failed to refresh signing keys: {}", e);
+                }
+            }
+        });
+
+        let jobs_service = self.clone();
+
+        // Recompute karma from votes once a day
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(86400)); // 24 hours
+            loop {
+                interval.tick().await;
+                if let Err(e) = jobs_service.reconcile_karma().await {
+                    tracing::error!("Failed to reconcile karma: {}", e);
+                }
+            }
+        });
+
         tracing::info!("Background jobs started successfully");
     }
 
@@ -130,6 +235,149 @@ impl BackgroundJobsService {
         Ok(())
     }
 
+    /// Cleanup expired email, phone, password reset and magic link tokens
+    async fn cleanup_expired_tokens(&self) -> Result<()> {
+        sqlx::query("SELECT cleanup_expired_tokens()")
+            .execute(&self.db)
+            .await?;
+
+        tracing::debug!("Expired tokens cleaned up");
+        Ok(())
+    }
+
+    /// Cleanup sign-in history older than 90 days
+    async fn cleanup_login_attempts(&self) -> Result<()> {
+        let result =
+            sqlx::query("DELETE FROM login_attempts WHERE created_at < NOW() - INTERVAL '90 days'")
+                .execute(&self.db)
+                .await?;
+
+        if result.rows_affected() > 0 {
+            tracing::info!("Cleaned up {} old login attempts", result.rows_affected());
+        }
+        Ok(())
+    }
+
+    /// Build queued personal data exports and remove expired ones
+    async fn process_data_exports(&self) -> Result<()> {
+        let ttl_days = self.config.data_export_ttl_days;
+
+        while let Some(export) = data_export_service::claim_next_export(&self.db).await? {
+            let export = match data_export_service::generate_export(
+                &self.db,
+                &export,
+                &self.config.data_export_dir,
+                chrono::Duration::days(ttl_days),
+            )
+            .await
+            {
+                Ok(export) => export,
+                Err(e) => {
+                    tracing::error!("Failed to generate data export {}: {}", export.id, e);
+                    continue;
+                }
+            };
+
+            let Some(download_url) = data_export_service::download_url(
+                &export,
+                &self.config.base_url,
+                &self.config.jwt_secret,
+            ) else {
+                continue;
+            };
+
+            if let Err(e) = self
+                .notification_service
+                .create_notification(
+                    export.user_id,
+                    None,
+                    crate::models::NotificationType::SystemAnnouncement,
+                    "Your data export is ready".to_string(),
+                    Some(format!(
+                        "Download it within {} days: {}",
+                        ttl_days, download_url
+                    )),
+                    None,
+                    None,
+                    None,
+                )
+                .await
+            {
+                tracing::warn!(
+                    "Failed to notify user {} of data export: {}",
+                    export.user_id,
+                    e
+                );
+            }
+        }
+
+        let removed = data_export_service::cleanup_expired_exports(&self.db).await?;
+        if removed > 0 {
+            tracing::info!("Cleaned up {} expired data exports", removed);
+        }
+
+        Ok(())
+    }
+
+    /// Scrub accounts whose deletion grace period has ended
+    async fn process_account_deletions(&self) -> Result<()> {
+        let user_ids = account_deletion_service::get_due_deletions(&self.db, 100).await?;
+        let upload_service = UploadService::new(self.config.upload_config.clone());
+
+        for user_id in user_ids {
+            match account_deletion_service::scrub_account(
+                &self.db,
+                &self.redis,
+                &upload_service,
+                user_id,
+            )
+            .await
+            {
+                Ok(true) => tracing::info!("Deleted account {}", user_id),
+                Ok(false) => {}
+                Err(e) => tracing::error!("Failed to delete account {}: {}", user_id, e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reinstate users whose suspension has ended and notify them
+    async fn process_expired_suspensions(&self) -> Result<()> {
+        let user_ids = suspension_service::get_expired_suspensions(&self.db, 100).await?;
+
+        for user_id in user_ids {
+            match suspension_service::reinstate_expired(
+                &self.db,
+                &self.notification_service,
+                &self.email_service,
+                user_id,
+            )
+            .await
+            {
+                Ok(true) => tracing::info!("Reinstated user {} after suspension ended", user_id),
+                Ok(false) => {}
+                Err(e) => tracing::error!("Failed to reinstate user {}: {}", user_id, e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Start a signing key rotation if the current key is old enough, then reload the key set
+    async fn refresh_signing_keys(&self) -> Result<()> {
+        let rotation = chrono::Duration::days(self.config.jwt_key_rotation_days);
+        // Retired keys must outlive every access token they signed
+        let grace = chrono::Duration::hours(self.config.jwt_key_grace_hours).max(
+            chrono::Duration::minutes(self.config.access_token_ttl_minutes),
+        );
+
+        signing_key_service::rotate_if_due(&self.db, rotation, grace).await?;
+        self.signing_keys.reload(&self.db).await?;
+
+        Ok(())
+    }
+
     /// Send daily digest emails to users who have it enabled
     async fn send_daily_digests(&self) -> Result<()> {
         let users_with_digest = sqlx::query!(
@@ -267,44 +515,20 @@ impl BackgroundJobsService {
         Ok(())
     }
 
-    /// Update user karma based on recent votes
-    pub async fn update_user_karma(&self) -> Result<()> {
-        // Update karma for users based on votes received in the last hour
-        sqlx::query!(
-            r#"
-            WITH karma_changes AS (
-                SELECT 
-                    p.author_id as user_id,
-                    SUM(pv.vote_type) as karma_change
-                FROM post_votes pv
-                JOIN posts p ON pv.post_id = p.id
-                WHERE pv.created_at > NOW() - INTERVAL '1 hour'
-                GROUP BY p.author_id
-                
-                UNION ALL
-                
-                SELECT 
-                    c.author_id as user_id,
-                    SUM(cv.vote_type) as karma_change
-                FROM comment_votes cv
-                JOIN comments c ON cv.comment_id = c.id
-                WHERE cv.created_at > NOW() - INTERVAL '1 hour'
-                GROUP BY c.author_id
-            )
-            UPDATE users 
-            SET karma_points = karma_points + COALESCE(kc.total_karma, 0)
-            FROM (
-                SELECT user_id, SUM(karma_change) as total_karma
-                FROM karma_changes
-                GROUP BY user_id
-            ) kc
-            WHERE users.id = kc.user_id
-            "#
-        )
-        .execute(&self.db)
-        .await?;
+    /// Correct users whose karma has drifted from the votes they've received
+    async fn reconcile_karma(&self) -> Result<()> {
+        let user_ids = karma_service::find_drifted_users(&self.db, 1000).await?;
+
+        for user_id in user_ids {
+            match karma_service::reconcile_user(&self.db, user_id).await {
+                Ok(0) => {}
+                Ok(correction) => {
+                    tracing::info!("Corrected karma of user {} by {}", user_id, correction)
+                }
+                Err(e) => tracing::error!("Failed to reconcile karma of user {}: {}", user_id, e),
+            }
+        }
 
-        tracing::debug!("User karma updated");
         Ok(())
     }
 }
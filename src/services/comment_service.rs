@@ -1,12 +1,13 @@
 use chrono::Utc;
-use sqlx::{PgPool, Row};
+use sqlx::{PgPool, Row, postgres::PgRow};
 use uuid::Uuid;
 
 use crate::{
     error::{AppError, Result},
     models::{
         Comment, CommentAuthor, CommentMediaResponse, CommentResponse, CommentSort, CommentStatus,
-        CreateCommentRequest, MembershipRole, UpdateCommentRequest, VoteResponse,
+        CreateCommentRequest, MembershipRole, PostCommunity, UpdateCommentRequest,
+        UserCommentResponse, VoteResponse,
     },
 };
 
@@ -442,6 +443,30 @@ pub async fn unsave_comment(db: &PgPool, user_id: Uuid, comment_id: Uuid) -> Res
     Ok(())
 }
 
+/// Columns for a comment in a user's history, with the post and community it
+/// was left in. Expects `comments c`, `posts p`, `communities co` and `users u`,
+/// with the viewer bound as `$1`.
+const USER_COMMENT_SELECT: &str = r#"
+    SELECT
+        c.id, c.content, c.post_id, c.author_id, c.parent_comment_id,
+        c.status, c.is_edited, c.upvotes, c.downvotes,
+        c.score, c.reply_count, c.depth, c.created_at, c.updated_at, c.edited_at,
+        u.username, u.display_name as user_display_name, u.avatar_url, u.is_verified,
+        p.title as post_title,
+        co.id as community_id, co.name as community_name,
+        co.display_name as community_display_name, co.icon_url as community_icon,
+        cv.vote_type as user_vote,
+        sc.id IS NOT NULL as is_saved
+    FROM comments c
+    JOIN posts p ON c.post_id = p.id
+    JOIN communities co ON p.community_id = co.id
+    JOIN users u ON c.author_id = u.id
+    LEFT JOIN comment_votes cv ON c.id = cv.comment_id AND cv.user_id = $1
+    LEFT JOIN saved_comments sc ON c.id = sc.comment_id AND sc.user_id = $1
+"#;
+
+/// A user's comments as `viewer_id` may see them: comments in private
+/// communities only show to members and the author themselves.
 pub async fn get_user_comments(
     db: &PgPool,
     user_id: Uuid,
@@ -449,7 +474,7 @@ pub async fn get_user_comments(
     sort: CommentSort,
     limit: u32,
     offset: u32,
-) -> Result<Vec<CommentResponse>> {
+) -> Result<Vec<UserCommentResponse>> {
     let order_clause = match sort {
         CommentSort::Best => "c.score DESC, c.created_at DESC",
         CommentSort::Top => "c.score DESC, c.created_at DESC",
@@ -460,22 +485,19 @@ pub async fn get_user_comments(
 
     let query = format!(
         r#"
-        SELECT 
-            c.id, c.content, c.post_id, c.author_id, c.parent_comment_id, 
-            c.status as "status: CommentStatus", c.is_edited, c.upvotes, c.downvotes, 
-            c.score, c.reply_count, c.depth, c.created_at, c.updated_at, c.edited_at,
-            u.username, u.display_name as user_display_name, u.avatar_url, u.is_verified,
-            CASE WHEN cv.vote_type IS NOT NULL THEN cv.vote_type ELSE NULL END as user_vote,
-            CASE WHEN sc.id IS NOT NULL THEN true ELSE false END as is_saved
-        FROM comments c
-        JOIN users u ON c.author_id = u.id
-        LEFT JOIN comment_votes cv ON c.id = cv.comment_id AND cv.user_id = $1
-        LEFT JOIN saved_comments sc ON c.id = sc.comment_id AND sc.user_id = $1
-        WHERE c.author_id = $2 AND c.status::TEXT as status_str != 'deleted'
+        {}
+        WHERE c.author_id = $2 AND c.status = 'active' AND co.status != 'banned'
+        AND (
+            co.community_type != 'private' OR $1 = $2
+            OR EXISTS (
+                SELECT 1 FROM community_memberships m
+                WHERE m.community_id = co.id AND m.user_id = $1
+            )
+        )
         ORDER BY {}
         LIMIT $3 OFFSET $4
         "#,
-        order_clause
+        USER_COMMENT_SELECT, order_clause
     );
 
     let rows = sqlx::query(&query)
@@ -488,22 +510,48 @@ pub async fn get_user_comments(
 
     let mut comments = Vec::new();
     for row in rows {
-        let comment_id: Uuid = row.get("id");
+        comments.push(user_comment_from_row(db, &row).await?);
+    }
 
-        // Get media for this comment
-        let media = get_comment_media(db, comment_id).await?;
+    Ok(comments)
+}
 
-        let comment = CommentResponse {
+/// Comments from a user's history by id, in no particular order. Visibility
+/// is up to the caller.
+pub async fn get_user_comments_by_ids(
+    db: &PgPool,
+    comment_ids: &[Uuid],
+    viewer_id: Option<Uuid>,
+) -> Result<Vec<UserCommentResponse>> {
+    let query = format!("{} WHERE c.id = ANY($2)", USER_COMMENT_SELECT);
+
+    let rows = sqlx::query(&query)
+        .bind(viewer_id)
+        .bind(comment_ids)
+        .fetch_all(db)
+        .await?;
+
+    let mut comments = Vec::new();
+    for row in rows {
+        comments.push(user_comment_from_row(db, &row).await?);
+    }
+
+    Ok(comments)
+}
+
+async fn user_comment_from_row(db: &PgPool, row: &PgRow) -> Result<UserCommentResponse> {
+    let comment_id: Uuid = row.get("id");
+
+    // Get media for this comment
+    let media = get_comment_media(db, comment_id).await?;
+
+    Ok(UserCommentResponse {
+        comment: CommentResponse {
             id: comment_id,
             content: row.get("content"),
             post_id: row.get("post_id"),
             parent_comment_id: row.get("parent_comment_id"),
-            status: serde_json::from_value(
-                serde_json::to_value(row.get::<serde_json::Value, _>("status"))
-                    .map_err(|_e| AppError::Internal("Missing status".to_string()))?,
-            )
-            .map_err(|e| AppError::Internal(e.to_string()))?,
-
+            status: row.get("status"),
             is_edited: row.get("is_edited"),
             upvotes: row.get("upvotes"),
             downvotes: row.get("downvotes"),
@@ -524,12 +572,15 @@ pub async fn get_user_comments(
             is_saved: row.get("is_saved"),
             replies: Vec::new(), // Don't load replies for user comment lists
             media,
-        };
-
-        comments.push(comment);
-    }
-
-    Ok(comments)
+        },
+        post_title: row.get("post_title"),
+        community: PostCommunity {
+            id: row.get("community_id"),
+            name: row.get("community_name"),
+            display_name: row.get("community_display_name"),
+            icon_url: row.get("community_icon"),
+        },
+    })
 }
 
 pub async fn get_saved_comments(
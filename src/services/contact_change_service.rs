@@ -0,0 +1,301 @@
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    auth::hash_token,
+    error::{AppError, Result},
+    models::User,
+    services::auth_service::{PHONE_CODE_MAX_ATTEMPTS, generate_verification_code},
+};
+
+pub const EMAIL_CHANNEL: &str = "email";
+pub const PHONE_CHANNEL: &str = "phone";
+
+const EMAIL_CHANGE_TTL_HOURS: i64 = 24;
+const PHONE_CHANGE_TTL_MINUTES: i64 = 10;
+
+/// An email or phone change waiting for confirmation.
+#[derive(Debug, Serialize, FromRow)]
+pub struct PendingContactChange {
+    pub channel: String,
+    pub new_value: String,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A confirmed change, with the value it replaced so the old address can be told.
+#[derive(Debug)]
+pub struct ContactChange {
+    pub user: User,
+    pub old_value: Option<String>,
+}
+
+/// Email and phone are unique across all accounts, so a new value must not
+/// belong to anyone else.
+async fn ensure_available(
+    conn: &mut PgConnection,
+    channel: &str,
+    value: &str,
+    user_id: Uuid,
+) -> Result<()> {
+    let query = match channel {
+        EMAIL_CHANNEL => "SELECT EXISTS(SELECT 1 FROM users WHERE email = $1 AND id != $2)",
+        _ => "SELECT EXISTS(SELECT 1 FROM users WHERE phone = $1 AND id != $2)",
+    };
+
+    let taken = sqlx::query_scalar::<_, bool>(query)
+        .bind(value)
+        .bind(user_id)
+        .fetch_one(conn)
+        .await?;
+
+    if taken {
+        return Err(AppError::Conflict(format!(
+            "This {} is already in use",
+            if channel == EMAIL_CHANNEL {
+                "email"
+            } else {
+                "phone number"
+            }
+        )));
+    }
+
+    Ok(())
+}
+
+/// Start (or restart) a change, replacing any earlier request on the channel.
+async fn create_request(
+    db: &PgPool,
+    user_id: Uuid,
+    channel: &str,
+    new_value: &str,
+    secret: &str,
+    ttl: Duration,
+) -> Result<()> {
+    let mut conn = db.acquire().await?;
+    ensure_available(&mut conn, channel, new_value, user_id).await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO contact_change_requests (user_id, channel, new_value, secret_hash, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (user_id, channel) DO UPDATE
+        SET new_value = EXCLUDED.new_value,
+            secret_hash = EXCLUDED.secret_hash,
+            attempts = 0,
+            expires_at = EXCLUDED.expires_at,
+            created_at = NOW()
+        "#,
+    )
+    .bind(user_id)
+    .bind(channel)
+    .bind(new_value)
+    .bind(hash_token(secret))
+    .bind(Utc::now() + ttl)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Start an email change. Returns the token to email to the new address.
+pub async fn request_email_change(db: &PgPool, user_id: Uuid, new_email: &str) -> Result<String> {
+    let bytes: [u8; 32] = rand::random();
+    let token = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+
+    create_request(
+        db,
+        user_id,
+        EMAIL_CHANNEL,
+        new_email,
+        &token,
+        Duration::hours(EMAIL_CHANGE_TTL_HOURS),
+    )
+    .await?;
+
+    Ok(token)
+}
+
+/// Start a phone change. Returns the code to text to the new number.
+pub async fn request_phone_change(db: &PgPool, user_id: Uuid, new_phone: &str) -> Result<String> {
+    let code = generate_verification_code();
+
+    create_request(
+        db,
+        user_id,
+        PHONE_CHANNEL,
+        new_phone,
+        &code,
+        Duration::minutes(PHONE_CHANGE_TTL_MINUTES),
+    )
+    .await?;
+
+    Ok(code)
+}
+
+pub async fn get_pending_changes(db: &PgPool, user_id: Uuid) -> Result<Vec<PendingContactChange>> {
+    let pending = sqlx::query_as::<_, PendingContactChange>(
+        r#"
+        SELECT channel, new_value, expires_at, created_at
+        FROM contact_change_requests
+        WHERE user_id = $1 AND expires_at > NOW()
+        ORDER BY created_at
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(pending)
+}
+
+pub async fn cancel_change(db: &PgPool, user_id: Uuid, channel: &str) -> Result<bool> {
+    let result =
+        sqlx::query("DELETE FROM contact_change_requests WHERE user_id = $1 AND channel = $2")
+            .bind(user_id)
+            .bind(channel)
+            .execute(db)
+            .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Swap the new value in. Only the address column and its verified flag
+/// change, so an email or phone account still satisfies `users_auth_check`.
+async fn apply_change(db: &PgPool, request_id: Uuid, channel: &str) -> Result<ContactChange> {
+    let mut tx = db.begin().await?;
+
+    let (user_id, new_value) = sqlx::query_as::<_, (Uuid, String)>(
+        "DELETE FROM contact_change_requests WHERE id = $1 RETURNING user_id, new_value",
+    )
+    .bind(request_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::BadRequest("Invalid or expired confirmation".to_string()))?;
+
+    // Someone else may have claimed the address since the request was made
+    let (old_value, update) = if channel == EMAIL_CHANNEL {
+        (
+            "SELECT email FROM users WHERE id = $1 FOR UPDATE",
+            "UPDATE users SET email = $2, email_verified = true, updated_at = NOW() WHERE id = $1 RETURNING *",
+        )
+    } else {
+        (
+            "SELECT phone FROM users WHERE id = $1 FOR UPDATE",
+            "UPDATE users SET phone = $2, phone_verified = true, updated_at = NOW() WHERE id = $1 RETURNING *",
+        )
+    };
+
+    let old_value = sqlx::query_scalar::<_, Option<String>>(old_value)
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    ensure_available(&mut tx, channel, &new_value, user_id).await?;
+
+    let user = sqlx::query_as::<_, User>(update)
+        .bind(user_id)
+        .bind(&new_value)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    // Links already mailed to the old address must not keep working
+    if channel == EMAIL_CHANNEL {
+        sqlx::query("DELETE FROM password_reset_tokens WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM magic_link_tokens WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(ContactChange { user, old_value })
+}
+
+/// Confirm an email change from the link sent to the new address.
+pub async fn confirm_email_change(db: &PgPool, token: &str) -> Result<ContactChange> {
+    let request_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        SELECT id FROM contact_change_requests
+        WHERE secret_hash = $1 AND channel = 'email' AND expires_at > NOW()
+        "#,
+    )
+    .bind(hash_token(token))
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| AppError::BadRequest("Invalid or expired confirmation link".to_string()))?;
+
+    apply_change(db, request_id, EMAIL_CHANNEL).await
+}
+
+/// Confirm a phone change with the code texted to the new number. Every
+/// check counts as an attempt; after too many the request stops working.
+pub async fn confirm_phone_change(db: &PgPool, user_id: Uuid, code: &str) -> Result<ContactChange> {
+    let invalid = || AppError::BadRequest("Invalid or expired code".to_string());
+
+    let (request_id, secret_hash, attempts) = sqlx::query_as::<_, (Uuid, String, i32)>(
+        r#"
+        UPDATE contact_change_requests
+        SET attempts = attempts + 1
+        WHERE user_id = $1 AND channel = 'phone' AND expires_at > NOW()
+        RETURNING id, secret_hash, attempts
+        "#,
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(invalid)?;
+
+    if attempts > PHONE_CODE_MAX_ATTEMPTS || secret_hash != hash_token(code.trim()) {
+        return Err(invalid());
+    }
+
+    apply_change(db, request_id, PHONE_CHANNEL).await
+}
+
+/// Tell the old email or phone about a confirmed change, in the background so
+/// a slow provider doesn't hold up the response.
+pub fn notify_previous_contact(state: &AppState, change: &ContactChange, channel: &str) {
+    let Some(old_value) = change.old_value.clone() else {
+        return;
+    };
+
+    let channel = channel.to_string();
+    let username = change.user.username.clone();
+    let user_id = change.user.id;
+    let new_email = change.user.email.clone().unwrap_or_default();
+    let email_service = state.email_service.clone();
+    let sms_service = state.sms_service.clone();
+    let app_name = state.config.app_name.clone();
+
+    tokio::spawn(async move {
+        let result = if channel == EMAIL_CHANNEL {
+            email_service
+                .send_email_changed_notice(&old_value, &username, &new_email)
+                .await
+        } else {
+            sms_service
+                .send_security_alert(&old_value, &app_name, "A phone number change")
+                .await
+                .map(|_| ())
+        };
+
+        if let Err(e) = result {
+            tracing::error!(
+                "Failed to notify user {} of {} change: {}",
+                user_id,
+                channel,
+                e
+            );
+        }
+    });
+}
@@ -0,0 +1,416 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{FromRow, PgPool};
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    auth::{sign_value, verify_signed_value},
+    error::{AppError, Result},
+};
+
+/// An export stuck in processing this long is assumed to belong to a crashed
+/// worker and is picked up again.
+const STALE_EXPORT_MINUTES: i64 = 60;
+
+/// One JSON file in the archive per section, each produced by a single query
+/// returning a JSON value for the user `$1`.
+const EXPORT_SECTIONS: &[(&str, &str)] = &[
+    (
+        "profile.json",
+        r#"
+        SELECT to_jsonb(u) - 'password_hash' - 'oauth_id' - 'search_vector'
+        FROM users u WHERE u.id = $1
+        "#,
+    ),
+    (
+        "username_history.json",
+        r#"
+        SELECT COALESCE(jsonb_agg(to_jsonb(h) - 'id' - 'user_id' ORDER BY h.changed_at), '[]'::jsonb)
+        FROM username_history h WHERE h.user_id = $1
+        "#,
+    ),
+    (
+        "preferences.json",
+        r#"
+        SELECT COALESCE((SELECT to_jsonb(p) FROM user_preferences p WHERE p.user_id = $1), '{}'::jsonb)
+        "#,
+    ),
+    (
+        "posts.json",
+        r#"
+        SELECT COALESCE(jsonb_agg(to_jsonb(p) - 'search_vector' - 'hot_score' ORDER BY p.created_at), '[]'::jsonb)
+        FROM posts p WHERE p.author_id = $1
+        "#,
+    ),
+    (
+        "comments.json",
+        r#"
+        SELECT COALESCE(jsonb_agg(to_jsonb(c) - 'path' ORDER BY c.created_at), '[]'::jsonb)
+        FROM comments c WHERE c.author_id = $1
+        "#,
+    ),
+    (
+        "votes.json",
+        r#"
+        SELECT jsonb_build_object(
+            'posts', (SELECT COALESCE(jsonb_agg(to_jsonb(v) ORDER BY v.created_at), '[]'::jsonb)
+                      FROM post_votes v WHERE v.user_id = $1),
+            'comments', (SELECT COALESCE(jsonb_agg(to_jsonb(v) ORDER BY v.created_at), '[]'::jsonb)
+                         FROM comment_votes v WHERE v.user_id = $1)
+        )
+        "#,
+    ),
+    (
+        "saved.json",
+        r#"
+        SELECT jsonb_build_object(
+            'posts', (SELECT COALESCE(jsonb_agg(to_jsonb(s) ORDER BY s.created_at), '[]'::jsonb)
+                      FROM saved_posts s WHERE s.user_id = $1),
+            'comments', (SELECT COALESCE(jsonb_agg(to_jsonb(s) ORDER BY s.created_at), '[]'::jsonb)
+                         FROM saved_comments s WHERE s.user_id = $1)
+        )
+        "#,
+    ),
+    (
+        "follows.json",
+        r#"
+        SELECT jsonb_build_object(
+            'following', (SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                              'username', u.username, 'followed_at', f.created_at) ORDER BY f.created_at), '[]'::jsonb)
+                          FROM user_follows f JOIN users u ON u.id = f.following_id
+                          WHERE f.follower_id = $1),
+            'followers', (SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                              'username', u.username, 'followed_at', f.created_at) ORDER BY f.created_at), '[]'::jsonb)
+                          FROM user_follows f JOIN users u ON u.id = f.follower_id
+                          WHERE f.following_id = $1),
+            'requested', (SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                              'username', u.username, 'requested_at', r.created_at) ORDER BY r.created_at), '[]'::jsonb)
+                          FROM follow_requests r JOIN users u ON u.id = r.target_id
+                          WHERE r.requester_id = $1),
+            'requests', (SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                             'username', u.username, 'requested_at', r.created_at) ORDER BY r.created_at), '[]'::jsonb)
+                         FROM follow_requests r JOIN users u ON u.id = r.requester_id
+                         WHERE r.target_id = $1)
+        )
+        "#,
+    ),
+    (
+        "blocks.json",
+        r#"
+        SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                   'username', u.username, 'blocked_at', b.created_at) ORDER BY b.created_at), '[]'::jsonb)
+        FROM user_blocks b JOIN users u ON u.id = b.blocked_id
+        WHERE b.blocker_id = $1
+        "#,
+    ),
+    (
+        "search_history.json",
+        r#"
+        SELECT COALESCE(jsonb_agg(to_jsonb(s) - 'user_id' ORDER BY s.created_at), '[]'::jsonb)
+        FROM search_history s WHERE s.user_id = $1
+        "#,
+    ),
+    (
+        "notifications.json",
+        r#"
+        SELECT COALESCE(jsonb_agg(to_jsonb(n) ORDER BY n.created_at), '[]'::jsonb)
+        FROM notifications n WHERE n.recipient_id = $1
+        "#,
+    ),
+    (
+        "media.json",
+        r#"
+        SELECT COALESCE(jsonb_agg(to_jsonb(m) - 'file_path' - 'user_id' ORDER BY m.created_at), '[]'::jsonb)
+        FROM media_files m WHERE m.user_id = $1 AND m.status = 'completed'
+        "#,
+    ),
+];
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct DataExport {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub status: String,
+    #[serde(skip_serializing)]
+    pub file_path: Option<String>,
+    pub file_size: Option<i64>,
+    pub error: Option<String>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Queue a new export. Only one can be in progress per user at a time.
+pub async fn request_export(db: &PgPool, user_id: Uuid) -> Result<DataExport> {
+    let in_progress = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM data_exports
+            WHERE user_id = $1 AND status IN ('pending', 'processing')
+        )
+        "#,
+    )
+    .bind(user_id)
+    .fetch_one(db)
+    .await?;
+
+    if in_progress {
+        return Err(AppError::Conflict(
+            "A data export is already being prepared".to_string(),
+        ));
+    }
+
+    // A concurrent request can get past the check; the unique index on
+    // in-progress exports turns it away
+    let export = sqlx::query_as::<_, DataExport>(
+        "INSERT INTO data_exports (user_id) VALUES ($1) RETURNING *",
+    )
+    .bind(user_id)
+    .fetch_one(db)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(ref db_error) if db_error.is_unique_violation() => {
+            AppError::Conflict("A data export is already being prepared".to_string())
+        }
+        e => e.into(),
+    })?;
+
+    Ok(export)
+}
+
+pub async fn list_exports(db: &PgPool, user_id: Uuid) -> Result<Vec<DataExport>> {
+    let exports = sqlx::query_as::<_, DataExport>(
+        "SELECT * FROM data_exports WHERE user_id = $1 ORDER BY created_at DESC",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(exports)
+}
+
+/// Signed download link for a completed export, valid until the export expires.
+pub fn download_url(export: &DataExport, base_url: &str, secret: &str) -> Option<String> {
+    let expires_at = export.expires_at.filter(|_| export.status == "completed")?;
+    let token = sign_value(&format!("{}:{}", export.id, expires_at.timestamp()), secret);
+
+    Some(format!(
+        "{}/api/data-exports/download?token={}",
+        base_url, token
+    ))
+}
+
+/// Look up the export a download token was issued for, if the token is
+/// genuine and the export is still available.
+pub async fn get_export_for_download(
+    db: &PgPool,
+    token: &str,
+    secret: &str,
+) -> Result<Option<DataExport>> {
+    let Some((export_id, expires_at)) =
+        verify_signed_value(token, secret).and_then(|value| value.split_once(':'))
+    else {
+        return Ok(None);
+    };
+
+    let (Ok(export_id), Ok(expires_at)) = (export_id.parse::<Uuid>(), expires_at.parse::<i64>())
+    else {
+        return Ok(None);
+    };
+
+    if expires_at <= Utc::now().timestamp() {
+        return Ok(None);
+    }
+
+    let export = sqlx::query_as::<_, DataExport>(
+        r#"
+        SELECT * FROM data_exports
+        WHERE id = $1 AND status = 'completed' AND expires_at > NOW()
+        "#,
+    )
+    .bind(export_id)
+    .fetch_optional(db)
+    .await?;
+
+    Ok(export)
+}
+
+/// Take the oldest pending export (or one abandoned by a crashed worker).
+pub async fn claim_next_export(db: &PgPool) -> Result<Option<DataExport>> {
+    let export = sqlx::query_as::<_, DataExport>(
+        r#"
+        UPDATE data_exports
+        SET status = 'processing', started_at = NOW()
+        WHERE id = (
+            SELECT id FROM data_exports
+            WHERE status = 'pending'
+               OR (status = 'processing' AND started_at < $1)
+            ORDER BY created_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING *
+        "#,
+    )
+    .bind(Utc::now() - Duration::minutes(STALE_EXPORT_MINUTES))
+    .fetch_optional(db)
+    .await?;
+
+    Ok(export)
+}
+
+/// Build the archive for a claimed export and mark it completed, or failed
+/// if anything goes wrong.
+pub async fn generate_export(
+    db: &PgPool,
+    export: &DataExport,
+    export_dir: &str,
+    ttl: Duration,
+) -> Result<DataExport> {
+    match write_archive(db, export, export_dir).await {
+        Ok((file_path, file_size)) => {
+            let export = sqlx::query_as::<_, DataExport>(
+                r#"
+                UPDATE data_exports
+                SET status = 'completed', file_path = $2, file_size = $3,
+                    completed_at = NOW(), expires_at = $4
+                WHERE id = $1
+                RETURNING *
+                "#,
+            )
+            .bind(export.id)
+            .bind(file_path.to_string_lossy().to_string())
+            .bind(file_size as i64)
+            .bind(Utc::now() + ttl)
+            .fetch_one(db)
+            .await?;
+
+            Ok(export)
+        }
+        Err(e) => {
+            sqlx::query("UPDATE data_exports SET status = 'failed', error = $2 WHERE id = $1")
+                .bind(export.id)
+                .bind(e.to_string())
+                .execute(db)
+                .await?;
+
+            Err(e)
+        }
+    }
+}
+
+async fn write_archive(
+    db: &PgPool,
+    export: &DataExport,
+    export_dir: &str,
+) -> Result<(PathBuf, u64)> {
+    let mut sections = Vec::with_capacity(EXPORT_SECTIONS.len());
+    for (file_name, query) in EXPORT_SECTIONS {
+        let value = sqlx::query_scalar::<_, Value>(query)
+            .bind(export.user_id)
+            .fetch_one(db)
+            .await?;
+        let contents = serde_json::to_vec_pretty(&value)
+            .map_err(|e| AppError::Internal(format!("Failed to serialize {}: {}", file_name, e)))?;
+        sections.push((file_name.to_string(), contents));
+    }
+
+    let media = sqlx::query_as::<_, (Uuid, String, String)>(
+        r#"
+        SELECT id, original_name, file_path FROM media_files
+        WHERE user_id = $1 AND status = 'completed'
+        ORDER BY created_at
+        "#,
+    )
+    .bind(export.user_id)
+    .fetch_all(db)
+    .await?;
+
+    tokio::fs::create_dir_all(export_dir).await?;
+    let file_path = Path::new(export_dir).join(format!("{}.zip", export.id));
+
+    // The zip writer is synchronous and media originals can be large
+    let archive_path = file_path.clone();
+    tokio::task::spawn_blocking(move || build_zip(&archive_path, sections, media))
+        .await
+        .map_err(|e| AppError::Internal(format!("Export task failed: {}", e)))??;
+
+    let file_size = tokio::fs::metadata(&file_path).await?.len();
+
+    Ok((file_path, file_size))
+}
+
+fn build_zip(
+    path: &Path,
+    sections: Vec<(String, Vec<u8>)>,
+    media: Vec<(Uuid, String, String)>,
+) -> Result<()> {
+    let zip_error = |e: zip::result::ZipError| AppError::Internal(format!("Zip error: {}", e));
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .large_file(true);
+
+    let mut zip = ZipWriter::new(File::create(path)?);
+
+    for (file_name, contents) in sections {
+        zip.start_file(file_name, options).map_err(zip_error)?;
+        zip.write_all(&contents)?;
+    }
+
+    for (media_id, original_name, media_path) in media {
+        let mut source = match File::open(&media_path) {
+            Ok(source) => source,
+            Err(e) => {
+                tracing::warn!("Skipping media file {} in export: {}", media_id, e);
+                continue;
+            }
+        };
+
+        let safe_name: String = original_name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '.' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+
+        zip.start_file(format!("media/{}_{}", media_id, safe_name), options)
+            .map_err(zip_error)?;
+        io::copy(&mut source, &mut zip)?;
+    }
+
+    zip.finish().map_err(zip_error)?;
+
+    Ok(())
+}
+
+/// Delete archives past their expiry. Returns how many were removed.
+pub async fn cleanup_expired_exports(db: &PgPool) -> Result<u64> {
+    let expired = sqlx::query_scalar::<_, Option<String>>(
+        "DELETE FROM data_exports WHERE expires_at < NOW() RETURNING file_path",
+    )
+    .fetch_all(db)
+    .await?;
+
+    for file_path in expired.iter().flatten() {
+        match tokio::fs::remove_file(file_path).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                tracing::error!("Failed to remove expired export {}: {}", file_path, e);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(expired.len() as u64)
+}
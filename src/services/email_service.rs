@@ -282,6 +282,451 @@ impl EmailService {
         .await
     }
 
+    pub async fn send_magic_link_email(
+        &self,
+        to_email: &str,
+        username: &str,
+        token: &str,
+        base_url: &str,
+    ) -> Result<()> {
+        let login_url = format!("{}/magic-link?token={}", base_url, token);
+
+        let subject = "Your sign-in link";
+        let html_content = format!(
+            r#"
+            <!DOCTYPE html>
+            <html>
+            <head>
+                <meta charset="utf-8">
+                <title>Sign In</title>
+                <style>
+                    body {{ font-family: Arial, sans-serif; line-height: 1.6; color: #333; }}
+                    .container {{ max-width: 600px; margin: 0 auto; padding: 20px; }}
+                    .header {{ background-color: #ff4500; color: white; padding: 20px; text-align: center; }}
+                    .content {{ padding: 20px; background-color: #f9f9f9; }}
+                    .button {{ display: inline-block; padding: 12px 24px; background-color: #ff4500; color: white; text-decoration: none; border-radius: 4px; margin: 20px 0; }}
+                    .footer {{ padding: 20px; text-align: center; color: #666; font-size: 12px; }}
+                    .warning {{ background-color: #fff3cd; border: 1px solid #ffeaa7; padding: 15px; border-radius: 4px; margin: 15px 0; }}
+                </style>
+            </head>
+            <body>
+                <div class="container">
+                    <div class="header">
+                        <h1>Sign In to Reddit Clone</h1>
+                    </div>
+                    <div class="content">
+                        <h2>Hi {}!</h2>
+                        <p>Click the button below to sign in to your Reddit Clone account. No password needed.</p>
+                        <a href="{}" class="button">Sign In</a>
+                        <p>Or copy and paste this link into your browser:</p>
+                        <p><a href="{}">{}</a></p>
+                        <div class="warning">
+                            <strong>Important:</strong>
+                            <ul>
+                                <li>This link will expire in 15 minutes</li>
+                                <li>You can only use this link once</li>
+                                <li>If you didn't request this link, please ignore this email</li>
+                            </ul>
+                        </div>
+                    </div>
+                    <div class="footer">
+                        <p>© 2024 Reddit Clone. All rights reserved.</p>
+                    </div>
+                </div>
+            </body>
+            </html>
+            "#,
+            username, login_url, login_url, login_url
+        );
+
+        let text_content = format!(
+            r#"
+            Hi {}!
+
+            Click this link to sign in to your Reddit Clone account: {}
+
+            Important:
+            - This link will expire in 15 minutes
+            - You can only use this link once
+            - If you didn't request this link, please ignore this email
+
+            © 2024 Reddit Clone. All rights reserved.
+            "#,
+            username, login_url
+        );
+
+        self.send_email(
+            to_email,
+            Some(username),
+            subject,
+            &html_content,
+            &text_content,
+        )
+        .await
+    }
+
+    pub async fn send_security_alert_email(
+        &self,
+        to_email: &str,
+        username: &str,
+        device: &str,
+        ip_address: &str,
+        signed_in_at: &str,
+    ) -> Result<()> {
+        let subject = "New sign-in to your account";
+        let html_content = format!(
+            r#"
+            <!DOCTYPE html>
+            <html>
+            <head>
+                <meta charset="utf-8">
+                <title>New Sign-In</title>
+                <style>
+                    body {{ font-family: Arial, sans-serif; line-height: 1.6; color: #333; }}
+                    .container {{ max-width: 600px; margin: 0 auto; padding: 20px; }}
+                    .header {{ background-color: #ff4500; color: white; padding: 20px; text-align: center; }}
+                    .content {{ padding: 20px; background-color: #f9f9f9; }}
+                    .footer {{ padding: 20px; text-align: center; color: #666; font-size: 12px; }}
+                    .warning {{ background-color: #fff3cd; border: 1px solid #ffeaa7; padding: 15px; border-radius: 4px; margin: 15px 0; }}
+                </style>
+            </head>
+            <body>
+                <div class="container">
+                    <div class="header">
+                        <h1>New Sign-In Detected</h1>
+                    </div>
+                    <div class="content">
+                        <h2>Hi {}!</h2>
+                        <p>Your Reddit Clone account was just signed in to from a device or location we haven't seen before.</p>
+                        <ul>
+                            <li><strong>Device:</strong> {}</li>
+                            <li><strong>IP address:</strong> {}</li>
+                            <li><strong>Time:</strong> {}</li>
+                        </ul>
+                        <div class="warning">
+                            <strong>Wasn't you?</strong> Change your password and sign out of your other sessions from your account settings right away.
+                        </div>
+                        <p>If this was you, you can safely ignore this email.</p>
+                    </div>
+                    <div class="footer">
+                        <p>© 2024 Reddit Clone. All rights reserved.</p>
+                    </div>
+                </div>
+            </body>
+            </html>
+            "#,
+            username, device, ip_address, signed_in_at
+        );
+
+        let text_content = format!(
+            r#"
+            Hi {}!
+
+            Your Reddit Clone account was just signed in to from a device or location we haven't seen before.
+
+            Device: {}
+            IP address: {}
+            Time: {}
+
+            Wasn't you? Change your password and sign out of your other sessions from your account settings right away.
+
+            If this was you, you can safely ignore this email.
+
+            © 2024 Reddit Clone. All rights reserved.
+            "#,
+            username, device, ip_address, signed_in_at
+        );
+
+        self.send_email(
+            to_email,
+            Some(username),
+            subject,
+            &html_content,
+            &text_content,
+        )
+        .await
+    }
+
+    pub async fn send_email_change_verification(
+        &self,
+        to_email: &str,
+        username: &str,
+        confirmation_token: &str,
+        base_url: &str,
+    ) -> Result<()> {
+        let confirmation_url = format!(
+            "{}/confirm-email-change?token={}",
+            base_url, confirmation_token
+        );
+
+        let subject = "Confirm your new email address";
+        let html_content = format!(
+            r#"
+            <!DOCTYPE html>
+            <html>
+            <head>
+                <meta charset="utf-8">
+                <title>Confirm Email Change</title>
+                <style>
+                    body {{ font-family: Arial, sans-serif; line-height: 1.6; color: #333; }}
+                    .container {{ max-width: 600px; margin: 0 auto; padding: 20px; }}
+                    .header {{ background-color: #ff4500; color: white; padding: 20px; text-align: center; }}
+                    .content {{ padding: 20px; background-color: #f9f9f9; }}
+                    .button {{ display: inline-block; padding: 12px 24px; background-color: #ff4500; color: white; text-decoration: none; border-radius: 4px; margin: 20px 0; }}
+                    .footer {{ padding: 20px; text-align: center; color: #666; font-size: 12px; }}
+                </style>
+            </head>
+            <body>
+                <div class="container">
+                    <div class="header">
+                        <h1>Confirm Your New Email</h1>
+                    </div>
+                    <div class="content">
+                        <h2>Hi {}!</h2>
+                        <p>You asked to change the email address on your Reddit Clone account to this one.</p>
+                        <p>Click the button below to confirm the change:</p>
+                        <a href="{}" class="button">Confirm Email Address</a>
+                        <p>Or copy and paste this link into your browser:</p>
+                        <p><a href="{}">{}</a></p>
+                        <p>This link will expire in 24 hours. Your current email address stays active until you confirm.</p>
+                        <p>If you didn't request this change, you can safely ignore this email.</p>
+                    </div>
+                    <div class="footer">
+                        <p>© 2024 Reddit Clone. All rights reserved.</p>
+                    </div>
+                </div>
+            </body>
+            </html>
+            "#,
+            username, confirmation_url, confirmation_url, confirmation_url
+        );
+
+        let text_content = format!(
+            r#"
+            Hi {}!
+
+            You asked to change the email address on your Reddit Clone account to this one.
+
+            Click this link to confirm the change: {}
+
+            This link will expire in 24 hours. Your current email address stays active until you confirm.
+
+            If you didn't request this change, you can safely ignore this email.
+
+            © 2024 Reddit Clone. All rights reserved.
+            "#,
+            username, confirmation_url
+        );
+
+        self.send_email(
+            to_email,
+            Some(username),
+            subject,
+            &html_content,
+            &text_content,
+        )
+        .await
+    }
+
+    pub async fn send_email_changed_notice(
+        &self,
+        to_email: &str,
+        username: &str,
+        new_email: &str,
+    ) -> Result<()> {
+        let subject = "Your email address was changed";
+        let html_content = format!(
+            r#"
+            <!DOCTYPE html>
+            <html>
+            <head>
+                <meta charset="utf-8">
+                <title>Email Address Changed</title>
+                <style>
+                    body {{ font-family: Arial, sans-serif; line-height: 1.6; color: #333; }}
+                    .container {{ max-width: 600px; margin: 0 auto; padding: 20px; }}
+                    .header {{ background-color: #ff4500; color: white; padding: 20px; text-align: center; }}
+                    .content {{ padding: 20px; background-color: #f9f9f9; }}
+                    .footer {{ padding: 20px; text-align: center; color: #666; font-size: 12px; }}
+                    .warning {{ background-color: #fff3cd; border: 1px solid #ffeaa7; padding: 15px; border-radius: 4px; margin: 15px 0; }}
+                </style>
+            </head>
+            <body>
+                <div class="container">
+                    <div class="header">
+                        <h1>Email Address Changed</h1>
+                    </div>
+                    <div class="content">
+                        <h2>Hi {}!</h2>
+                        <p>The email address on your Reddit Clone account was changed to <strong>{}</strong>. We won't send account emails to this address any more.</p>
+                        <div class="warning">
+                            <strong>Wasn't you?</strong> Someone may have access to your account. Contact support right away.
+                        </div>
+                    </div>
+                    <div class="footer">
+                        <p>© 2024 Reddit Clone. All rights reserved.</p>
+                    </div>
+                </div>
+            </body>
+            </html>
+            "#,
+            username, new_email
+        );
+
+        let text_content = format!(
+            r#"
+            Hi {}!
+
+            The email address on your Reddit Clone account was changed to {}. We won't send account emails to this address any more.
+
+            Wasn't you? Someone may have access to your account. Contact support right away.
+
+            © 2024 Reddit Clone. All rights reserved.
+            "#,
+            username, new_email
+        );
+
+        self.send_email(
+            to_email,
+            Some(username),
+            subject,
+            &html_content,
+            &text_content,
+        )
+        .await
+    }
+
+    pub async fn send_account_deletion_scheduled_email(
+        &self,
+        to_email: &str,
+        username: &str,
+        deletion_date: &str,
+    ) -> Result<()> {
+        let subject = "Your account is scheduled for deletion";
+        let html_content = format!(
+            r#"
+            <!DOCTYPE html>
+            <html>
+            <head>
+                <meta charset="utf-8">
+                <title>Account Deletion Scheduled</title>
+                <style>
+                    body {{ font-family: Arial, sans-serif; line-height: 1.6; color: #333; }}
+                    .container {{ max-width: 600px; margin: 0 auto; padding: 20px; }}
+                    .header {{ background-color: #ff4500; color: white; padding: 20px; text-align: center; }}
+                    .content {{ padding: 20px; background-color: #f9f9f9; }}
+                    .footer {{ padding: 20px; text-align: center; color: #666; font-size: 12px; }}
+                    .warning {{ background-color: #fff3cd; border: 1px solid #ffeaa7; padding: 15px; border-radius: 4px; margin: 15px 0; }}
+                </style>
+            </head>
+            <body>
+                <div class="container">
+                    <div class="header">
+                        <h1>Account Deletion Scheduled</h1>
+                    </div>
+                    <div class="content">
+                        <h2>Hi {}!</h2>
+                        <p>Your Reddit Clone account will be permanently deleted on <strong>{}</strong>. You have been signed out everywhere.</p>
+                        <p>After that your personal information and uploads are removed, and your posts and comments will be shown as written by [deleted].</p>
+                        <div class="warning">
+                            <strong>Changed your mind?</strong> Just sign in again before that date and the deletion will be cancelled.
+                        </div>
+                    </div>
+                    <div class="footer">
+                        <p>© 2024 Reddit Clone. All rights reserved.</p>
+                    </div>
+                </div>
+            </body>
+            </html>
+            "#,
+            username, deletion_date
+        );
+
+        let text_content = format!(
+            r#"
+            Hi {}!
+
+            Your Reddit Clone account will be permanently deleted on {}. You have been signed out everywhere.
+
+            After that your personal information and uploads are removed, and your posts and comments will be shown as written by [deleted].
+
+            Changed your mind? Just sign in again before that date and the deletion will be cancelled.
+
+            © 2024 Reddit Clone. All rights reserved.
+            "#,
+            username, deletion_date
+        );
+
+        self.send_email(
+            to_email,
+            Some(username),
+            subject,
+            &html_content,
+            &text_content,
+        )
+        .await
+    }
+
+    pub async fn send_suspension_lifted_email(&self, to_email: &str, username: &str) -> Result<()> {
+        let subject = "Your account has been reinstated";
+        let html_content = format!(
+            r#"
+            <!DOCTYPE html>
+            <html>
+            <head>
+                <meta charset="utf-8">
+                <title>Account Reinstated</title>
+                <style>
+                    body {{ font-family: Arial, sans-serif; line-height: 1.6; color: #333; }}
+                    .container {{ max-width: 600px; margin: 0 auto; padding: 20px; }}
+                    .header {{ background-color: #ff4500; color: white; padding: 20px; text-align: center; }}
+                    .content {{ padding: 20px; background-color: #f9f9f9; }}
+                    .footer {{ padding: 20px; text-align: center; color: #666; font-size: 12px; }}
+                </style>
+            </head>
+            <body>
+                <div class="container">
+                    <div class="header">
+                        <h1>Account Reinstated</h1>
+                    </div>
+                    <div class="content">
+                        <h2>Hi {}!</h2>
+                        <p>The suspension on your Reddit Clone account has ended. You can sign in and take part again.</p>
+                        <p>Please make sure to follow the site rules and community guidelines going forward.</p>
+                    </div>
+                    <div class="footer">
+                        <p>© 2024 Reddit Clone. All rights reserved.</p>
+                    </div>
+                </div>
+            </body>
+            </html>
+            "#,
+            username
+        );
+
+        let text_content = format!(
+            r#"
+            Hi {}!
+
+            The suspension on your Reddit Clone account has ended. You can sign in and take part again.
+
+            Please make sure to follow the site rules and community guidelines going forward.
+
+            © 2024 Reddit Clone. All rights reserved.
+            "#,
+            username
+        );
+
+        self.send_email(
+            to_email,
+            Some(username),
+            subject,
+            &html_content,
+            &text_content,
+        )
+        .await
+    }
+
     pub async fn send_welcome_email(&self, to_email: &str, username: &str) -> Result<()> {
         let subject = "Welcome to Reddit Clone!";
         let html_content = format!(
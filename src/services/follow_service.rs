@@ -0,0 +1,414 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    error::{AppError, Result},
+    models::NotificationType,
+    services::{notification_service::NotificationService, user_service},
+};
+
+/// A user in someone's follower or following list.
+#[derive(Debug, Serialize, FromRow)]
+pub struct FollowListUser {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub is_verified: bool,
+    pub followed_at: Option<DateTime<Utc>>,
+    /// This user and the list's owner follow each other
+    pub is_mutual: bool,
+}
+
+/// Someone waiting for approval to follow a user.
+#[derive(Debug, Serialize, FromRow)]
+pub struct FollowRequest {
+    pub requester_id: Uuid,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub is_verified: bool,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FollowOutcome {
+    Following,
+    /// The user approves followers; the follow waits until they do
+    Requested,
+}
+
+pub async fn list_followers(
+    db: &PgPool,
+    user_id: Uuid,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<FollowListUser>> {
+    let followers = sqlx::query_as::<_, FollowListUser>(
+        r#"
+        SELECT
+            u.id, u.username, u.display_name, u.avatar_url, u.is_verified,
+            f.created_at as followed_at,
+            EXISTS(
+                SELECT 1 FROM user_follows b
+                WHERE b.follower_id = $1 AND b.following_id = f.follower_id
+            ) as is_mutual
+        FROM user_follows f
+        JOIN users u ON f.follower_id = u.id
+        WHERE f.following_id = $1 AND u.status != 'deleted'
+        ORDER BY f.created_at DESC
+        LIMIT $2 OFFSET $3
+        "#,
+    )
+    .bind(user_id)
+    .bind(limit as i64)
+    .bind(offset as i64)
+    .fetch_all(db)
+    .await?;
+
+    Ok(followers)
+}
+
+pub async fn list_following(
+    db: &PgPool,
+    user_id: Uuid,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<FollowListUser>> {
+    let following = sqlx::query_as::<_, FollowListUser>(
+        r#"
+        SELECT
+            u.id, u.username, u.display_name, u.avatar_url, u.is_verified,
+            f.created_at as followed_at,
+            EXISTS(
+                SELECT 1 FROM user_follows b
+                WHERE b.follower_id = f.following_id AND b.following_id = $1
+            ) as is_mutual
+        FROM user_follows f
+        JOIN users u ON f.following_id = u.id
+        WHERE f.follower_id = $1 AND u.status != 'deleted'
+        ORDER BY f.created_at DESC
+        LIMIT $2 OFFSET $3
+        "#,
+    )
+    .bind(user_id)
+    .bind(limit as i64)
+    .bind(offset as i64)
+    .fetch_all(db)
+    .await?;
+
+    Ok(following)
+}
+
+pub async fn is_follow_requested(db: &PgPool, requester_id: Uuid, target_id: Uuid) -> Result<bool> {
+    let exists = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM follow_requests WHERE requester_id = $1 AND target_id = $2)",
+    )
+    .bind(requester_id)
+    .bind(target_id)
+    .fetch_one(db)
+    .await?;
+
+    Ok(exists)
+}
+
+/// Follow a user, or ask to if they approve their followers.
+pub async fn follow(state: &AppState, follower_id: Uuid, target_id: Uuid) -> Result<FollowOutcome> {
+    if follower_id == target_id {
+        return Err(AppError::BadRequest("Cannot follow yourself".to_string()));
+    }
+
+    let target = user_service::get_user_by_id(&state.db, target_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    // Blocking cuts both ways, as it does in `sever`
+    if user_service::is_blocked(&state.db, target.id, follower_id).await?
+        || user_service::is_blocked(&state.db, follower_id, target.id).await?
+    {
+        return Err(AppError::Authorization(
+            "Cannot follow this user".to_string(),
+        ));
+    }
+
+    if user_service::is_following(&state.db, follower_id, target.id).await? {
+        return Err(AppError::Conflict(
+            "Already following this user".to_string(),
+        ));
+    }
+
+    let requires_approval = sqlx::query_scalar::<_, bool>(
+        "SELECT require_follow_approval FROM user_preferences WHERE user_id = $1",
+    )
+    .bind(target.id)
+    .fetch_optional(&state.db)
+    .await?
+    .unwrap_or(false);
+
+    let follower = user_service::get_user_by_id(&state.db, follower_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    if requires_approval {
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO follow_requests (requester_id, target_id) VALUES ($1, $2)
+            ON CONFLICT (requester_id, target_id) DO NOTHING
+            "#,
+        )
+        .bind(follower_id)
+        .bind(target.id)
+        .execute(&state.db)
+        .await?;
+
+        if inserted.rows_affected() == 0 {
+            return Err(AppError::Conflict(
+                "Follow request already sent".to_string(),
+            ));
+        }
+
+        notify(
+            state,
+            target.id,
+            follower_id,
+            "New follow request",
+            format!("{} wants to follow you", follower.username),
+        )
+        .await;
+
+        return Ok(FollowOutcome::Requested);
+    }
+
+    let inserted = sqlx::query(
+        r#"
+        INSERT INTO user_follows (follower_id, following_id) VALUES ($1, $2)
+        ON CONFLICT (follower_id, following_id) DO NOTHING
+        "#,
+    )
+    .bind(follower_id)
+    .bind(target.id)
+    .execute(&state.db)
+    .await?;
+
+    if inserted.rows_affected() == 0 {
+        return Err(AppError::Conflict(
+            "Already following this user".to_string(),
+        ));
+    }
+
+    notify(
+        state,
+        target.id,
+        follower_id,
+        "New follower",
+        format!("{} started following you", follower.username),
+    )
+    .await;
+
+    Ok(FollowOutcome::Following)
+}
+
+/// Stop following a user, or withdraw a pending request to. Returns false if
+/// there was neither.
+pub async fn unfollow(db: &PgPool, follower_id: Uuid, target_id: Uuid) -> Result<bool> {
+    let unfollowed =
+        sqlx::query("DELETE FROM user_follows WHERE follower_id = $1 AND following_id = $2")
+            .bind(follower_id)
+            .bind(target_id)
+            .execute(db)
+            .await?;
+
+    if unfollowed.rows_affected() > 0 {
+        return Ok(true);
+    }
+
+    let withdrawn =
+        sqlx::query("DELETE FROM follow_requests WHERE requester_id = $1 AND target_id = $2")
+            .bind(follower_id)
+            .bind(target_id)
+            .execute(db)
+            .await?;
+
+    Ok(withdrawn.rows_affected() > 0)
+}
+
+/// Requests waiting on the user's approval, newest first.
+pub async fn list_follow_requests(
+    db: &PgPool,
+    user_id: Uuid,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<FollowRequest>> {
+    let requests = sqlx::query_as::<_, FollowRequest>(
+        r#"
+        SELECT
+            r.requester_id, u.username, u.display_name, u.avatar_url, u.is_verified,
+            r.created_at
+        FROM follow_requests r
+        JOIN users u ON r.requester_id = u.id
+        WHERE r.target_id = $1 AND u.status != 'deleted'
+        ORDER BY r.created_at DESC
+        LIMIT $2 OFFSET $3
+        "#,
+    )
+    .bind(user_id)
+    .bind(limit as i64)
+    .bind(offset as i64)
+    .fetch_all(db)
+    .await?;
+
+    Ok(requests)
+}
+
+/// Turn a pending request into a follow. Returns false if there was no such
+/// request.
+pub async fn approve_follow_request(
+    state: &AppState,
+    user_id: Uuid,
+    requester_id: Uuid,
+) -> Result<bool> {
+    let mut tx = state.db.begin().await?;
+
+    let request = sqlx::query(
+        "DELETE FROM follow_requests WHERE target_id = $1 AND requester_id = $2 RETURNING id",
+    )
+    .bind(user_id)
+    .bind(requester_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if request.is_none() {
+        return Ok(false);
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO user_follows (follower_id, following_id) VALUES ($1, $2)
+        ON CONFLICT (follower_id, following_id) DO NOTHING
+        "#,
+    )
+    .bind(requester_id)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    if let Some(user) = user_service::get_user_by_id(&state.db, user_id).await? {
+        notify(
+            state,
+            requester_id,
+            user_id,
+            "Follow request approved",
+            format!("{} approved your follow request", user.username),
+        )
+        .await;
+    }
+
+    Ok(true)
+}
+
+/// Turn down a pending request. Returns false if there was no such request.
+pub async fn reject_follow_request(db: &PgPool, user_id: Uuid, requester_id: Uuid) -> Result<bool> {
+    let rejected =
+        sqlx::query("DELETE FROM follow_requests WHERE target_id = $1 AND requester_id = $2")
+            .bind(user_id)
+            .bind(requester_id)
+            .execute(db)
+            .await?;
+
+    Ok(rejected.rows_affected() > 0)
+}
+
+/// Let everyone still waiting follow the user, for when they stop approving
+/// followers.
+pub async fn approve_all_follow_requests(db: &PgPool, user_id: Uuid) -> Result<()> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO user_follows (follower_id, following_id)
+        SELECT requester_id, target_id FROM follow_requests WHERE target_id = $1
+        ON CONFLICT (follower_id, following_id) DO NOTHING
+        "#,
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM follow_requests WHERE target_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Drop follows and follow requests between two users in either direction,
+/// for when one blocks the other.
+pub async fn sever(db: &PgPool, user_id: Uuid, other_id: Uuid) -> Result<()> {
+    sqlx::query(
+        "DELETE FROM user_follows WHERE (follower_id = $1 AND following_id = $2) OR (follower_id = $2 AND following_id = $1)",
+    )
+    .bind(user_id)
+    .bind(other_id)
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        "DELETE FROM follow_requests WHERE (requester_id = $1 AND target_id = $2) OR (requester_id = $2 AND target_id = $1)",
+    )
+    .bind(user_id)
+    .bind(other_id)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Tell a user about a follow. Failures are only logged; the follow itself
+/// has already happened.
+async fn notify(
+    state: &AppState,
+    recipient_id: Uuid,
+    sender_id: Uuid,
+    title: &str,
+    content: String,
+) {
+    let notification_service = NotificationService::new(
+        state.db.clone(),
+        state.redis.clone(),
+        state.email_service.clone(),
+        state.sms_service.clone(),
+    );
+
+    let notified = notification_service
+        .create_notification(
+            recipient_id,
+            Some(sender_id),
+            NotificationType::Follow,
+            title.to_string(),
+            Some(content),
+            None,
+            None,
+            None,
+        )
+        .await;
+
+    match notified {
+        // Turned off by the recipient
+        Ok(_) | Err(AppError::BadRequest(_)) => {}
+        Err(e) => tracing::warn!(
+            "Failed to notify user {} of follow by {}: {}",
+            recipient_id,
+            sender_id,
+            e
+        ),
+    }
+}
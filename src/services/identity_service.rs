@@ -0,0 +1,290 @@
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, Result},
+    models::{AuthProvider, User, UserIdentity, UserStatus},
+    services::{auth_service, invite_service},
+};
+
+/// What an external provider told us about the person signing in.
+#[derive(Debug, Clone)]
+pub struct ExternalProfile {
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+/// Find the account a provider identity is linked to, recording the sign-in.
+pub async fn find_user_by_identity(
+    db: &PgPool,
+    provider: &str,
+    subject: &str,
+) -> Result<Option<User>> {
+    let user = sqlx::query_as::<_, User>(
+        r#"
+        SELECT u.* FROM users u
+        JOIN user_identities i ON i.user_id = u.id
+        WHERE i.provider = $1 AND i.subject = $2 AND u.status != 'deleted'
+        "#,
+    )
+    .bind(provider)
+    .bind(subject)
+    .fetch_optional(db)
+    .await?;
+
+    if user.is_some() {
+        sqlx::query(
+            "UPDATE user_identities SET last_used_at = NOW() WHERE provider = $1 AND subject = $2",
+        )
+        .bind(provider)
+        .bind(subject)
+        .execute(db)
+        .await?;
+    }
+
+    Ok(user)
+}
+
+fn username_base(profile: &ExternalProfile) -> String {
+    let source = profile
+        .display_name
+        .as_deref()
+        .or_else(|| profile.email.as_deref().and_then(|e| e.split('@').next()))
+        .unwrap_or_default();
+
+    let base: String = source
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .take(20)
+        .collect();
+
+    if base.is_empty() {
+        "user".to_string()
+    } else {
+        base
+    }
+}
+
+/// Create a new account for someone signing in with a provider for the first
+/// time, redeeming `invite_code` if one was given.
+pub async fn create_user_with_identity(
+    db: &PgPool,
+    auth_provider: AuthProvider,
+    profile: &ExternalProfile,
+    invite_code: Option<&str>,
+) -> Result<User> {
+    // Never attach a provider to an existing account by email alone; the owner
+    // has to sign in and link it themselves
+    if let Some(email) = &profile.email {
+        let email_taken =
+            sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM users WHERE email = $1)")
+                .bind(email)
+                .fetch_one(db)
+                .await?;
+
+        if email_taken {
+            return Err(AppError::Conflict(
+                "An account with this email already exists. Sign in and link this provider from your account settings".to_string(),
+            ));
+        }
+    }
+
+    let username = auth_service::generate_unique_username(db, &username_base(profile)).await?;
+    let user_id = Uuid::new_v4();
+    let now = chrono::Utc::now();
+
+    let mut tx = db.begin().await?;
+
+    let user = sqlx::query_as::<_, User>(
+        r#"
+        INSERT INTO users (
+            id, username, email, display_name, avatar_url,
+            auth_provider, email_verified, status,
+            created_at, updated_at, last_login_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        RETURNING *
+        "#,
+    )
+    .bind(user_id)
+    .bind(&username)
+    .bind(&profile.email)
+    .bind(&profile.display_name)
+    .bind(&profile.avatar_url)
+    .bind(auth_provider)
+    .bind(profile.email_verified)
+    .bind(UserStatus::Active)
+    .bind(now)
+    .bind(now)
+    .bind(now)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO user_preferences (
+            id, user_id, email_notifications, push_notifications,
+            comment_reply_notifications, post_reply_notifications,
+            mention_notifications, upvote_notifications,
+            community_notifications, nsfw_content,
+            created_at, updated_at
+        )
+        VALUES ($1, $2, true, true, true, true, true, false, true, false, $3, $4)
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(now)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO user_identities (user_id, provider, subject, email, last_used_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(user_id)
+    .bind(&profile.provider)
+    .bind(&profile.subject)
+    .bind(&profile.email)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+
+    if let Some(invite_code) = invite_code.filter(|code| !code.trim().is_empty()) {
+        invite_service::redeem(&mut tx, invite_code, user_id).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(user)
+}
+
+/// Link a provider identity to an existing account.
+pub async fn link_identity(
+    db: &PgPool,
+    user_id: Uuid,
+    profile: &ExternalProfile,
+) -> Result<UserIdentity> {
+    let identity = sqlx::query_as::<_, UserIdentity>(
+        r#"
+        INSERT INTO user_identities (user_id, provider, subject, email)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (provider, subject) DO NOTHING
+        RETURNING *
+        "#,
+    )
+    .bind(user_id)
+    .bind(&profile.provider)
+    .bind(&profile.subject)
+    .bind(&profile.email)
+    .fetch_optional(db)
+    .await?;
+
+    if let Some(identity) = identity {
+        return Ok(identity);
+    }
+
+    let owner = sqlx::query_scalar::<_, Uuid>(
+        "SELECT user_id FROM user_identities WHERE provider = $1 AND subject = $2",
+    )
+    .bind(&profile.provider)
+    .bind(&profile.subject)
+    .fetch_one(db)
+    .await?;
+
+    if owner == user_id {
+        Err(AppError::Conflict(
+            "This account is already linked".to_string(),
+        ))
+    } else {
+        Err(AppError::Conflict(
+            "This account is already linked to another user".to_string(),
+        ))
+    }
+}
+
+pub async fn list_identities(db: &PgPool, user_id: Uuid) -> Result<Vec<UserIdentity>> {
+    let identities = sqlx::query_as::<_, UserIdentity>(
+        "SELECT * FROM user_identities WHERE user_id = $1 ORDER BY created_at",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(identities)
+}
+
+/// Count the ways a user can still sign in: a password, linked identities,
+/// passkeys, a verified email (magic links) and a phone usable for SMS codes.
+/// Locks the user row so concurrent removals can't both see the same count;
+/// call it inside the transaction that removes the credential.
+pub async fn lock_sign_in_methods(conn: &mut PgConnection, user_id: Uuid) -> Result<i64> {
+    let count = sqlx::query_scalar::<_, i64>(
+        r#"
+        WITH locked AS (
+            SELECT * FROM users WHERE id = $1 FOR UPDATE
+        )
+        SELECT
+            (CASE WHEN password_hash IS NOT NULL THEN 1 ELSE 0 END)
+            + (CASE WHEN email IS NOT NULL AND email_verified THEN 1 ELSE 0 END)
+            + (CASE WHEN phone IS NOT NULL AND (auth_provider = 'phone' OR phone_verified) THEN 1 ELSE 0 END)
+            + (SELECT COUNT(*) FROM user_identities WHERE user_id = $1)
+            + (SELECT COUNT(*) FROM user_passkeys WHERE user_id = $1)
+        FROM locked
+        "#,
+    )
+    .bind(user_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    Ok(count)
+}
+
+pub fn last_sign_in_method_error() -> AppError {
+    AppError::BadRequest(
+        "This is your only way to sign in. Add another sign-in method before removing it"
+            .to_string(),
+    )
+}
+
+/// Unlink a provider identity. Returns false if the user has no such identity;
+/// refuses to remove the account's last sign-in method.
+pub async fn unlink_identity(db: &PgPool, user_id: Uuid, identity_id: Uuid) -> Result<bool> {
+    let mut tx = db.begin().await?;
+
+    let methods = lock_sign_in_methods(&mut tx, user_id).await?;
+
+    let owned = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM user_identities WHERE id = $1 AND user_id = $2)",
+    )
+    .bind(identity_id)
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if !owned {
+        return Ok(false);
+    }
+
+    if methods <= 1 {
+        return Err(last_sign_in_method_error());
+    }
+
+    sqlx::query("DELETE FROM user_identities WHERE id = $1")
+        .bind(identity_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(true)
+}
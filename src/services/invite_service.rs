@@ -0,0 +1,318 @@
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use serde::Serialize;
+use sqlx::{FromRow, PgConnection, PgPool};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::{
+    config::{Config, RegistrationMode},
+    error::{AppError, Result},
+};
+
+/// Longest a regular user's invite code can stay valid.
+const USER_INVITE_MAX_DAYS: i64 = 30;
+
+const CODE_LENGTH: usize = 12;
+// No 0/O or 1/I, so codes survive being read out or retyped
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct InviteCode {
+    pub id: Uuid,
+    pub code: String,
+    pub created_by: Uuid,
+    pub created_by_username: String,
+    pub note: Option<String>,
+    pub max_uses: Option<i32>,
+    pub use_count: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An account registered with an invite code.
+#[derive(Debug, Serialize, FromRow)]
+pub struct InviteRedemption {
+    #[serde(skip)]
+    pub invite_code_id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An invite code with the accounts registered with it.
+#[derive(Debug, Serialize)]
+pub struct InviteWithRedemptions {
+    #[serde(flatten)]
+    pub invite: InviteCode,
+    pub redemptions: Vec<InviteRedemption>,
+}
+
+#[derive(Debug, Default)]
+pub struct NewInvite<'a> {
+    pub note: Option<&'a str>,
+    /// `None` for unlimited uses
+    pub max_uses: Option<i32>,
+    /// `None` for a code that doesn't expire
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+const INVITE_COLUMNS: &str = r#"
+    i.id, i.code, i.created_by, u.username AS created_by_username, i.note,
+    i.max_uses, i.use_count, i.expires_at, i.revoked_at, i.created_at
+"#;
+
+fn generate_code() -> String {
+    let mut rng = rand::rng();
+    (0..CODE_LENGTH)
+        .map(|_| CODE_ALPHABET[rng.random_range(0..CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+fn normalize_code(code: &str) -> String {
+    code.trim().to_uppercase()
+}
+
+/// Reject a new account the registration mode doesn't allow. Codes are only
+/// checked for presence here; `redeem` checks them when the account is created.
+pub fn ensure_registration_allowed(config: &Config, invite_code: Option<&str>) -> Result<()> {
+    let has_code = invite_code.is_some_and(|code| !code.trim().is_empty());
+
+    match config.registration_mode {
+        RegistrationMode::Open => Ok(()),
+        RegistrationMode::InviteOnly if has_code => Ok(()),
+        RegistrationMode::InviteOnly => Err(AppError::Authorization(
+            "An invite code is required to register".to_string(),
+        )),
+        RegistrationMode::Closed => Err(AppError::Authorization(
+            "Registration is closed".to_string(),
+        )),
+    }
+}
+
+/// Use up one use of an invite code for a new account. Runs on the caller's
+/// connection so a bad code rolls back the account it was meant for.
+pub async fn redeem(conn: &mut PgConnection, code: &str, user_id: Uuid) -> Result<()> {
+    let invite_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE invite_codes i SET use_count = use_count + 1
+        FROM users u
+        WHERE i.code = $1 AND u.id = i.created_by AND u.status != 'deleted'
+            AND i.revoked_at IS NULL
+            AND (i.expires_at IS NULL OR i.expires_at > NOW())
+            AND (i.max_uses IS NULL OR i.use_count < i.max_uses)
+        RETURNING i.id
+        "#,
+    )
+    .bind(normalize_code(code))
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| AppError::BadRequest("Invalid or expired invite code".to_string()))?;
+
+    sqlx::query("INSERT INTO invite_redemptions (invite_code_id, user_id) VALUES ($1, $2)")
+        .bind(invite_id)
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Store a new invite code. No limits are applied; see `create_user_invite`
+/// for the ones regular users get.
+pub async fn insert_invite(
+    conn: &mut PgConnection,
+    created_by: Uuid,
+    invite: &NewInvite<'_>,
+) -> Result<InviteCode> {
+    if invite
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        return Err(AppError::Validation(
+            "Expiry must be in the future".to_string(),
+        ));
+    }
+
+    let invite_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        INSERT INTO invite_codes (code, created_by, note, max_uses, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+    )
+    .bind(generate_code())
+    .bind(created_by)
+    .bind(invite.note.map(str::trim).filter(|note| !note.is_empty()))
+    .bind(invite.max_uses)
+    .bind(invite.expires_at)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let invite = sqlx::query_as::<_, InviteCode>(&format!(
+        "SELECT {} FROM invite_codes i JOIN users u ON u.id = i.created_by WHERE i.id = $1",
+        INVITE_COLUMNS
+    ))
+    .bind(invite_id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(invite)
+}
+
+/// Create an invite code for a regular user: single use, expiring within
+/// `USER_INVITE_MAX_DAYS`, and only while they have fewer than the configured
+/// number of unused codes.
+pub async fn create_user_invite(
+    db: &PgPool,
+    config: &Config,
+    user_id: Uuid,
+    note: Option<&str>,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<InviteCode> {
+    if config.user_invite_limit <= 0 {
+        return Err(AppError::Authorization(
+            "Only staff can create invite codes".to_string(),
+        ));
+    }
+
+    let latest_expiry = Utc::now() + Duration::days(USER_INVITE_MAX_DAYS);
+    if expires_at.is_some_and(|expires_at| expires_at > latest_expiry) {
+        return Err(AppError::Validation(format!(
+            "Invite codes can be valid for at most {} days",
+            USER_INVITE_MAX_DAYS
+        )));
+    }
+
+    let mut tx = db.begin().await?;
+
+    // Serialises concurrent requests from the same user so the limit holds
+    sqlx::query("SELECT id FROM users WHERE id = $1 FOR UPDATE")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let outstanding = sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT COUNT(*) FROM invite_codes
+        WHERE created_by = $1 AND revoked_at IS NULL
+            AND (expires_at IS NULL OR expires_at > NOW())
+            AND (max_uses IS NULL OR use_count < max_uses)
+        "#,
+    )
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if outstanding >= config.user_invite_limit {
+        return Err(AppError::BadRequest(format!(
+            "You can have at most {} unused invite codes",
+            config.user_invite_limit
+        )));
+    }
+
+    let invite = insert_invite(
+        &mut tx,
+        user_id,
+        &NewInvite {
+            note,
+            max_uses: Some(1),
+            expires_at: Some(expires_at.unwrap_or(latest_expiry)),
+        },
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(invite)
+}
+
+/// Invite codes, newest first, with who registered with each. `created_by`
+/// limits the list to one user's codes.
+pub async fn list_invites(
+    db: &PgPool,
+    created_by: Option<Uuid>,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<InviteWithRedemptions>> {
+    let invites = sqlx::query_as::<_, InviteCode>(&format!(
+        r#"
+        SELECT {}
+        FROM invite_codes i
+        JOIN users u ON u.id = i.created_by
+        WHERE ($1::uuid IS NULL OR i.created_by = $1)
+        ORDER BY i.created_at DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        INVITE_COLUMNS
+    ))
+    .bind(created_by)
+    .bind(limit as i64)
+    .bind(offset as i64)
+    .fetch_all(db)
+    .await?;
+
+    let invite_ids: Vec<Uuid> = invites.iter().map(|invite| invite.id).collect();
+
+    let redemptions = sqlx::query_as::<_, InviteRedemption>(
+        r#"
+        SELECT r.invite_code_id, r.user_id, u.username, r.created_at
+        FROM invite_redemptions r
+        JOIN users u ON u.id = r.user_id
+        WHERE r.invite_code_id = ANY($1)
+        ORDER BY r.created_at
+        "#,
+    )
+    .bind(&invite_ids)
+    .fetch_all(db)
+    .await?;
+
+    let mut redemptions_by_invite: HashMap<Uuid, Vec<InviteRedemption>> = HashMap::new();
+    for redemption in redemptions {
+        redemptions_by_invite
+            .entry(redemption.invite_code_id)
+            .or_default()
+            .push(redemption);
+    }
+
+    Ok(invites
+        .into_iter()
+        .map(|invite| InviteWithRedemptions {
+            redemptions: redemptions_by_invite.remove(&invite.id).unwrap_or_default(),
+            invite,
+        })
+        .collect())
+}
+
+/// Stop an invite code from being used. `created_by` restricts it to one
+/// user's codes. Returns the code, or `None` if there was no such unrevoked
+/// code.
+pub async fn revoke_invite(
+    conn: &mut PgConnection,
+    invite_id: Uuid,
+    created_by: Option<Uuid>,
+) -> Result<Option<String>> {
+    let code = sqlx::query_scalar::<_, String>(
+        r#"
+        UPDATE invite_codes SET revoked_at = NOW()
+        WHERE id = $1 AND ($2::uuid IS NULL OR created_by = $2) AND revoked_at IS NULL
+        RETURNING code
+        "#,
+    )
+    .bind(invite_id)
+    .bind(created_by)
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(code)
+}
+
+/// Revoke one of the user's own invite codes. Returns false if they have no
+/// such unrevoked code.
+pub async fn revoke_user_invite(db: &PgPool, user_id: Uuid, invite_id: Uuid) -> Result<bool> {
+    let mut conn = db.acquire().await?;
+    let code = revoke_invite(&mut conn, invite_id, Some(user_id)).await?;
+
+    Ok(code.is_some())
+}
@@ -0,0 +1,315 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, Result},
+    models::KarmaInterval,
+    services::post_service::USER_POSTS_VISIBILITY,
+};
+
+/// Reasons the `update_user_karma` trigger records start with `post_` or
+/// `comment_`; so do the adjustments made by `reconcile_user`.
+const POST_KARMA: &str = "h.reason LIKE 'post\\_%'";
+const COMMENT_KARMA: &str = "h.reason LIKE 'comment\\_%'";
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct KarmaBreakdown {
+    pub post_karma: i64,
+    pub comment_karma: i64,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct CommunityKarma {
+    pub community_id: Uuid,
+    pub name: String,
+    pub display_name: String,
+    pub icon_url: Option<String>,
+    pub post_karma: i64,
+    pub comment_karma: i64,
+    pub total: i64,
+}
+
+/// Karma gained or lost within one interval.
+#[derive(Debug, Serialize, FromRow)]
+pub struct KarmaBucket {
+    pub bucket_start: DateTime<Utc>,
+    pub post_karma: i64,
+    pub comment_karma: i64,
+    pub total: i64,
+}
+
+impl KarmaInterval {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            KarmaInterval::Day => "day",
+            KarmaInterval::Week => "week",
+            KarmaInterval::Month => "month",
+        }
+    }
+
+    /// How far back a history goes when no start is given
+    fn default_span(&self) -> Duration {
+        match self {
+            KarmaInterval::Day => Duration::days(30),
+            KarmaInterval::Week => Duration::weeks(26),
+            KarmaInterval::Month => Duration::days(365),
+        }
+    }
+
+    /// Longest history one request can ask for, to bound the number of buckets
+    fn max_span(&self) -> Duration {
+        match self {
+            KarmaInterval::Day => Duration::days(366),
+            KarmaInterval::Week => Duration::weeks(260),
+            KarmaInterval::Month => Duration::days(3660),
+        }
+    }
+}
+
+/// The user's karma split between posts and comments.
+pub async fn get_breakdown(db: &PgPool, user_id: Uuid) -> Result<KarmaBreakdown> {
+    let breakdown = sqlx::query_as::<_, KarmaBreakdown>(&format!(
+        r#"
+        SELECT
+            COALESCE(SUM(h.karma_change) FILTER (WHERE {}), 0)::bigint as post_karma,
+            COALESCE(SUM(h.karma_change) FILTER (WHERE {}), 0)::bigint as comment_karma
+        FROM user_karma_history h
+        WHERE h.user_id = $1
+        "#,
+        POST_KARMA, COMMENT_KARMA
+    ))
+    .bind(user_id)
+    .fetch_one(db)
+    .await?;
+
+    Ok(breakdown)
+}
+
+/// Where the user's karma came from, highest first. Karma from private
+/// communities is only shown to their members and the user themselves.
+pub async fn get_community_breakdown(
+    db: &PgPool,
+    user_id: Uuid,
+    viewer_id: Option<Uuid>,
+    limit: u32,
+) -> Result<Vec<CommunityKarma>> {
+    let communities = sqlx::query_as::<_, CommunityKarma>(&format!(
+        r#"
+        SELECT
+            c.id as community_id, c.name, c.display_name, c.icon_url,
+            COALESCE(SUM(h.karma_change) FILTER (WHERE {}), 0)::bigint as post_karma,
+            COALESCE(SUM(h.karma_change) FILTER (WHERE {}), 0)::bigint as comment_karma,
+            SUM(h.karma_change)::bigint as total
+        FROM user_karma_history h
+        LEFT JOIN comments cm ON h.comment_id = cm.id
+        JOIN posts p ON p.id = COALESCE(h.post_id, cm.post_id)
+        JOIN communities c ON p.community_id = c.id
+        WHERE h.user_id = $2
+        {}
+        GROUP BY c.id
+        ORDER BY total DESC, c.name
+        LIMIT $3
+        "#,
+        POST_KARMA, COMMENT_KARMA, USER_POSTS_VISIBILITY
+    ))
+    .bind(viewer_id)
+    .bind(user_id)
+    .bind(limit as i64)
+    .fetch_all(db)
+    .await?;
+
+    Ok(communities)
+}
+
+/// Karma per day, week or month between `from` and `to`, including
+/// intervals where nothing changed. Buckets are in UTC.
+pub async fn get_history(
+    db: &PgPool,
+    user_id: Uuid,
+    interval: KarmaInterval,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<Vec<KarmaBucket>> {
+    let to = to.unwrap_or_else(Utc::now);
+    let from = from.unwrap_or(to - interval.default_span());
+
+    if from >= to {
+        return Err(AppError::Validation("from must be before to".to_string()));
+    }
+
+    if to - from > interval.max_span() {
+        return Err(AppError::Validation(format!(
+            "History by {} can cover at most {} days",
+            interval.as_sql(),
+            interval.max_span().num_days()
+        )));
+    }
+
+    let buckets = sqlx::query_as::<_, KarmaBucket>(&format!(
+        r#"
+        WITH buckets AS (
+            SELECT generate_series(
+                date_trunc($2, $3::timestamptz, 'UTC'),
+                $4::timestamptz,
+                ('1 ' || $2)::interval
+            ) as bucket_start
+        ),
+        changes AS (
+            SELECT
+                date_trunc($2, h.created_at, 'UTC') as bucket_start,
+                SUM(h.karma_change) FILTER (WHERE {}) as post_karma,
+                SUM(h.karma_change) FILTER (WHERE {}) as comment_karma,
+                SUM(h.karma_change) as total
+            FROM user_karma_history h
+            WHERE h.user_id = $1 AND h.created_at >= $3 AND h.created_at < $4
+            GROUP BY 1
+        )
+        SELECT
+            b.bucket_start,
+            COALESCE(c.post_karma, 0)::bigint as post_karma,
+            COALESCE(c.comment_karma, 0)::bigint as comment_karma,
+            COALESCE(c.total, 0)::bigint as total
+        FROM buckets b
+        LEFT JOIN changes c ON c.bucket_start = b.bucket_start
+        ORDER BY b.bucket_start
+        "#,
+        POST_KARMA, COMMENT_KARMA
+    ))
+    .bind(user_id)
+    .bind(interval.as_sql())
+    .bind(from)
+    .bind(to)
+    .fetch_all(db)
+    .await?;
+
+    Ok(buckets)
+}
+
+/// Users whose karma total or history doesn't add up to the votes on their
+/// posts and comments.
+pub async fn find_drifted_users(db: &PgPool, limit: i64) -> Result<Vec<Uuid>> {
+    let user_ids = sqlx::query_scalar::<_, Uuid>(&format!(
+        r#"
+        WITH post_karma AS (
+            SELECT p.author_id as user_id, SUM(pv.vote_type)::bigint as karma
+            FROM post_votes pv
+            JOIN posts p ON pv.post_id = p.id
+            GROUP BY p.author_id
+        ),
+        comment_karma AS (
+            SELECT c.author_id as user_id, SUM(cv.vote_type)::bigint as karma
+            FROM comment_votes cv
+            JOIN comments c ON cv.comment_id = c.id
+            GROUP BY c.author_id
+        ),
+        recorded AS (
+            SELECT
+                h.user_id,
+                COALESCE(SUM(h.karma_change) FILTER (WHERE {}), 0)::bigint as post_karma,
+                COALESCE(SUM(h.karma_change) FILTER (WHERE {}), 0)::bigint as comment_karma
+            FROM user_karma_history h
+            GROUP BY h.user_id
+        )
+        SELECT u.id
+        FROM users u
+        LEFT JOIN post_karma pk ON pk.user_id = u.id
+        LEFT JOIN comment_karma ck ON ck.user_id = u.id
+        LEFT JOIN recorded r ON r.user_id = u.id
+        WHERE u.status != 'deleted' AND (
+            u.karma_points != COALESCE(pk.karma, 0) + COALESCE(ck.karma, 0)
+            OR COALESCE(r.post_karma, 0) != COALESCE(pk.karma, 0)
+            OR COALESCE(r.comment_karma, 0) != COALESCE(ck.karma, 0)
+        )
+        LIMIT $1
+        "#,
+        POST_KARMA, COMMENT_KARMA
+    ))
+    .bind(limit)
+    .fetch_all(db)
+    .await?;
+
+    Ok(user_ids)
+}
+
+/// Recompute a user's karma from the votes on their posts and comments,
+/// recording any difference in their history so it keeps adding up. Returns
+/// the correction made to their total.
+///
+/// The user row is locked first. A vote trigger waiting on that lock hasn't
+/// committed its vote yet, so it isn't counted here and its own increment
+/// lands on top of the corrected total.
+pub async fn reconcile_user(db: &PgPool, user_id: Uuid) -> Result<i64> {
+    let mut tx = db.begin().await?;
+
+    let karma_points =
+        sqlx::query_scalar::<_, i32>("SELECT karma_points FROM users WHERE id = $1 FOR UPDATE")
+            .bind(user_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+    let Some(karma_points) = karma_points else {
+        return Ok(0);
+    };
+
+    let (post_karma, comment_karma) = sqlx::query_as::<_, (i64, i64)>(
+        r#"
+        SELECT
+            (SELECT COALESCE(SUM(pv.vote_type), 0)::bigint
+                FROM post_votes pv JOIN posts p ON pv.post_id = p.id
+                WHERE p.author_id = $1),
+            (SELECT COALESCE(SUM(cv.vote_type), 0)::bigint
+                FROM comment_votes cv JOIN comments c ON cv.comment_id = c.id
+                WHERE c.author_id = $1)
+        "#,
+    )
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let (recorded_post, recorded_comment) = sqlx::query_as::<_, (i64, i64)>(&format!(
+        r#"
+        SELECT
+            COALESCE(SUM(h.karma_change) FILTER (WHERE {}), 0)::bigint,
+            COALESCE(SUM(h.karma_change) FILTER (WHERE {}), 0)::bigint
+        FROM user_karma_history h
+        WHERE h.user_id = $1
+        "#,
+        POST_KARMA, COMMENT_KARMA
+    ))
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    for (reason, change) in [
+        ("post_reconciliation", post_karma - recorded_post),
+        ("comment_reconciliation", comment_karma - recorded_comment),
+    ] {
+        if change != 0 {
+            sqlx::query(
+                "INSERT INTO user_karma_history (user_id, karma_change, reason) VALUES ($1, $2, $3)",
+            )
+            .bind(user_id)
+            .bind(change as i32)
+            .bind(reason)
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    let total = post_karma + comment_karma;
+    let correction = total - karma_points as i64;
+
+    if correction != 0 {
+        sqlx::query("UPDATE users SET karma_points = $2 WHERE id = $1")
+            .bind(user_id)
+            .bind(total as i32)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(correction)
+}
@@ -0,0 +1,278 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool, types::ipnetwork::IpNetwork};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    auth::ClientInfo,
+    error::{AppError, Result},
+    models::{AuthProvider, User},
+    services::session_service,
+};
+
+// Progressive lockout: after the threshold every further failure doubles the
+// lock, starting at BASE_LOCKOUT_SECONDS and capped at MAX_LOCKOUT_SECONDS
+const ACCOUNT_LOCKOUT_THRESHOLD: u64 = 5;
+const IP_LOCKOUT_THRESHOLD: u64 = 20;
+const BASE_LOCKOUT_SECONDS: usize = 60;
+const MAX_LOCKOUT_SECONDS: usize = 3600;
+const FAILURE_WINDOW_SECONDS: usize = 86400;
+
+/// A sign-in attempt as shown to the account owner.
+#[derive(Debug, Serialize, FromRow)]
+pub struct LoginActivity {
+    pub id: Uuid,
+    pub method: String,
+    pub success: bool,
+    pub failure_reason: Option<String>,
+    pub ip_address: Option<String>,
+    pub device_label: Option<String>,
+    pub new_device: bool,
+    pub new_ip: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Lockout key for an account: the user id when the identifier matched one,
+/// otherwise the identifier itself so unknown accounts lock out identically.
+fn account_key(user_id: Option<Uuid>, identifier: Option<&str>) -> Option<String> {
+    match (user_id, identifier) {
+        (Some(user_id), _) => Some(user_id.to_string()),
+        (None, Some(identifier)) => Some(identifier.trim().to_lowercase()),
+        (None, None) => None,
+    }
+}
+
+/// Refuse the attempt if the account or the client's IP is locked out.
+pub async fn check_lockout(
+    state: &AppState,
+    user_id: Option<Uuid>,
+    identifier: Option<&str>,
+    client: &ClientInfo,
+) -> Result<()> {
+    let mut lock_keys = Vec::new();
+    if let Some(key) = account_key(user_id, identifier) {
+        lock_keys.push(format!("login_lockout:account:{}", key));
+    }
+    if let Some(ip) = client.ip_address {
+        lock_keys.push(format!("login_lockout:ip:{}", ip));
+    }
+
+    for key in lock_keys {
+        if state.redis.cache_get(&key).await?.is_some() {
+            return Err(AppError::RateLimit);
+        }
+    }
+
+    Ok(())
+}
+
+async fn register_failure(state: &AppState, scope: &str, key: &str, threshold: u64) -> Result<()> {
+    let failures = state
+        .redis
+        .increment_counter(
+            &format!("login_failures:{}:{}", scope, key),
+            FAILURE_WINDOW_SECONDS,
+        )
+        .await?;
+
+    if failures >= threshold {
+        let doublings = (failures - threshold).min(6) as u32;
+        let lock_seconds = (BASE_LOCKOUT_SECONDS << doublings).min(MAX_LOCKOUT_SECONDS);
+
+        state
+            .redis
+            .cache_set(
+                &format!("login_lockout:{}:{}", scope, key),
+                "1",
+                lock_seconds,
+            )
+            .await?;
+
+        tracing::warn!(
+            "Login lockout for {} {} after {} failures ({}s)",
+            scope,
+            key,
+            failures,
+            lock_seconds
+        );
+    }
+
+    Ok(())
+}
+
+/// Record a failed attempt and count it towards the account and IP lockouts.
+pub async fn record_failure(
+    state: &AppState,
+    user_id: Option<Uuid>,
+    identifier: Option<&str>,
+    method: &str,
+    reason: &str,
+    client: &ClientInfo,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO login_attempts (
+            user_id, identifier, method, success, failure_reason,
+            ip_address, user_agent, device_label
+        )
+        VALUES ($1, $2, $3, false, $4, $5, $6, $7)
+        "#,
+    )
+    .bind(user_id)
+    .bind(identifier)
+    .bind(method)
+    .bind(reason)
+    .bind(client.ip_address.map(IpNetwork::from))
+    .bind(&client.user_agent)
+    .bind(session_service::device_label(client.user_agent.as_deref()))
+    .execute(&state.db)
+    .await?;
+
+    if let Some(key) = account_key(user_id, identifier) {
+        register_failure(state, "account", &key, ACCOUNT_LOCKOUT_THRESHOLD).await?;
+    }
+
+    if let Some(ip) = client.ip_address {
+        register_failure(state, "ip", &ip.to_string(), IP_LOCKOUT_THRESHOLD).await?;
+    }
+
+    Ok(())
+}
+
+/// Record a completed sign-in, reset the account's failure count and alert
+/// the user if it came from a device or IP address they haven't used before.
+pub async fn record_success(
+    state: &AppState,
+    user: &User,
+    method: &str,
+    client: &ClientInfo,
+) -> Result<()> {
+    let device_label = session_service::device_label(client.user_agent.as_deref());
+    let ip_address = client.ip_address.map(IpNetwork::from);
+
+    let (has_history, known_device, known_ip) = sqlx::query_as::<_, (bool, bool, bool)>(
+        r#"
+        SELECT
+            EXISTS(SELECT 1 FROM login_attempts WHERE user_id = $1 AND success),
+            EXISTS(SELECT 1 FROM login_attempts WHERE user_id = $1 AND success AND device_label = $2),
+            EXISTS(SELECT 1 FROM login_attempts WHERE user_id = $1 AND success AND ip_address = $3)
+        "#,
+    )
+    .bind(user.id)
+    .bind(&device_label)
+    .bind(ip_address)
+    .fetch_one(&state.db)
+    .await?;
+
+    // The very first sign-in (or one we know nothing about) isn't an anomaly
+    let new_device = has_history && device_label.is_some() && !known_device;
+    let new_ip = has_history && ip_address.is_some() && !known_ip;
+
+    sqlx::query(
+        r#"
+        INSERT INTO login_attempts (
+            user_id, method, success, ip_address, user_agent,
+            device_label, new_device, new_ip
+        )
+        VALUES ($1, $2, true, $3, $4, $5, $6, $7)
+        "#,
+    )
+    .bind(user.id)
+    .bind(method)
+    .bind(ip_address)
+    .bind(&client.user_agent)
+    .bind(&device_label)
+    .bind(new_device)
+    .bind(new_ip)
+    .execute(&state.db)
+    .await?;
+
+    state
+        .redis
+        .cache_delete(&format!("login_failures:account:{}", user.id))
+        .await?;
+
+    if new_device || new_ip {
+        send_new_sign_in_alert(state, user, device_label, client);
+    }
+
+    Ok(())
+}
+
+/// Send the alert in the background so a slow email or SMS provider doesn't
+/// hold up the login. Email is preferred; SMS is used for phone-only accounts.
+fn send_new_sign_in_alert(
+    state: &AppState,
+    user: &User,
+    device_label: Option<String>,
+    client: &ClientInfo,
+) {
+    let email = user.email.clone().filter(|_| user.email_verified);
+    let phone = user
+        .phone
+        .clone()
+        .filter(|_| user.phone_verified || matches!(user.auth_provider, AuthProvider::Phone));
+
+    let username = user.username.clone();
+    let user_id = user.id;
+    let device = device_label.unwrap_or_else(|| "Unknown device".to_string());
+    let ip_address = client
+        .ip_address
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "Unknown".to_string());
+    let signed_in_at = Utc::now().format("%Y-%m-%d %H:%M UTC").to_string();
+    let email_service = state.email_service.clone();
+    let sms_service = state.sms_service.clone();
+    let app_name = state.config.app_name.clone();
+
+    tokio::spawn(async move {
+        let result = if let Some(email) = email {
+            email_service
+                .send_security_alert_email(&email, &username, &device, &ip_address, &signed_in_at)
+                .await
+        } else if let Some(phone) = phone {
+            sms_service
+                .send_security_alert(
+                    &phone,
+                    &app_name,
+                    &format!("A sign-in from a new device ({})", device),
+                )
+                .await
+                .map(|_| ())
+        } else {
+            Ok(())
+        };
+
+        if let Err(e) = result {
+            tracing::error!("Failed to send sign-in alert to user {}: {}", user_id, e);
+        }
+    });
+}
+
+/// The user's most recent sign-in attempts, newest first.
+pub async fn get_login_activity(
+    db: &PgPool,
+    user_id: Uuid,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<LoginActivity>> {
+    let activity = sqlx::query_as::<_, LoginActivity>(
+        r#"
+        SELECT
+            id, method, success, failure_reason, host(ip_address) AS ip_address,
+            device_label, new_device, new_ip, created_at
+        FROM login_attempts
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        LIMIT $2 OFFSET $3
+        "#,
+    )
+    .bind(user_id)
+    .bind(limit as i64)
+    .bind(offset as i64)
+    .fetch_all(db)
+    .await?;
+
+    Ok(activity)
+}
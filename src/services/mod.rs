@@ -1,14 +1,35 @@
+pub mod account_deletion_service;
+pub mod admin_service;
+pub mod api_token_service;
 pub mod apple_service;
 pub mod auth_service;
 pub mod background_jobs;
 pub mod comment_service;
 pub mod community_service;
+pub mod contact_change_service;
+pub mod data_export_service;
 pub mod email_service;
+pub mod follow_service;
+pub mod identity_service;
+pub mod invite_service;
+pub mod karma_service;
+pub mod login_security_service;
 pub mod notification_service;
+pub mod oauth_provider_service;
+pub mod oidc_service;
+pub mod passkey_service;
+pub mod password_service;
 pub mod post_service;
+pub mod profile_service;
 pub mod search_service;
+pub mod session_service;
+pub mod signing_key_service;
 pub mod sms_service;
+pub mod social_login_service;
+pub mod suspension_service;
+pub mod two_factor_service;
 pub mod typing_service;
 pub mod upload_service;
 pub mod user_service;
+pub mod username_service;
 pub mod websocket_service;
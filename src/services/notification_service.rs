@@ -2,13 +2,17 @@ use chrono::Utc;
 use regex::Regex;
 use serde_json::json;
 use sqlx::{PgPool, Row};
+use std::collections::HashSet;
 use uuid::Uuid;
 
 use crate::{
     error::{AppError, Result},
-    models::{Notification, NotificationResponse, NotificationType, UserPreferences},
+    models::{Notification, NotificationResponse, NotificationType, UserPreferences, UserStatus},
     redis::RedisClient,
-    services::{email_service::EmailService, sms_service::SmsService},
+    services::{
+        email_service::EmailService, sms_service::SmsService, username_service,
+        username_service::ResolvedUser,
+    },
 };
 
 #[derive(Clone)]
@@ -284,7 +288,11 @@ impl NotificationService {
             COALESCE(push_notifications, false) as push_notifications,
             COALESCE(nsfw_content, false) as nsfw_content,
             created_at,
-            updated_at
+            updated_at,
+            show_profile_posts,
+            show_profile_comments,
+            hide_followers,
+            require_follow_approval
             FROM user_preferences WHERE user_id = $1
             "#,
         )
@@ -305,6 +313,10 @@ impl NotificationService {
             nsfw_content: row.get("nsfw_content"),
             created_at: row.get("created_at"),
             updated_at: row.get("updated_at"),
+            show_profile_posts: row.get("show_profile_posts"),
+            show_profile_comments: row.get("show_profile_comments"),
+            hide_followers: row.get("hide_followers"),
+            require_follow_approval: row.get("require_follow_approval"),
         });
 
         let prefs = match preferences {
@@ -387,7 +399,11 @@ impl NotificationService {
             COALESCE(push_notifications, false) as push_notifications,
             COALESCE(nsfw_content, false) as nsfw_content,
             created_at,
-            updated_at
+            updated_at,
+            show_profile_posts,
+            show_profile_comments,
+            hide_followers,
+            require_follow_approval
             FROM user_preferences WHERE user_id = $1
             "#,
         )
@@ -408,6 +424,10 @@ impl NotificationService {
             nsfw_content: row.get("nsfw_content"),
             created_at: row.get("created_at"),
             updated_at: row.get("updated_at"),
+            show_profile_posts: row.get("show_profile_posts"),
+            show_profile_comments: row.get("show_profile_comments"),
+            hide_followers: row.get("hide_followers"),
+            require_follow_approval: row.get("require_follow_approval"),
         });
 
         let prefs = match preferences {
@@ -711,16 +731,18 @@ impl NotificationService {
         comment_id: Option<Uuid>,
     ) -> Result<()> {
         let mentions = self.extract_mentions(content);
+        let mut notified = HashSet::new();
 
         for username in mentions {
-            // Get user ID by username
-            if let Ok(Some(user)) = sqlx::query!(
-                "SELECT id FROM users WHERE username = $1 AND status = 'active'",
-                username
-            )
-            .fetch_optional(&self.db)
-            .await
+            // Old names still reach users who have renamed themselves
+            if let Ok(Some(ResolvedUser { user, .. })) =
+                username_service::resolve(&self.db, &username).await
             {
+                // A user mentioned by both their old and new name hears once
+                if !matches!(user.status, UserStatus::Active) || !notified.insert(user.id) {
+                    continue;
+                }
+
                 let _ = self
                     .notify_mention(user.id, mentioner_id, post_id, comment_id, content)
                     .await;
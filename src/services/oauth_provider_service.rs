@@ -0,0 +1,813 @@
+use axum::{
+    Json,
+    http::{Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use oauth2::url::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    auth::{Claims, ClientInfo, hash_token},
+    error::{AppError, Result},
+    models::User,
+    services::session_service::{self, Delegation},
+};
+
+const AUTHORIZATION_CODE_TTL_MINUTES: i64 = 10;
+const MAX_REDIRECT_URIS: usize = 10;
+
+/// Scopes an application can ask for, with the description shown to the user
+/// on the consent screen.
+pub const SCOPES: &[(&str, &str)] = &[
+    ("openid", "Sign you in with your account"),
+    ("profile", "See your username, display name and avatar"),
+    ("email", "See your email address"),
+    ("identity", "Access your account details"),
+    ("read", "Read posts, comments, communities and profiles"),
+    ("vote", "Vote on posts and comments"),
+    (
+        "submit",
+        "Submit, edit and report posts and comments, and upload media",
+    ),
+    ("save", "Save posts and comments and see what you've saved"),
+    ("subscribe", "Join and leave communities and follow users"),
+    ("privatemessages", "Read and manage your notifications"),
+    ("modposts", "Moderate the communities you moderate"),
+];
+
+/// Scope an application token needs for a route, by method and route template.
+/// Account, security and settings routes return `None`: only the user's own
+/// sessions can use them.
+pub fn required_scope(method: &Method, route: &str) -> Option<&'static str> {
+    let scope = match (method.as_str(), route) {
+        ("GET", "/api/oauth/userinfo") => "openid",
+        ("GET", "/api/users/me") => "identity",
+        (
+            "GET",
+            "/api/users/{username}"
+            | "/api/users/{username}/posts"
+            | "/api/users/{username}/comments"
+            | "/api/users/{username}/overview"
+            | "/api/users/{username}/followers"
+            | "/api/users/{username}/following"
+            | "/api/users/{username}/karma"
+            | "/api/users/{username}/karma/history"
+            | "/api/users/me/follow-requests"
+            | "/api/feed/home"
+            | "/api/communities"
+            | "/api/communities/{name}"
+            | "/api/communities/{name}/members"
+            | "/api/communities/{name}/rules"
+            | "/api/communities/{name}/flairs"
+            | "/api/posts"
+            | "/api/posts/{post_id}"
+            | "/api/comments/{comment_id}"
+            | "/api/{post_id}/comments"
+            | "/api/search"
+            | "/api/search/trending"
+            | "/api/search/autocomplete"
+            | "/api/typing/users",
+        ) => "read",
+        ("POST", "/api/posts/{post_id}/vote" | "/api/comments/{comment_id}/vote") => "vote",
+        ("POST" | "DELETE", "/api/posts/{post_id}/save" | "/api/comments/{comment_id}/save")
+        | ("GET", "/api/users/me/saved" | "/api/users/me/comments/saved") => "save",
+        (
+            "POST",
+            "/api/posts"
+            | "/api/comments"
+            | "/api/posts/{post_id}/report"
+            | "/api/comments/{comment_id}/report"
+            | "/api/typing/start"
+            | "/api/typing/stop"
+            | "/api/upload/initiate"
+            | "/api/upload/chunk"
+            | "/api/upload/complete"
+            | "/api/upload/post-image"
+            | "/api/upload/comment-image",
+        )
+        | ("PUT" | "DELETE", "/api/posts/{post_id}" | "/api/comments/{comment_id}")
+        | ("GET", "/api/upload/status/{upload_id}" | "/api/media/my")
+        | ("DELETE", "/api/upload/cancel/{upload_id}" | "/api/media/{media_id}") => "submit",
+        (
+            "POST",
+            "/api/communities/{name}/join"
+            | "/api/communities/{name}/leave"
+            | "/api/users/me/follow/{user_id}"
+            | "/api/users/me/follow-requests/{user_id}/approve",
+        )
+        | (
+            "DELETE",
+            "/api/users/me/unfollow/{user_id}" | "/api/users/me/follow-requests/{user_id}",
+        ) => "subscribe",
+        ("GET", "/api/notifications" | "/api/notifications/count")
+        | ("POST", "/api/notifications/read" | "/api/notifications/read-all")
+        | ("DELETE", "/api/notifications/{notification_id}") => "privatemessages",
+        ("PUT", "/api/communities/{name}" | "/api/communities/{name}/members/{member_id}/role")
+        | ("DELETE", "/api/communities/{name}/members/{member_id}")
+        | ("POST", "/api/communities/{name}/rules" | "/api/communities/{name}/flairs") => {
+            "modposts"
+        }
+        _ => return None,
+    };
+
+    Some(scope)
+}
+
+/// A registered third-party application. Confidential clients (server-side
+/// apps) authenticate with a secret; public clients (SPAs, mobile apps) have
+/// none and rely on PKCE alone.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct OAuthClient {
+    pub id: Uuid,
+    pub client_id: String,
+    #[serde(skip_serializing)]
+    pub client_secret_hash: Option<String>,
+    pub owner_id: Uuid,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub allowed_scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl OAuthClient {
+    pub fn is_confidential(&self) -> bool {
+        self.client_secret_hash.is_some()
+    }
+}
+
+/// An application the user has authorized, as shown to the user.
+#[derive(Debug, Serialize, FromRow)]
+pub struct AuthorizedApp {
+    pub client_id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Query of an authorization request, as sent by the application to the consent page.
+#[derive(Debug, Deserialize)]
+pub struct AuthorizationRequest {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub nonce: Option<String>,
+}
+
+/// Token endpoint response (RFC 6749 section 5.1).
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+    pub refresh_token: String,
+    pub scope: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+/// Errors from the token and revocation endpoints, in the RFC 6749 format
+/// (`{"error", "error_description"}`) that OAuth client libraries expect.
+#[derive(Debug)]
+pub enum OAuthError {
+    InvalidRequest(String),
+    InvalidClient,
+    InvalidGrant(String),
+    UnsupportedGrantType,
+    Server(AppError),
+}
+
+impl From<AppError> for OAuthError {
+    fn from(error: AppError) -> Self {
+        match error {
+            AppError::Authentication(message) => OAuthError::InvalidGrant(message),
+            AppError::BadRequest(message) | AppError::Validation(message) => {
+                OAuthError::InvalidRequest(message)
+            }
+            error => OAuthError::Server(error),
+        }
+    }
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        let (status, error, description) = match self {
+            OAuthError::InvalidRequest(message) => {
+                (StatusCode::BAD_REQUEST, "invalid_request", message)
+            }
+            OAuthError::InvalidClient => (
+                StatusCode::UNAUTHORIZED,
+                "invalid_client",
+                "Client authentication failed".to_string(),
+            ),
+            OAuthError::InvalidGrant(message) => {
+                (StatusCode::BAD_REQUEST, "invalid_grant", message)
+            }
+            OAuthError::UnsupportedGrantType => (
+                StatusCode::BAD_REQUEST,
+                "unsupported_grant_type",
+                "Only authorization_code and refresh_token are supported".to_string(),
+            ),
+            OAuthError::Server(e) => {
+                tracing::error!("OAuth token endpoint error: {}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "server_error",
+                    "Internal server error".to_string(),
+                )
+            }
+        };
+
+        let body = Json(json!({
+            "error": error,
+            "error_description": description
+        }));
+
+        (status, body).into_response()
+    }
+}
+
+fn generate_secret(len: usize) -> String {
+    let bytes: Vec<u8> = (0..len).map(|_| rand::random::<u8>()).collect();
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Redirect URIs must be absolute and fragment-free; plain http is only
+/// allowed for loopback addresses during development. Custom schemes are
+/// accepted for native apps.
+fn validate_redirect_uri(redirect_uri: &str) -> Result<()> {
+    let invalid = || AppError::Validation(format!("redirect_uris: '{}' is invalid", redirect_uri));
+
+    let url = Url::parse(redirect_uri).map_err(|_| invalid())?;
+
+    if url.fragment().is_some() {
+        return Err(invalid());
+    }
+
+    if url.scheme() == "http"
+        && !matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"))
+    {
+        return Err(AppError::Validation(format!(
+            "redirect_uris: '{}' must use https",
+            redirect_uri
+        )));
+    }
+
+    Ok(())
+}
+
+fn validate_scopes(scopes: &[String], allowed: &[String]) -> Result<()> {
+    if scopes.is_empty() {
+        return Err(AppError::BadRequest(
+            "At least one scope is required".to_string(),
+        ));
+    }
+
+    for scope in scopes {
+        if !allowed.contains(scope) {
+            return Err(AppError::BadRequest(format!(
+                "Scope '{}' is not allowed",
+                scope
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_scopes(scope: Option<&str>) -> Vec<String> {
+    let mut scopes: Vec<String> = Vec::new();
+    for scope in scope.unwrap_or_default().split_whitespace() {
+        if !scopes.iter().any(|existing| existing == scope) {
+            scopes.push(scope.to_string());
+        }
+    }
+    scopes
+}
+
+/// Register an application. Returns the client secret for confidential
+/// clients; it is only stored hashed, so this is the one time it's shown.
+pub async fn create_client(
+    db: &PgPool,
+    owner_id: Uuid,
+    name: &str,
+    redirect_uris: &[String],
+    scopes: &[String],
+    confidential: bool,
+) -> Result<(OAuthClient, Option<String>)> {
+    if redirect_uris.is_empty() || redirect_uris.len() > MAX_REDIRECT_URIS {
+        return Err(AppError::Validation(format!(
+            "redirect_uris: between 1 and {} redirect URIs are required",
+            MAX_REDIRECT_URIS
+        )));
+    }
+
+    for redirect_uri in redirect_uris {
+        validate_redirect_uri(redirect_uri)?;
+    }
+
+    let supported: Vec<String> = SCOPES.iter().map(|(scope, _)| scope.to_string()).collect();
+    validate_scopes(scopes, &supported)?;
+
+    let client_secret = confidential.then(|| generate_secret(32));
+
+    let client = sqlx::query_as::<_, OAuthClient>(
+        r#"
+        INSERT INTO oauth_clients (
+            client_id, client_secret_hash, owner_id, name, redirect_uris, allowed_scopes
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING *
+        "#,
+    )
+    .bind(generate_secret(18))
+    .bind(client_secret.as_deref().map(hash_token))
+    .bind(owner_id)
+    .bind(name.trim())
+    .bind(redirect_uris)
+    .bind(parse_scopes(Some(&scopes.join(" "))))
+    .fetch_one(db)
+    .await?;
+
+    Ok((client, client_secret))
+}
+
+pub async fn list_clients(db: &PgPool, owner_id: Uuid) -> Result<Vec<OAuthClient>> {
+    let clients = sqlx::query_as::<_, OAuthClient>(
+        "SELECT * FROM oauth_clients WHERE owner_id = $1 ORDER BY created_at DESC",
+    )
+    .bind(owner_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(clients)
+}
+
+/// Delete an application along with every grant, code and session it holds.
+/// Returns false if the user owns no such application.
+pub async fn delete_client(state: &AppState, owner_id: Uuid, id: Uuid) -> Result<bool> {
+    let mut tx = state.db.begin().await?;
+
+    let jtis = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT s.token_jti FROM user_sessions s
+        JOIN oauth_clients c ON c.id = s.oauth_client_id
+        WHERE c.id = $1 AND c.owner_id = $2 AND s.revoked_at IS NULL
+        "#,
+    )
+    .bind(id)
+    .bind(owner_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let result = sqlx::query("DELETE FROM oauth_clients WHERE id = $1 AND owner_id = $2")
+        .bind(id)
+        .bind(owner_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    for jti in jtis {
+        state.redis.delete_session(&jti).await?;
+    }
+
+    Ok(result.rows_affected() > 0)
+}
+
+async fn get_client(db: &PgPool, client_id: &str) -> Result<Option<OAuthClient>> {
+    let client =
+        sqlx::query_as::<_, OAuthClient>("SELECT * FROM oauth_clients WHERE client_id = $1")
+            .bind(client_id)
+            .fetch_optional(db)
+            .await?;
+
+    Ok(client)
+}
+
+/// Identify the client calling the token or revocation endpoint. Confidential
+/// clients must present their secret.
+pub async fn authenticate_client(
+    db: &PgPool,
+    client_id: &str,
+    client_secret: Option<&str>,
+) -> std::result::Result<OAuthClient, OAuthError> {
+    let client = get_client(db, client_id)
+        .await?
+        .ok_or(OAuthError::InvalidClient)?;
+
+    let secret_matches = match &client.client_secret_hash {
+        Some(secret_hash) => client_secret.map(hash_token).as_ref() == Some(secret_hash),
+        None => true,
+    };
+
+    if !secret_matches {
+        return Err(OAuthError::InvalidClient);
+    }
+
+    Ok(client)
+}
+
+/// Check an authorization request before asking the user for consent: known
+/// client, registered redirect URI, a PKCE S256 challenge and scopes the
+/// client may request. Returns the client and requested scopes.
+pub async fn validate_authorization_request(
+    db: &PgPool,
+    request: &AuthorizationRequest,
+) -> Result<(OAuthClient, Vec<String>)> {
+    let client = get_client(db, &request.client_id)
+        .await?
+        .ok_or_else(|| AppError::BadRequest("Unknown client_id".to_string()))?;
+
+    if !client.redirect_uris.contains(&request.redirect_uri) {
+        return Err(AppError::BadRequest(
+            "redirect_uri is not registered for this client".to_string(),
+        ));
+    }
+
+    if request.response_type != "code" {
+        return Err(AppError::BadRequest(
+            "Unsupported response_type, only 'code' is supported".to_string(),
+        ));
+    }
+
+    let code_challenge_valid = request
+        .code_challenge
+        .as_ref()
+        .is_some_and(|challenge| (43..=128).contains(&challenge.len()));
+
+    if !code_challenge_valid || request.code_challenge_method.as_deref() != Some("S256") {
+        return Err(AppError::BadRequest(
+            "A PKCE code_challenge with code_challenge_method S256 is required".to_string(),
+        ));
+    }
+
+    let scopes = parse_scopes(request.scope.as_deref());
+    validate_scopes(&scopes, &client.allowed_scopes)?;
+
+    Ok((client, scopes))
+}
+
+/// Whether the user has already granted the application every requested scope,
+/// so the consent screen can be skipped.
+pub async fn is_already_granted(
+    db: &PgPool,
+    user_id: Uuid,
+    client: &OAuthClient,
+    scopes: &[String],
+) -> Result<bool> {
+    let granted = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM oauth_grants WHERE user_id = $1 AND client_id = $2 AND scopes @> $3)",
+    )
+    .bind(user_id)
+    .bind(client.id)
+    .bind(scopes)
+    .fetch_one(db)
+    .await?;
+
+    Ok(granted)
+}
+
+fn redirect_with(
+    redirect_uri: &str,
+    params: &[(&str, &str)],
+    state: Option<&str>,
+) -> Result<String> {
+    let mut url = Url::parse(redirect_uri)
+        .map_err(|_| AppError::BadRequest("Invalid redirect_uri".to_string()))?;
+
+    {
+        let mut query = url.query_pairs_mut();
+        for (name, value) in params {
+            query.append_pair(name, value);
+        }
+        if let Some(state) = state {
+            query.append_pair("state", state);
+        }
+    }
+
+    Ok(url.to_string())
+}
+
+/// Record the user's consent and issue a single-use authorization code.
+/// Returns the URL to send the user back to the application with.
+pub async fn approve_authorization(
+    db: &PgPool,
+    user_id: Uuid,
+    client: &OAuthClient,
+    request: &AuthorizationRequest,
+    scopes: &[String],
+) -> Result<String> {
+    let code = generate_secret(32);
+
+    sqlx::query(
+        r#"
+        INSERT INTO oauth_grants (user_id, client_id, scopes)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, client_id) DO UPDATE
+        SET scopes = ARRAY(SELECT DISTINCT UNNEST(oauth_grants.scopes || EXCLUDED.scopes)),
+            updated_at = NOW()
+        "#,
+    )
+    .bind(user_id)
+    .bind(client.id)
+    .bind(scopes)
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO oauth_authorization_codes (
+            code_hash, client_id, user_id, redirect_uri, scopes, code_challenge, nonce, expires_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+    )
+    .bind(hash_token(&code))
+    .bind(client.id)
+    .bind(user_id)
+    .bind(&request.redirect_uri)
+    .bind(scopes)
+    .bind(&request.code_challenge)
+    .bind(&request.nonce)
+    .bind(Utc::now() + Duration::minutes(AUTHORIZATION_CODE_TTL_MINUTES))
+    .execute(db)
+    .await?;
+
+    redirect_with(
+        &request.redirect_uri,
+        &[("code", &code)],
+        request.state.as_deref(),
+    )
+}
+
+/// URL telling the application the user declined.
+pub fn deny_authorization(request: &AuthorizationRequest) -> Result<String> {
+    redirect_with(
+        &request.redirect_uri,
+        &[
+            ("error", "access_denied"),
+            ("error_description", "The user denied the request"),
+        ],
+        request.state.as_deref(),
+    )
+}
+
+#[derive(Debug, FromRow)]
+struct AuthorizationCode {
+    client_id: Uuid,
+    user_id: Uuid,
+    redirect_uri: String,
+    scopes: Vec<String>,
+    code_challenge: String,
+    nonce: Option<String>,
+    expires_at: DateTime<Utc>,
+}
+
+/// PKCE S256: the challenge is the unpadded base64url SHA-256 of the verifier.
+fn verify_code_challenge(code_verifier: &str, code_challenge: &str) -> bool {
+    (43..=128).contains(&code_verifier.len())
+        && base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(Sha256::digest(code_verifier.as_bytes()))
+            == code_challenge
+}
+
+/// Exchange an authorization code for tokens. Codes are single use and bound
+/// to the client, redirect URI and PKCE challenge they were issued with.
+pub async fn exchange_authorization_code(
+    state: &AppState,
+    client: &OAuthClient,
+    code: &str,
+    redirect_uri: &str,
+    code_verifier: &str,
+    client_info: &ClientInfo,
+) -> std::result::Result<TokenResponse, OAuthError> {
+    let invalid = || OAuthError::InvalidGrant("Invalid or expired authorization code".to_string());
+
+    let authorization = sqlx::query_as::<_, AuthorizationCode>(
+        r#"
+        DELETE FROM oauth_authorization_codes
+        WHERE code_hash = $1
+        RETURNING client_id, user_id, redirect_uri, scopes, code_challenge, nonce, expires_at
+        "#,
+    )
+    .bind(hash_token(code))
+    .fetch_optional(&state.db)
+    .await
+    .map_err(AppError::from)?
+    .ok_or_else(invalid)?;
+
+    if authorization.client_id != client.id
+        || authorization.expires_at <= Utc::now()
+        || authorization.redirect_uri != redirect_uri
+        || !verify_code_challenge(code_verifier, &authorization.code_challenge)
+    {
+        return Err(invalid());
+    }
+
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1 AND status = 'active'")
+        .bind(authorization.user_id)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(AppError::from)?
+        .ok_or_else(invalid)?;
+
+    let delegation = Delegation {
+        oauth_client_id: client.id,
+        client_id: client.client_id.clone(),
+        scopes: authorization.scopes.clone(),
+    };
+
+    let tokens =
+        session_service::create_delegated_session(state, &user, client_info, &delegation).await?;
+
+    let id_token = if authorization.scopes.iter().any(|scope| scope == "openid") {
+        Some(create_id_token(
+            state,
+            &user,
+            client,
+            &authorization.scopes,
+            authorization.nonce.as_deref(),
+        )?)
+    } else {
+        None
+    };
+
+    Ok(TokenResponse {
+        access_token: tokens.access_token,
+        token_type: "Bearer",
+        expires_in: tokens.expires_in,
+        refresh_token: tokens.refresh_token,
+        scope: authorization.scopes.join(" "),
+        id_token,
+    })
+}
+
+/// Rotate an application's refresh token, keeping the scopes it was granted.
+pub async fn refresh_tokens(
+    state: &AppState,
+    client: &OAuthClient,
+    refresh_token: &str,
+    client_info: &ClientInfo,
+) -> std::result::Result<TokenResponse, OAuthError> {
+    let (_, tokens) =
+        session_service::refresh_session(state, refresh_token, client_info, Some(client)).await?;
+
+    let claims = Claims::verify(&tokens.access_token, &state.signing_keys)?;
+
+    Ok(TokenResponse {
+        access_token: tokens.access_token,
+        token_type: "Bearer",
+        expires_in: tokens.expires_in,
+        refresh_token: tokens.refresh_token,
+        scope: claims.scope.unwrap_or_default(),
+        id_token: None,
+    })
+}
+
+/// Revoke the session an access or refresh token belongs to (RFC 7009).
+/// Tokens that are unknown or belong to another client are ignored.
+pub async fn revoke_token(state: &AppState, client: &OAuthClient, token: &str) -> Result<()> {
+    let family_id = sqlx::query_scalar::<_, Uuid>(
+        "SELECT family_id FROM user_sessions WHERE refresh_token_hash = $1 AND oauth_client_id = $2",
+    )
+    .bind(hash_token(token))
+    .bind(client.id)
+    .fetch_optional(&state.db)
+    .await?;
+
+    let family_id = match family_id {
+        Some(family_id) => Some(family_id),
+        None => match Claims::verify(token, &state.signing_keys) {
+            Ok(claims) => sqlx::query_scalar::<_, Uuid>(
+                "SELECT family_id FROM user_sessions WHERE token_jti = $1 AND oauth_client_id = $2",
+            )
+            .bind(&claims.jti)
+            .bind(client.id)
+            .fetch_optional(&state.db)
+            .await?,
+            Err(_) => None,
+        },
+    };
+
+    if let Some(family_id) = family_id {
+        session_service::revoke_family(state, family_id).await?;
+    }
+
+    Ok(())
+}
+
+/// OpenID Connect claims about the user that the granted scopes allow the
+/// application to see. Used for both the ID token and the userinfo endpoint.
+pub fn user_claims(user: &User, scopes: &[String]) -> Map<String, Value> {
+    let has_scope = |scope: &str| scopes.iter().any(|granted| granted == scope);
+
+    let mut claims = Map::new();
+    claims.insert("sub".to_string(), json!(user.id));
+
+    if has_scope("profile") {
+        claims.insert("preferred_username".to_string(), json!(user.username));
+        claims.insert(
+            "name".to_string(),
+            json!(user.display_name.as_deref().unwrap_or(&user.username)),
+        );
+        claims.insert("picture".to_string(), json!(user.avatar_url));
+        claims.insert("updated_at".to_string(), json!(user.updated_at.timestamp()));
+    }
+
+    match &user.email {
+        Some(email) if has_scope("email") => {
+            claims.insert("email".to_string(), json!(email));
+            claims.insert("email_verified".to_string(), json!(user.email_verified));
+        }
+        _ => {}
+    }
+
+    claims
+}
+
+fn create_id_token(
+    state: &AppState,
+    user: &User,
+    client: &OAuthClient,
+    scopes: &[String],
+    nonce: Option<&str>,
+) -> Result<String> {
+    let now = Utc::now();
+    let ttl = Duration::minutes(state.config.access_token_ttl_minutes);
+
+    let mut claims = user_claims(user, scopes);
+    claims.insert("iss".to_string(), json!(state.config.base_url));
+    claims.insert("aud".to_string(), json!(client.client_id));
+    claims.insert("iat".to_string(), json!(now.timestamp()));
+    claims.insert("exp".to_string(), json!((now + ttl).timestamp()));
+    if let Some(nonce) = nonce {
+        claims.insert("nonce".to_string(), json!(nonce));
+    }
+
+    state.signing_keys.sign(&claims)
+}
+
+pub async fn list_authorized_apps(db: &PgPool, user_id: Uuid) -> Result<Vec<AuthorizedApp>> {
+    let apps = sqlx::query_as::<_, AuthorizedApp>(
+        r#"
+        SELECT c.client_id, c.name, g.scopes, g.created_at, g.updated_at
+        FROM oauth_grants g
+        JOIN oauth_clients c ON c.id = g.client_id
+        WHERE g.user_id = $1
+        ORDER BY g.updated_at DESC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(apps)
+}
+
+/// Remove an application's access: its grant and every session it holds.
+/// Returns false if the user never authorized it.
+pub async fn revoke_authorization(
+    state: &AppState,
+    user_id: Uuid,
+    client_id: &str,
+) -> Result<bool> {
+    let oauth_client_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        DELETE FROM oauth_grants g
+        USING oauth_clients c
+        WHERE c.id = g.client_id AND g.user_id = $1 AND c.client_id = $2
+        RETURNING g.client_id
+        "#,
+    )
+    .bind(user_id)
+    .bind(client_id)
+    .fetch_optional(&state.db)
+    .await?;
+
+    let Some(oauth_client_id) = oauth_client_id else {
+        return Ok(false);
+    };
+
+    sqlx::query("DELETE FROM oauth_authorization_codes WHERE user_id = $1 AND client_id = $2")
+        .bind(user_id)
+        .bind(oauth_client_id)
+        .execute(&state.db)
+        .await?;
+
+    session_service::revoke_client_sessions(state, user_id, oauth_client_id).await?;
+
+    Ok(true)
+}
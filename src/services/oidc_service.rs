@@ -0,0 +1,825 @@
+use axum::http::HeaderMap;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header, jwk::JwkSet};
+use oauth2::url::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, error::Error, sync::RwLock};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    config::{Config, OidcProviderConfig},
+    error::{AppError, Result},
+    redis::RedisClient,
+    services::{identity_service::ExternalProfile, social_login_service},
+};
+
+const METADATA_CACHE_MINUTES: i64 = 60;
+const JWKS_CACHE_MINUTES: i64 = 60;
+// An unknown kid forces a JWKS refetch, but no more often than this
+const JWKS_MIN_REFRESH_SECONDS: i64 = 60;
+const PENDING_AUTHORIZATION_TTL_SECONDS: usize = 10 * 60;
+
+/// Names already taken by the built-in provider routes.
+const RESERVED_NAMES: &[&str] = &["google", "apple", "exchange"];
+
+/// The parts of a provider's discovery document we use.
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenSet {
+    access_token: String,
+    id_token: Option<String>,
+}
+
+/// A sign-in or link started with a provider, kept in Redis under the `state`
+/// parameter until the provider redirects back.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingAuthorization {
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    /// Set when a signed-in user is linking the provider rather than signing in
+    pub link_user_id: Option<Uuid>,
+    /// Used if the sign-in creates a new account
+    pub invite_code: Option<String>,
+}
+
+pub struct OidcProvider {
+    config: OidcProviderConfig,
+    redirect_uri: String,
+    http_client: reqwest::Client,
+    metadata: RwLock<Option<(ProviderMetadata, DateTime<Utc>)>>,
+    jwks: RwLock<Option<(JwkSet, DateTime<Utc>)>>,
+}
+
+impl OidcProvider {
+    fn new(
+        config: OidcProviderConfig,
+        redirect_uri: String,
+    ) -> std::result::Result<Self, Box<dyn Error>> {
+        Url::parse(&config.issuer)?;
+
+        let http_client = reqwest::ClientBuilder::new()
+            .redirect(reqwest::redirect::Policy::none())
+            .timeout(std::time::Duration::from_secs(10))
+            .build()?;
+
+        Ok(Self {
+            config,
+            redirect_uri,
+            http_client,
+            metadata: RwLock::new(None),
+            jwks: RwLock::new(None),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    pub fn display_name(&self) -> &str {
+        &self.config.display_name
+    }
+
+    fn provider_error(&self, message: &str) -> AppError {
+        AppError::Authentication(format!("{}: {}", self.config.display_name, message))
+    }
+
+    /// The discovery document, fetched lazily so a provider being down doesn't
+    /// stop the server from starting, and cached for an hour.
+    async fn metadata(&self) -> Result<ProviderMetadata> {
+        let cached = self
+            .metadata
+            .read()
+            .expect("OIDC metadata lock poisoned")
+            .clone()
+            .filter(|(_, fetched_at)| {
+                *fetched_at > Utc::now() - Duration::minutes(METADATA_CACHE_MINUTES)
+            });
+
+        if let Some((metadata, _)) = cached {
+            return Ok(metadata);
+        }
+
+        let issuer = self.config.issuer.trim_end_matches('/');
+        let response = self
+            .http_client
+            .get(format!("{}/.well-known/openid-configuration", issuer))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            tracing::error!(
+                "OIDC discovery for {} failed with status {}",
+                self.config.name,
+                response.status()
+            );
+            return Err(AppError::Internal(format!(
+                "OIDC discovery failed for {}",
+                self.config.name
+            )));
+        }
+
+        let metadata: ProviderMetadata = response.json().await?;
+
+        // The issuer in the document must be the one we trust (OIDC Discovery 4.3)
+        if metadata.issuer.trim_end_matches('/') != issuer {
+            return Err(AppError::Internal(format!(
+                "OIDC discovery for {} returned issuer {}",
+                self.config.name, metadata.issuer
+            )));
+        }
+
+        *self.metadata.write().expect("OIDC metadata lock poisoned") =
+            Some((metadata.clone(), Utc::now()));
+
+        Ok(metadata)
+    }
+
+    /// The provider's signing keys, refetched when they're older than an hour
+    /// or a token names a key we haven't seen (the provider rotated).
+    async fn jwks(&self, jwks_uri: &str, kid: Option<&str>) -> Result<JwkSet> {
+        let cached = self.jwks.read().expect("OIDC JWKS lock poisoned").clone();
+        let now = Utc::now();
+
+        if let Some((jwks, fetched_at)) = &cached {
+            let fresh = *fetched_at > now - Duration::minutes(JWKS_CACHE_MINUTES);
+            let has_key = kid.is_none_or(|kid| jwks.find(kid).is_some());
+            let recently_fetched = *fetched_at > now - Duration::seconds(JWKS_MIN_REFRESH_SECONDS);
+
+            if (fresh && has_key) || recently_fetched {
+                return Ok(jwks.clone());
+            }
+        }
+
+        let jwks: JwkSet = self
+            .http_client
+            .get(jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        *self.jwks.write().expect("OIDC JWKS lock poisoned") = Some((jwks.clone(), now));
+
+        Ok(jwks)
+    }
+
+    /// Build the URL to send the user to, with PKCE and a nonce bound to the
+    /// ID token.
+    async fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_challenge: &str,
+    ) -> Result<String> {
+        let metadata = self.metadata().await?;
+
+        let mut url = Url::parse(&metadata.authorization_endpoint)
+            .map_err(|_| self.provider_error("invalid authorization endpoint"))?;
+
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.redirect_uri)
+            .append_pair("scope", &self.config.scopes.join(" "))
+            .append_pair("state", state)
+            .append_pair("nonce", nonce)
+            .append_pair("code_challenge", code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(url.to_string())
+    }
+
+    async fn exchange_code(
+        &self,
+        metadata: &ProviderMetadata,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<TokenSet> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.redirect_uri.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(client_secret) = &self.config.client_secret {
+            form.push(("client_secret", client_secret.as_str()));
+        }
+
+        let response = self
+            .http_client
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            tracing::error!(
+                "OIDC token exchange with {} failed: {} {}",
+                self.config.name,
+                response.status(),
+                response.text().await.unwrap_or_default()
+            );
+            return Err(self.provider_error("failed to exchange authorization code"));
+        }
+
+        Ok(response.json().await?)
+    }
+
+    /// Verify an ID token's signature against the provider's JWKS, plus its
+    /// issuer, audience, expiry and nonce. Returns its claims.
+    async fn verify_id_token(
+        &self,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<Map<String, Value>> {
+        let invalid = || self.provider_error("invalid ID token");
+
+        let header = decode_header(id_token).map_err(|_| invalid())?;
+
+        // Symmetric algorithms would let anyone holding the client secret mint tokens
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(invalid());
+        }
+
+        let jwks = self.jwks(&metadata.jwks_uri, header.kid.as_deref()).await?;
+        let jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        }
+        .ok_or_else(invalid)?;
+
+        let decoding_key = DecodingKey::from_jwk(jwk).map_err(|_| invalid())?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_issuer(&[&metadata.issuer]);
+
+        let claims = decode::<Map<String, Value>>(id_token, &decoding_key, &validation)
+            .map_err(|e| {
+                tracing::warn!("Rejected ID token from {}: {}", self.config.name, e);
+                invalid()
+            })?
+            .claims;
+
+        if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+            return Err(invalid());
+        }
+
+        Ok(claims)
+    }
+
+    async fn userinfo(&self, endpoint: &str, access_token: &str) -> Result<Map<String, Value>> {
+        let claims = self
+            .http_client
+            .get(endpoint)
+            .bearer_auth(access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(claims)
+    }
+
+    /// Complete a sign-in: exchange the code, verify the ID token and map its
+    /// claims (topped up from the userinfo endpoint when the ID token lacks
+    /// the email or name) to a profile.
+    pub async fn authenticate(
+        &self,
+        code: &str,
+        pending: &PendingAuthorization,
+    ) -> Result<ExternalProfile> {
+        let metadata = self.metadata().await?;
+        let tokens = self
+            .exchange_code(&metadata, code, &pending.code_verifier)
+            .await?;
+
+        let id_token = tokens
+            .id_token
+            .ok_or_else(|| self.provider_error("no ID token returned"))?;
+
+        let mut claims = self
+            .verify_id_token(&metadata, &id_token, &pending.nonce)
+            .await?;
+
+        let mapping = &self.config.claims;
+        let missing_profile =
+            claim(&claims, &mapping.email).is_none() || claim(&claims, &mapping.name).is_none();
+
+        match &metadata.userinfo_endpoint {
+            Some(endpoint) if missing_profile => {
+                let userinfo = self.userinfo(endpoint, &tokens.access_token).await?;
+
+                // Userinfo must describe the same user as the ID token (OIDC Core 5.3.2)
+                if userinfo.get("sub") != claims.get("sub") {
+                    return Err(self.provider_error("userinfo subject mismatch"));
+                }
+
+                for (name, value) in userinfo {
+                    claims.entry(name).or_insert(value);
+                }
+            }
+            _ => {}
+        }
+
+        let subject = match claim(&claims, &mapping.subject) {
+            Some(Value::String(subject)) => subject.clone(),
+            Some(Value::Number(subject)) => subject.to_string(),
+            _ => return Err(self.provider_error("missing subject claim")),
+        };
+
+        let email = claim_str(&claims, &mapping.email).map(|email| email.to_lowercase());
+        let email_verified = self.config.trust_email
+            || match claim(&claims, &mapping.email_verified) {
+                Some(Value::Bool(verified)) => *verified,
+                Some(Value::String(verified)) => verified == "true",
+                _ => false,
+            };
+
+        Ok(ExternalProfile {
+            provider: self.config.name.clone(),
+            subject,
+            email_verified: email.is_some() && email_verified,
+            email,
+            display_name: claim_str(&claims, &mapping.name),
+            avatar_url: claim_str(&claims, &mapping.picture),
+        })
+    }
+}
+
+/// Look up a claim by name; dotted names reach into nested objects.
+fn claim<'a>(claims: &'a Map<String, Value>, name: &str) -> Option<&'a Value> {
+    if let Some(value) = claims.get(name) {
+        return Some(value);
+    }
+
+    let mut parts = name.split('.');
+    let mut value = claims.get(parts.next()?)?;
+    for part in parts {
+        value = value.get(part)?;
+    }
+
+    Some(value)
+}
+
+fn claim_str(claims: &Map<String, Value>, name: &str) -> Option<String> {
+    claim(claims, name)
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+/// The configured generic OIDC providers, by name.
+pub struct OidcProviders {
+    providers: HashMap<String, OidcProvider>,
+}
+
+impl OidcProviders {
+    pub fn new(config: &Config) -> std::result::Result<Self, Box<dyn Error>> {
+        let mut providers = HashMap::new();
+
+        for provider_config in &config.oidc_providers {
+            let name = provider_config.name.clone();
+
+            let valid_name = name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+            if !valid_name || RESERVED_NAMES.contains(&name.as_str()) {
+                return Err(format!("Invalid OIDC provider name '{}'", name).into());
+            }
+
+            let redirect_uri = provider_config.redirect_uri.clone().unwrap_or_else(|| {
+                format!(
+                    "http://{}:{}/api/auth/oauth/{}/callback",
+                    config.host, config.port, name
+                )
+            });
+
+            providers.insert(
+                name,
+                OidcProvider::new(provider_config.clone(), redirect_uri)?,
+            );
+        }
+
+        Ok(Self { providers })
+    }
+
+    pub fn get(&self, name: &str) -> Result<&OidcProvider> {
+        self.providers
+            .get(name)
+            .ok_or_else(|| AppError::NotFound("Unknown sign-in provider".to_string()))
+    }
+
+    pub fn list(&self) -> Vec<&OidcProvider> {
+        let mut providers: Vec<&OidcProvider> = self.providers.values().collect();
+        providers.sort_by(|a, b| a.name().cmp(b.name()));
+        providers
+    }
+}
+
+fn random_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// What a handler needs to send the user to the provider.
+#[derive(Debug)]
+pub struct AuthorizationStart {
+    pub authorization_url: String,
+    /// `Set-Cookie` value binding the flow to this browser
+    pub cookie: String,
+}
+
+/// Start a sign-in (or, with `link_user_id`, an identity link) with a
+/// provider. The flow is bound to the browser it's started in: the nonce is
+/// derived from a secret in the returned cookie, and the callback is refused
+/// without it.
+pub async fn start_authorization(
+    state: &AppState,
+    provider: &OidcProvider,
+    link_user_id: Option<Uuid>,
+    invite_code: Option<String>,
+) -> Result<AuthorizationStart> {
+    let binding = social_login_service::bind_to_browser(
+        provider.name(),
+        false,
+        state.config.base_url.starts_with("https://"),
+    );
+
+    let oauth_state = random_token();
+    let pending = PendingAuthorization {
+        provider: provider.name().to_string(),
+        nonce: binding.nonce,
+        code_verifier: random_token(),
+        link_user_id,
+        invite_code,
+    };
+
+    let code_challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .encode(Sha256::digest(pending.code_verifier.as_bytes()));
+
+    let authorization_url = provider
+        .authorization_url(&oauth_state, &pending.nonce, &code_challenge)
+        .await?;
+
+    let pending = serde_json::to_string(&pending)
+        .map_err(|e| AppError::Internal(format!("Failed to serialize OIDC state: {}", e)))?;
+
+    state
+        .redis
+        .cache_set(
+            &format!("oidc-state-{}", oauth_state),
+            &pending,
+            PENDING_AUTHORIZATION_TTL_SECONDS,
+        )
+        .await?;
+
+    Ok(AuthorizationStart {
+        authorization_url,
+        cookie: binding.cookie,
+    })
+}
+
+/// Take the pending authorization a provider redirected back with. Each
+/// `state` can only be used once, and only from the browser holding the
+/// matching cookie.
+pub async fn take_pending_authorization(
+    redis: &RedisClient,
+    provider: &OidcProvider,
+    state: &str,
+    headers: &HeaderMap,
+) -> Result<PendingAuthorization> {
+    let key = format!("oidc-state-{}", state);
+    let invalid = || AppError::Authorization("Invalid OAuth state".to_string());
+
+    let pending = redis.cache_take(&key).await?.ok_or_else(invalid)?;
+
+    let pending: PendingAuthorization = serde_json::from_str(&pending).map_err(|_| invalid())?;
+
+    if pending.provider != provider.name() {
+        return Err(invalid());
+    }
+
+    social_login_service::verify_binding(headers, &pending.nonce)?;
+
+    Ok(pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OidcClaimMapping;
+    use axum::{
+        Form, Json, Router,
+        extract::State,
+        http::{HeaderValue, StatusCode, header::COOKIE},
+        routing::{get, post},
+    };
+    use jsonwebtoken::{EncodingKey, Header, encode};
+    use ring::{
+        rand::SystemRandom,
+        signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair},
+    };
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    const CLIENT_ID: &str = "test-client";
+    const SUBJECT: &str = "user-123";
+
+    /// What the mock issuer remembers once a user has approved a sign-in.
+    struct Grant {
+        code: String,
+        nonce: String,
+        code_challenge: String,
+    }
+
+    /// A local OpenID provider: discovery, JWKS and a token endpoint issuing
+    /// ES256 ID tokens for codes handed out by `approve`.
+    struct MockIssuer {
+        issuer: String,
+        signing_key: EncodingKey,
+        jwks: Value,
+        grants: Mutex<Vec<Grant>>,
+    }
+
+    impl MockIssuer {
+        async fn start() -> Arc<Self> {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+            let rng = SystemRandom::new();
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+            let key_pair =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                    .unwrap();
+
+            // Uncompressed point: 0x04 || x || y
+            let public_key = key_pair.public_key().as_ref();
+            let encode_b64 =
+                |bytes: &[u8]| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+
+            let mock = Arc::new(Self {
+                issuer,
+                signing_key: EncodingKey::from_ec_der(pkcs8.as_ref()),
+                jwks: json!({
+                    "keys": [{
+                        "kty": "EC",
+                        "crv": "P-256",
+                        "kid": "test-key",
+                        "alg": "ES256",
+                        "use": "sig",
+                        "x": encode_b64(&public_key[1..33]),
+                        "y": encode_b64(&public_key[33..]),
+                    }]
+                }),
+                grants: Mutex::new(Vec::new()),
+            });
+
+            let app = Router::new()
+                .route("/.well-known/openid-configuration", get(discovery))
+                .route("/jwks", get(jwks))
+                .route("/token", post(token))
+                .with_state(mock.clone());
+
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+            mock
+        }
+
+        /// Play the user approving the sign-in at `authorization_url`, and
+        /// return the code the provider would redirect back with.
+        fn approve(&self, authorization_url: &str) -> String {
+            let url = Url::parse(authorization_url).unwrap();
+            let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+
+            assert!(authorization_url.starts_with(&format!("{}/authorize", self.issuer)));
+            assert_eq!(params["client_id"], CLIENT_ID);
+            assert_eq!(params["response_type"], "code");
+            assert_eq!(params["code_challenge_method"], "S256");
+
+            let code = random_token();
+            self.grants.lock().unwrap().push(Grant {
+                code: code.clone(),
+                nonce: params["nonce"].clone(),
+                code_challenge: params["code_challenge"].clone(),
+            });
+
+            code
+        }
+    }
+
+    async fn discovery(State(mock): State<Arc<MockIssuer>>) -> Json<Value> {
+        Json(json!({
+            "issuer": mock.issuer,
+            "authorization_endpoint": format!("{}/authorize", mock.issuer),
+            "token_endpoint": format!("{}/token", mock.issuer),
+            "jwks_uri": format!("{}/jwks", mock.issuer),
+        }))
+    }
+
+    async fn jwks(State(mock): State<Arc<MockIssuer>>) -> Json<Value> {
+        Json(mock.jwks.clone())
+    }
+
+    async fn token(
+        State(mock): State<Arc<MockIssuer>>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> std::result::Result<Json<Value>, StatusCode> {
+        let mut grants = mock.grants.lock().unwrap();
+
+        // Codes are single use
+        let position = grants
+            .iter()
+            .position(|grant| Some(&grant.code) == form.get("code"))
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        let grant = grants.remove(position);
+
+        let verifier = form.get("code_verifier").ok_or(StatusCode::BAD_REQUEST)?;
+        let challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(Sha256::digest(verifier.as_bytes()));
+        if challenge != grant.code_challenge
+            || form.get("client_id").map(String::as_str) != Some(CLIENT_ID)
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        let now = Utc::now().timestamp();
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some("test-key".to_string());
+
+        let id_token = encode(
+            &header,
+            &json!({
+                "iss": mock.issuer,
+                "aud": CLIENT_ID,
+                "sub": SUBJECT,
+                "email": "Person@Example.com",
+                "email_verified": true,
+                "name": "Test Person",
+                "nonce": grant.nonce,
+                "iat": now,
+                "exp": now + 300,
+            }),
+            &mock.signing_key,
+        )
+        .unwrap();
+
+        Ok(Json(json!({
+            "access_token": random_token(),
+            "token_type": "Bearer",
+            "id_token": id_token,
+        })))
+    }
+
+    fn provider(issuer: &str) -> OidcProvider {
+        let config = OidcProviderConfig {
+            name: "mock".to_string(),
+            display_name: "Mock".to_string(),
+            issuer: issuer.to_string(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: None,
+            scopes: vec!["openid".to_string(), "email".to_string()],
+            redirect_uri: None,
+            claims: OidcClaimMapping {
+                subject: "sub".to_string(),
+                email: "email".to_string(),
+                email_verified: "email_verified".to_string(),
+                name: "name".to_string(),
+                picture: "picture".to_string(),
+            },
+            trust_email: false,
+        };
+
+        OidcProvider::new(
+            config,
+            "http://localhost/api/auth/oauth/mock/callback".to_string(),
+        )
+        .unwrap()
+    }
+
+    fn pending(nonce: &str) -> PendingAuthorization {
+        PendingAuthorization {
+            provider: "mock".to_string(),
+            nonce: nonce.to_string(),
+            code_verifier: random_token(),
+            link_user_id: None,
+            invite_code: None,
+        }
+    }
+
+    /// The request headers of a browser holding the cookie from `set_cookie`.
+    fn browser(set_cookie: &str) -> HeaderMap {
+        let cookie = set_cookie.split(';').next().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_str(cookie).unwrap());
+        headers
+    }
+
+    async fn authorization_url(provider: &OidcProvider, pending: &PendingAuthorization) -> String {
+        let code_challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(Sha256::digest(pending.code_verifier.as_bytes()));
+
+        provider
+            .authorization_url("state", &pending.nonce, &code_challenge)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn signs_in_against_mock_issuer() {
+        let mock = MockIssuer::start().await;
+        let provider = provider(&mock.issuer);
+
+        let binding = social_login_service::bind_to_browser("mock", false, false);
+        let pending = pending(&binding.nonce);
+
+        let code = mock.approve(&authorization_url(&provider, &pending).await);
+
+        social_login_service::verify_binding(&browser(&binding.cookie), &pending.nonce).unwrap();
+        let profile = provider.authenticate(&code, &pending).await.unwrap();
+
+        assert_eq!(profile.provider, "mock");
+        assert_eq!(profile.subject, SUBJECT);
+        assert_eq!(profile.email.as_deref(), Some("person@example.com"));
+        assert!(profile.email_verified);
+        assert_eq!(profile.display_name.as_deref(), Some("Test Person"));
+    }
+
+    #[tokio::test]
+    async fn rejects_callback_from_another_browser() {
+        let started = social_login_service::bind_to_browser("mock", false, false);
+        let other = social_login_service::bind_to_browser("mock", false, false);
+
+        assert!(
+            social_login_service::verify_binding(&browser(&other.cookie), &started.nonce).is_err()
+        );
+        assert!(social_login_service::verify_binding(&HeaderMap::new(), &started.nonce).is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_code_issued_for_another_flow() {
+        let mock = MockIssuer::start().await;
+        let provider = provider(&mock.issuer);
+
+        // An attacker approves their own flow and plants the code in ours
+        let attacker = pending(&social_login_service::bind_to_browser("mock", false, false).nonce);
+        let code = mock.approve(&authorization_url(&provider, &attacker).await);
+
+        let mut victim =
+            pending(&social_login_service::bind_to_browser("mock", false, false).nonce);
+        victim.code_verifier = attacker.code_verifier.clone();
+
+        let result = provider.authenticate(&code, &victim).await;
+        assert!(matches!(result, Err(AppError::Authentication(_))));
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_code_verifier() {
+        let mock = MockIssuer::start().await;
+        let provider = provider(&mock.issuer);
+
+        let mut pending = pending(&random_token());
+        let code = mock.approve(&authorization_url(&provider, &pending).await);
+        pending.code_verifier = random_token();
+
+        let result = provider.authenticate(&code, &pending).await;
+        assert!(matches!(result, Err(AppError::Authentication(_))));
+    }
+
+    #[tokio::test]
+    async fn rejects_reused_code() {
+        let mock = MockIssuer::start().await;
+        let provider = provider(&mock.issuer);
+
+        let pending = pending(&random_token());
+        let code = mock.approve(&authorization_url(&provider, &pending).await);
+
+        provider.authenticate(&code, &pending).await.unwrap();
+
+        let result = provider.authenticate(&code, &pending).await;
+        assert!(matches!(result, Err(AppError::Authentication(_))));
+    }
+}
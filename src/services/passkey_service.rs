@@ -0,0 +1,581 @@
+use base64::Engine;
+use sqlx::{PgPool, types::Json};
+use std::error::Error;
+use uuid::Uuid;
+use webauthn_rs::prelude::*;
+
+use crate::{
+    AppState,
+    config::Config,
+    error::{AppError, Result},
+    models::{User, UserPasskey},
+    services::identity_service,
+};
+
+const CEREMONY_TTL_SECONDS: usize = 300;
+
+pub fn build_webauthn(config: &Config) -> std::result::Result<Webauthn, Box<dyn Error>> {
+    new_webauthn(
+        &config.webauthn_rp_id,
+        &config.webauthn_rp_origin,
+        &config.app_name,
+    )
+}
+
+fn new_webauthn(
+    rp_id: &str,
+    rp_origin: &str,
+    rp_name: &str,
+) -> std::result::Result<Webauthn, Box<dyn Error>> {
+    let rp_origin = Url::parse(rp_origin)?;
+    let webauthn = WebauthnBuilder::new(rp_id, &rp_origin)?
+        .rp_name(rp_name)
+        .build()?;
+
+    Ok(webauthn)
+}
+
+fn encode_credential_id(credential_id: &[u8]) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(credential_id)
+}
+
+pub async fn get_user_passkeys(db: &PgPool, user_id: Uuid) -> Result<Vec<UserPasskey>> {
+    let passkeys = sqlx::query_as::<_, UserPasskey>(
+        "SELECT * FROM user_passkeys WHERE user_id = $1 ORDER BY created_at",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(passkeys)
+}
+
+/// Begin registering a new passkey. The ceremony state is kept server side in
+/// Redis, keyed by user, until the authenticator's response comes back.
+pub async fn start_registration(
+    state: &AppState,
+    user: &User,
+) -> Result<CreationChallengeResponse> {
+    let existing = get_user_passkeys(&state.db, user.id).await?;
+    let exclude_credentials: Vec<CredentialID> = existing
+        .iter()
+        .map(|passkey| passkey.passkey.cred_id().clone())
+        .collect();
+
+    let display_name = user.display_name.as_deref().unwrap_or(&user.username);
+
+    let (challenge, registration) = state
+        .webauthn
+        .start_passkey_registration(
+            user.id,
+            &user.username,
+            display_name,
+            Some(exclude_credentials),
+        )
+        .map_err(|e| AppError::Internal(format!("Failed to start passkey registration: {}", e)))?;
+
+    let registration = serde_json::to_string(&registration)
+        .map_err(|e| AppError::Internal(format!("Failed to store passkey registration: {}", e)))?;
+
+    state
+        .redis
+        .cache_set(
+            &format!("passkey_registration:{}", user.id),
+            &registration,
+            CEREMONY_TTL_SECONDS,
+        )
+        .await?;
+
+    Ok(challenge)
+}
+
+pub async fn finish_registration(
+    state: &AppState,
+    user_id: Uuid,
+    name: &str,
+    credential: &RegisterPublicKeyCredential,
+) -> Result<UserPasskey> {
+    let key = format!("passkey_registration:{}", user_id);
+    let registration = state
+        .redis
+        .cache_take(&key)
+        .await?
+        .and_then(|value| serde_json::from_str::<PasskeyRegistration>(&value).ok())
+        .ok_or_else(|| AppError::BadRequest("No passkey registration in progress".to_string()))?;
+
+    let passkey = state
+        .webauthn
+        .finish_passkey_registration(credential, &registration)
+        .map_err(|e| AppError::BadRequest(format!("Passkey registration failed: {}", e)))?;
+
+    let credential_id = encode_credential_id(passkey.cred_id().as_ref());
+
+    let existing = sqlx::query("SELECT id FROM user_passkeys WHERE credential_id = $1")
+        .bind(&credential_id)
+        .fetch_optional(&state.db)
+        .await?;
+
+    if existing.is_some() {
+        return Err(AppError::Conflict(
+            "This passkey is already registered".to_string(),
+        ));
+    }
+
+    let passkey = sqlx::query_as::<_, UserPasskey>(
+        r#"
+        INSERT INTO user_passkeys (user_id, credential_id, name, passkey)
+        VALUES ($1, $2, $3, $4)
+        RETURNING *
+        "#,
+    )
+    .bind(user_id)
+    .bind(&credential_id)
+    .bind(name)
+    .bind(Json(passkey))
+    .fetch_one(&state.db)
+    .await?;
+
+    Ok(passkey)
+}
+
+/// Begin a passkey sign-in. Passkeys are discoverable, so no username is needed:
+/// the authenticator tells us which user and credential it used.
+pub async fn start_authentication(state: &AppState) -> Result<(String, RequestChallengeResponse)> {
+    let (challenge, authentication) = state
+        .webauthn
+        .start_discoverable_authentication()
+        .map_err(|e| AppError::Internal(format!("Failed to start passkey sign-in: {}", e)))?;
+
+    let authentication = serde_json::to_string(&authentication)
+        .map_err(|e| AppError::Internal(format!("Failed to store passkey sign-in: {}", e)))?;
+
+    let challenge_id = Uuid::new_v4().to_string();
+    state
+        .redis
+        .cache_set(
+            &format!("passkey_authentication:{}", challenge_id),
+            &authentication,
+            CEREMONY_TTL_SECONDS,
+        )
+        .await?;
+
+    Ok((challenge_id, challenge))
+}
+
+/// Verify a passkey assertion and return the user it belongs to.
+pub async fn finish_authentication(
+    state: &AppState,
+    challenge_id: &str,
+    credential: &PublicKeyCredential,
+) -> Result<Uuid> {
+    // Challenges are single use, whatever the outcome
+    let key = format!("passkey_authentication:{}", challenge_id);
+    let authentication = state
+        .redis
+        .cache_take(&key)
+        .await?
+        .and_then(|value| serde_json::from_str::<DiscoverableAuthentication>(&value).ok())
+        .ok_or_else(|| {
+            AppError::Authentication("Invalid or expired passkey challenge".to_string())
+        })?;
+
+    let (user_id, credential_id) = state
+        .webauthn
+        .identify_discoverable_authentication(credential)
+        .map_err(|_| AppError::Authentication("Passkey not recognised".to_string()))?;
+
+    let mut stored = sqlx::query_as::<_, UserPasskey>(
+        "SELECT * FROM user_passkeys WHERE user_id = $1 AND credential_id = $2",
+    )
+    .bind(user_id)
+    .bind(encode_credential_id(credential_id))
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::Authentication("Passkey not recognised".to_string()))?;
+
+    let result = state
+        .webauthn
+        .finish_discoverable_authentication(
+            credential,
+            authentication,
+            &[DiscoverableKey::from(&stored.passkey.0)],
+        )
+        .map_err(|_| AppError::Authentication("Passkey verification failed".to_string()))?;
+
+    // Persist the new signature counter / backup state if the authenticator changed it
+    let credential_changed = stored.passkey.0.update_credential(&result).unwrap_or(false);
+
+    sqlx::query(
+        r#"
+        UPDATE user_passkeys
+        SET last_used_at = NOW(), passkey = CASE WHEN $2 THEN $3 ELSE passkey END
+        WHERE id = $1
+        "#,
+    )
+    .bind(stored.id)
+    .bind(credential_changed)
+    .bind(&stored.passkey)
+    .execute(&state.db)
+    .await?;
+
+    Ok(stored.user_id)
+}
+
+pub async fn rename_passkey(
+    db: &PgPool,
+    user_id: Uuid,
+    passkey_id: Uuid,
+    name: &str,
+) -> Result<bool> {
+    let result = sqlx::query("UPDATE user_passkeys SET name = $1 WHERE id = $2 AND user_id = $3")
+        .bind(name)
+        .bind(passkey_id)
+        .bind(user_id)
+        .execute(db)
+        .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Delete a passkey. Returns false if the user has no such passkey; refuses to
+/// remove the account's last sign-in method.
+pub async fn delete_passkey(db: &PgPool, user_id: Uuid, passkey_id: Uuid) -> Result<bool> {
+    let mut tx = db.begin().await?;
+
+    let methods = identity_service::lock_sign_in_methods(&mut tx, user_id).await?;
+
+    let result = sqlx::query("DELETE FROM user_passkeys WHERE id = $1 AND user_id = $2")
+        .bind(passkey_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    if methods <= 1 {
+        return Err(identity_service::last_sign_in_method_error());
+    }
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use ring::{
+        rand::{SecureRandom, SystemRandom},
+        signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, KeyPair},
+    };
+    use serde::{Serialize, de::DeserializeOwned};
+    use serde_json::{Value, json};
+    use sha2::{Digest, Sha256};
+
+    const RP_ID: &str = "example.com";
+    const ORIGIN: &str = "https://example.com";
+    const OTHER_ORIGIN: &str = "https://attacker.test";
+
+    fn b64(bytes: &[u8]) -> String {
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    fn unb64(value: &Value) -> Vec<u8> {
+        URL_SAFE_NO_PAD
+            .decode(value.as_str().unwrap().trim_end_matches('='))
+            .unwrap()
+    }
+
+    /// Just enough CBOR for a COSE key and a "none" attestation object.
+    fn cbor_head(major: u8, len: usize, out: &mut Vec<u8>) {
+        match len {
+            0..24 => out.push(major << 5 | len as u8),
+            24..256 => out.extend([major << 5 | 24, len as u8]),
+            _ => {
+                out.push(major << 5 | 25);
+                out.extend((len as u16).to_be_bytes());
+            }
+        }
+    }
+
+    fn cbor_int(value: i64, out: &mut Vec<u8>) {
+        if value >= 0 {
+            cbor_head(0, value as usize, out);
+        } else {
+            cbor_head(1, (-1 - value) as usize, out);
+        }
+    }
+
+    fn cbor_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+        cbor_head(2, bytes.len(), out);
+        out.extend(bytes);
+    }
+
+    fn cbor_text(text: &str, out: &mut Vec<u8>) {
+        cbor_head(3, text.len(), out);
+        out.extend(text.as_bytes());
+    }
+
+    fn authenticator_data(rp_id: &Value, flags: u8, counter: u32) -> Vec<u8> {
+        let mut data = Sha256::digest(rp_id.as_str().unwrap().as_bytes()).to_vec();
+        data.push(flags);
+        data.extend(counter.to_be_bytes());
+        data
+    }
+
+    fn client_data(kind: &str, challenge: &Value, origin: &str) -> Vec<u8> {
+        serde_json::to_vec(&json!({
+            "type": kind,
+            "challenge": challenge,
+            "origin": origin,
+            "crossOrigin": false,
+        }))
+        .unwrap()
+    }
+
+    /// A software authenticator holding one discoverable P-256 credential. It
+    /// only sees the options JSON a browser would pass it.
+    struct SoftPasskey {
+        key_pair: EcdsaKeyPair,
+        credential_id: Vec<u8>,
+        user_handle: Vec<u8>,
+        counter: u32,
+    }
+
+    impl SoftPasskey {
+        fn register(
+            challenge: &CreationChallengeResponse,
+            origin: &str,
+        ) -> (Self, RegisterPublicKeyCredential) {
+            let options = serde_json::to_value(challenge).unwrap();
+            let options = &options["publicKey"];
+
+            let rng = SystemRandom::new();
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+            let key_pair =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+                    .unwrap();
+
+            let mut credential_id = vec![0u8; 16];
+            rng.fill(&mut credential_id).unwrap();
+
+            // EC2 key on P-256 for ES256, from the uncompressed point 0x04 || x || y
+            let public_key = key_pair.public_key().as_ref();
+            let mut cose_key = Vec::new();
+            cbor_head(5, 5, &mut cose_key);
+            for (label, value) in [(1, 2), (3, -7), (-1, 1)] {
+                cbor_int(label, &mut cose_key);
+                cbor_int(value, &mut cose_key);
+            }
+            cbor_int(-2, &mut cose_key);
+            cbor_bytes(&public_key[1..33], &mut cose_key);
+            cbor_int(-3, &mut cose_key);
+            cbor_bytes(&public_key[33..], &mut cose_key);
+
+            // User present, user verified, attested credential data included
+            let mut auth_data = authenticator_data(&options["rp"]["id"], 0x45, 0);
+            auth_data.extend([0u8; 16]);
+            auth_data.extend((credential_id.len() as u16).to_be_bytes());
+            auth_data.extend(&credential_id);
+            auth_data.extend(cose_key);
+
+            let mut attestation_object = Vec::new();
+            cbor_head(5, 3, &mut attestation_object);
+            cbor_text("fmt", &mut attestation_object);
+            cbor_text("none", &mut attestation_object);
+            cbor_text("attStmt", &mut attestation_object);
+            cbor_head(5, 0, &mut attestation_object);
+            cbor_text("authData", &mut attestation_object);
+            cbor_bytes(&auth_data, &mut attestation_object);
+
+            let client_data = client_data("webauthn.create", &options["challenge"], origin);
+
+            let credential = serde_json::from_value(json!({
+                "id": b64(&credential_id),
+                "rawId": b64(&credential_id),
+                "response": {
+                    "attestationObject": b64(&attestation_object),
+                    "clientDataJSON": b64(&client_data),
+                },
+                "type": "public-key",
+                "extensions": {},
+            }))
+            .unwrap();
+
+            let authenticator = Self {
+                key_pair,
+                credential_id,
+                user_handle: unb64(&options["user"]["id"]),
+                counter: 0,
+            };
+
+            (authenticator, credential)
+        }
+
+        fn sign_in(
+            &mut self,
+            challenge: &RequestChallengeResponse,
+            origin: &str,
+        ) -> PublicKeyCredential {
+            let options = serde_json::to_value(challenge).unwrap();
+            let options = &options["publicKey"];
+
+            self.counter += 1;
+
+            // User present, user verified
+            let auth_data = authenticator_data(&options["rpId"], 0x05, self.counter);
+            let client_data = client_data("webauthn.get", &options["challenge"], origin);
+
+            let mut signed = auth_data.clone();
+            signed.extend(Sha256::digest(&client_data));
+            let signature = self.key_pair.sign(&SystemRandom::new(), &signed).unwrap();
+
+            serde_json::from_value(json!({
+                "id": b64(&self.credential_id),
+                "rawId": b64(&self.credential_id),
+                "response": {
+                    "authenticatorData": b64(&auth_data),
+                    "clientDataJSON": b64(&client_data),
+                    "signature": b64(signature.as_ref()),
+                    "userHandle": b64(&self.user_handle),
+                },
+                "type": "public-key",
+                "extensions": {},
+            }))
+            .unwrap()
+        }
+    }
+
+    fn webauthn() -> Webauthn {
+        new_webauthn(RP_ID, ORIGIN, "Test").unwrap()
+    }
+
+    /// Ceremony state goes through Redis as JSON between the two requests.
+    fn stored<T: Serialize + DeserializeOwned>(state: &T) -> T {
+        serde_json::from_str(&serde_json::to_string(state).unwrap()).unwrap()
+    }
+
+    fn register(webauthn: &Webauthn, user_id: Uuid) -> (SoftPasskey, Passkey) {
+        let (challenge, registration) = webauthn
+            .start_passkey_registration(user_id, "tester", "Tester", None)
+            .unwrap();
+
+        let (authenticator, credential) = SoftPasskey::register(&challenge, ORIGIN);
+        let passkey = webauthn
+            .finish_passkey_registration(&credential, &stored(&registration))
+            .unwrap();
+
+        (authenticator, passkey)
+    }
+
+    #[test]
+    fn registers_and_signs_in_with_passkey() {
+        let webauthn = webauthn();
+        let user_id = Uuid::new_v4();
+        let (mut authenticator, mut passkey) = register(&webauthn, user_id);
+
+        assert_eq!(
+            encode_credential_id(passkey.cred_id().as_ref()),
+            b64(&authenticator.credential_id)
+        );
+
+        let (challenge, authentication) = webauthn.start_discoverable_authentication().unwrap();
+        let credential = authenticator.sign_in(&challenge, ORIGIN);
+
+        let (identified_user, credential_id) = webauthn
+            .identify_discoverable_authentication(&credential)
+            .unwrap();
+        assert_eq!(identified_user, user_id);
+        assert_eq!(credential_id, passkey.cred_id().as_ref());
+
+        let result = webauthn
+            .finish_discoverable_authentication(
+                &credential,
+                stored(&authentication),
+                &[DiscoverableKey::from(&passkey)],
+            )
+            .unwrap();
+
+        assert!(result.user_verified());
+        assert_eq!(passkey.update_credential(&result), Some(true));
+    }
+
+    #[test]
+    fn rejects_registration_from_another_origin() {
+        let webauthn = webauthn();
+        let (challenge, registration) = webauthn
+            .start_passkey_registration(Uuid::new_v4(), "tester", "Tester", None)
+            .unwrap();
+
+        let (_, credential) = SoftPasskey::register(&challenge, OTHER_ORIGIN);
+
+        assert!(
+            webauthn
+                .finish_passkey_registration(&credential, &registration)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_assertion_from_another_origin() {
+        let webauthn = webauthn();
+        let (mut authenticator, passkey) = register(&webauthn, Uuid::new_v4());
+
+        let (challenge, authentication) = webauthn.start_discoverable_authentication().unwrap();
+        let credential = authenticator.sign_in(&challenge, OTHER_ORIGIN);
+
+        assert!(
+            webauthn
+                .finish_discoverable_authentication(
+                    &credential,
+                    authentication,
+                    &[DiscoverableKey::from(&passkey)],
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_replayed_assertion() {
+        let webauthn = webauthn();
+        let (mut authenticator, mut passkey) = register(&webauthn, Uuid::new_v4());
+
+        let (challenge, authentication) = webauthn.start_discoverable_authentication().unwrap();
+        let credential = authenticator.sign_in(&challenge, ORIGIN);
+        let result = webauthn
+            .finish_discoverable_authentication(
+                &credential,
+                stored(&authentication),
+                &[DiscoverableKey::from(&passkey)],
+            )
+            .unwrap();
+        passkey.update_credential(&result);
+
+        // A captured assertion is bound to the challenge it answered
+        let (_, next_authentication) = webauthn.start_discoverable_authentication().unwrap();
+        assert!(
+            webauthn
+                .finish_discoverable_authentication(
+                    &credential,
+                    next_authentication,
+                    &[DiscoverableKey::from(&passkey)],
+                )
+                .is_err()
+        );
+
+        // and its signature counter is stale even against its own challenge
+        assert!(
+            webauthn
+                .finish_discoverable_authentication(
+                    &credential,
+                    stored(&authentication),
+                    &[DiscoverableKey::from(&passkey)],
+                )
+                .is_err()
+        );
+    }
+}
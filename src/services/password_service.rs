@@ -0,0 +1,110 @@
+use sha1::{Digest, Sha1};
+use std::{io::ErrorKind, path::Path};
+
+use crate::{
+    config::Config,
+    error::{AppError, Result},
+};
+
+/// Check a candidate password against the configured policy: length,
+/// required character classes, not containing the username or email, and not
+/// appearing in the local breached password list.
+pub async fn validate_password(
+    config: &Config,
+    password: &str,
+    username: Option<&str>,
+    email: Option<&str>,
+) -> Result<()> {
+    let mut problems = Vec::new();
+
+    if password.chars().count() < config.password_min_length {
+        problems.push(format!(
+            "must be at least {} characters",
+            config.password_min_length
+        ));
+    }
+
+    if config.password_require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+        problems.push("must contain an uppercase letter".to_string());
+    }
+
+    if config.password_require_lowercase && !password.chars().any(|c| c.is_lowercase()) {
+        problems.push("must contain a lowercase letter".to_string());
+    }
+
+    if config.password_require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+        problems.push("must contain a digit".to_string());
+    }
+
+    if config.password_require_symbol && password.chars().all(|c| c.is_alphanumeric()) {
+        problems.push("must contain a symbol".to_string());
+    }
+
+    let lowercase_password = password.to_lowercase();
+    let email_local_part = email.and_then(|email| email.split('@').next());
+
+    // Very short names would reject too many unrelated passwords
+    let contains_personal_info = [username, email_local_part]
+        .into_iter()
+        .flatten()
+        .map(|value| value.trim().to_lowercase())
+        .any(|value| value.chars().count() >= 3 && lowercase_password.contains(&value));
+
+    if contains_personal_info {
+        problems.push("must not contain your username or email".to_string());
+    }
+
+    if problems.is_empty() && is_breached(config, password).await {
+        problems.push(
+            "has appeared in a data breach and can't be used, please choose another".to_string(),
+        );
+    }
+
+    if !problems.is_empty() {
+        return Err(AppError::Validation(format!(
+            "password: {}",
+            problems.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// Look the password up in the breached password list, stored in the same
+/// k-anonymity layout as the Pwned Passwords range API: the uppercase SHA-1
+/// hex digest is split into a 5 character prefix naming the file and a 35
+/// character suffix listed in it as `SUFFIX:COUNT`. Nothing leaves the server.
+async fn is_breached(config: &Config, password: &str) -> bool {
+    let Some(dir) = &config.password_breach_list_dir else {
+        return false;
+    };
+
+    let digest = format!("{:X}", Sha1::digest(password.as_bytes()));
+    let (prefix, suffix) = digest.split_at(5);
+
+    for file_name in [prefix.to_string(), format!("{}.txt", prefix)] {
+        let path = Path::new(dir).join(file_name);
+
+        match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => {
+                return contents.lines().any(|line| {
+                    line.split(':')
+                        .next()
+                        .is_some_and(|entry| entry.trim().eq_ignore_ascii_case(suffix))
+                });
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => {
+                // Don't lock people out of setting a password because the list is unreadable
+                tracing::error!(
+                    "Failed to read breached password list {}: {}",
+                    path.display(),
+                    e
+                );
+                return false;
+            }
+        }
+    }
+
+    false
+}
@@ -270,6 +270,169 @@ pub async fn get_posts_count(
     let row = query_builder.fetch_one(db).await?;
     Ok(row.get::<i64, _>("count") as u32)
 }
+
+/// The viewer's home feed: posts from the communities they've joined and the
+/// users they follow, minus blocked authors and, unless they've opted in,
+/// NSFW posts.
+///
+/// Rather than filtering every post on the site, each joined community and
+/// followed author contributes only its own first `offset + limit` posts in
+/// the requested order (an index scan apiece), and those are merged. That
+/// keeps the cost proportional to the page being read even for users in
+/// hundreds of communities.
+pub async fn get_home_feed(
+    db: &PgPool,
+    user_id: Uuid,
+    sort: PostSort,
+    time_range: Option<TimeRange>,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<PostListResponse>> {
+    let show_nsfw = sqlx::query_scalar::<_, Option<bool>>(
+        "SELECT nsfw_content FROM user_preferences WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?
+    .flatten()
+    .unwrap_or(false);
+
+    let time_filter = match time_range {
+        Some(TimeRange::Hour) => "AND p.created_at >= NOW() - INTERVAL '1 hour'",
+        Some(TimeRange::Day) => "AND p.created_at >= NOW() - INTERVAL '1 day'",
+        Some(TimeRange::Week) => "AND p.created_at >= NOW() - INTERVAL '1 week'",
+        Some(TimeRange::Month) => "AND p.created_at >= NOW() - INTERVAL '1 month'",
+        Some(TimeRange::Year) => "AND p.created_at >= NOW() - INTERVAL '1 year'",
+        Some(TimeRange::All) | None => "",
+    };
+
+    let order_clause = match sort {
+        PostSort::Hot => "p.hot_score DESC, p.created_at DESC",
+        PostSort::New => "p.created_at DESC",
+        PostSort::Top => "p.score DESC, p.created_at DESC",
+        PostSort::Rising => "p.score DESC, p.created_at DESC",
+    };
+
+    // Applied inside each source so filtered-out posts don't eat into its share
+    let visibility_filter = r#"
+        AND ($2 OR (p.is_nsfw IS NOT TRUE AND c.is_nsfw IS NOT TRUE))
+        AND NOT EXISTS (
+            SELECT 1 FROM user_blocks b WHERE b.blocker_id = $1 AND b.blocked_id = p.author_id
+        )
+    "#;
+
+    let query = format!(
+        r#"
+        WITH feed AS (
+            SELECT src.*
+            FROM community_memberships cm
+            JOIN communities c ON c.id = cm.community_id AND c.status != 'banned'
+            CROSS JOIN LATERAL (
+                SELECT p.id, p.hot_score, p.score, p.created_at FROM posts p
+                WHERE p.community_id = cm.community_id AND p.status = 'active'
+                {time_filter} {visibility_filter}
+                ORDER BY {order_clause}
+                LIMIT $3
+            ) src
+            WHERE cm.user_id = $1
+            UNION
+            SELECT src.*
+            FROM user_follows f
+            CROSS JOIN LATERAL (
+                SELECT p.id, p.hot_score, p.score, p.created_at FROM posts p
+                JOIN communities c ON c.id = p.community_id
+                WHERE p.author_id = f.following_id AND p.status = 'active'
+                AND c.status != 'banned' AND c.community_type != 'private'
+                {time_filter} {visibility_filter}
+                ORDER BY {order_clause}
+                LIMIT $3
+            ) src
+            WHERE f.follower_id = $1
+        ),
+        page AS (
+            SELECT p.id FROM feed p
+            ORDER BY {order_clause}
+            LIMIT $4 OFFSET $5
+        )
+        SELECT
+            p.id, p.title, p.post_type, p.is_nsfw, p.is_spoiler, p.score,
+            p.comment_count, p.created_at,
+            u.id as author_id, u.username, u.display_name as user_display_name,
+            u.avatar_url, u.is_verified,
+            c.id as community_id, c.name as community_name,
+            c.display_name as community_display_name, c.icon_url as community_icon,
+            pv.vote_type as user_vote,
+            COALESCE(mv.cdn_url, mv.file_path) as thumbnail_url
+        FROM page
+        JOIN posts p ON p.id = page.id
+        JOIN users u ON p.author_id = u.id
+        JOIN communities c ON p.community_id = c.id
+        LEFT JOIN post_votes pv ON p.id = pv.post_id AND pv.user_id = $1
+        LEFT JOIN post_media pm ON p.id = pm.post_id AND pm.media_order = 1
+        LEFT JOIN media_files mf ON pm.media_file_id = mf.id
+        LEFT JOIN media_variants mv ON mf.id = mv.media_file_id AND mv.variant_type = 'thumbnail'
+        ORDER BY {order_clause}
+        "#
+    );
+
+    let rows = sqlx::query(&query)
+        .bind(user_id)
+        .bind(show_nsfw)
+        .bind((offset + limit) as i64)
+        .bind(limit as i64)
+        .bind(offset as i64)
+        .fetch_all(db)
+        .await?;
+
+    let mut posts = Vec::new();
+    for row in rows {
+        let flair = get_post_flair(db, row.get("id")).await?;
+
+        posts.push(PostListResponse {
+            id: row.get("id"),
+            title: row.get("title"),
+            post_type: row.get("post_type"),
+            is_nsfw: row.get("is_nsfw"),
+            is_spoiler: row.get("is_spoiler"),
+            author: PostAuthor {
+                id: row.get("author_id"),
+                username: row.get("username"),
+                display_name: row.get("user_display_name"),
+                avatar_url: row.get("avatar_url"),
+                is_verified: row.get("is_verified"),
+            },
+            community: PostCommunity {
+                id: row.get("community_id"),
+                name: row.get("community_name"),
+                display_name: row.get("community_display_name"),
+                icon_url: row.get("community_icon"),
+            },
+            score: row.get("score"),
+            comment_count: row.get("comment_count"),
+            created_at: row.get("created_at"),
+            user_vote: row.get("user_vote"),
+            thumbnail_url: row.get("thumbnail_url"),
+            flair,
+        });
+    }
+
+    Ok(posts)
+}
+
+/// Which of a user's posts the viewer (`$1`) may see on their profile (`$2`):
+/// posts in private communities only show to members and the author.
+pub const USER_POSTS_VISIBILITY: &str = r#"
+    AND c.status != 'banned'
+    AND (
+        c.community_type != 'private' OR $1 = $2
+        OR EXISTS (
+            SELECT 1 FROM community_memberships m
+            WHERE m.community_id = c.id AND m.user_id = $1
+        )
+    )
+"#;
+
+/// A user's posts as `viewer_id` may see them.
 pub async fn get_user_posts(
     db: &PgPool,
     author_id: Uuid,
@@ -301,6 +464,8 @@ pub async fn get_user_posts(
     "#
     .to_string();
 
+    query.push_str(USER_POSTS_VISIBILITY);
+
     // Add time range filter
     if let Some(time) = time_range {
         let time_filter = match time {
@@ -369,15 +534,96 @@ pub async fn get_user_posts(
     Ok(posts)
 }
 
-pub async fn get_user_posts_count(db: &PgPool, author_id: Uuid) -> Result<u32> {
-    let row = sqlx::query!(
-        "SELECT COUNT(*) as count FROM posts WHERE author_id = $1 AND status = 'active'",
-        author_id
+pub async fn get_user_posts_count(
+    db: &PgPool,
+    author_id: Uuid,
+    viewer_id: Option<Uuid>,
+) -> Result<u32> {
+    let query = format!(
+        r#"
+        SELECT COUNT(*) FROM posts p
+        JOIN communities c ON p.community_id = c.id
+        WHERE p.status = 'active' AND p.author_id = $2
+        {}
+        "#,
+        USER_POSTS_VISIBILITY
+    );
+
+    let count = sqlx::query_scalar::<_, i64>(&query)
+        .bind(viewer_id)
+        .bind(author_id)
+        .fetch_one(db)
+        .await?;
+
+    Ok(count as u32)
+}
+
+/// Posts by id for a list, in no particular order. Visibility is up to the
+/// caller.
+pub async fn get_posts_by_ids(
+    db: &PgPool,
+    post_ids: &[Uuid],
+    viewer_id: Option<Uuid>,
+) -> Result<Vec<PostListResponse>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            p.id, p.title, p.post_type, p.is_nsfw, p.is_spoiler, p.score,
+            p.comment_count, p.created_at,
+            u.id as author_id, u.username, u.display_name as user_display_name,
+            u.avatar_url, u.is_verified,
+            c.id as community_id, c.name as community_name,
+            c.display_name as community_display_name, c.icon_url as community_icon,
+            pv.vote_type as user_vote,
+            COALESCE(mv.cdn_url, mv.file_path) as thumbnail_url
+        FROM posts p
+        JOIN users u ON p.author_id = u.id
+        JOIN communities c ON p.community_id = c.id
+        LEFT JOIN post_votes pv ON p.id = pv.post_id AND pv.user_id = $1
+        LEFT JOIN post_media pm ON p.id = pm.post_id AND pm.media_order = 1
+        LEFT JOIN media_files mf ON pm.media_file_id = mf.id
+        LEFT JOIN media_variants mv ON mf.id = mv.media_file_id AND mv.variant_type = 'thumbnail'
+        WHERE p.id = ANY($2)
+        "#,
     )
-    .fetch_one(db)
+    .bind(viewer_id)
+    .bind(post_ids)
+    .fetch_all(db)
     .await?;
 
-    Ok(row.count.unwrap_or(0) as u32)
+    let mut posts = Vec::new();
+    for row in rows {
+        let flair = get_post_flair(db, row.get("id")).await?;
+
+        posts.push(PostListResponse {
+            id: row.get("id"),
+            title: row.get("title"),
+            post_type: row.get("post_type"),
+            is_nsfw: row.get("is_nsfw"),
+            is_spoiler: row.get("is_spoiler"),
+            author: PostAuthor {
+                id: row.get("author_id"),
+                username: row.get("username"),
+                display_name: row.get("user_display_name"),
+                avatar_url: row.get("avatar_url"),
+                is_verified: row.get("is_verified"),
+            },
+            community: PostCommunity {
+                id: row.get("community_id"),
+                name: row.get("community_name"),
+                display_name: row.get("community_display_name"),
+                icon_url: row.get("community_icon"),
+            },
+            score: row.get("score"),
+            comment_count: row.get("comment_count"),
+            created_at: row.get("created_at"),
+            user_vote: row.get("user_vote"),
+            thumbnail_url: row.get("thumbnail_url"),
+            flair,
+        });
+    }
+
+    Ok(posts)
 }
 
 pub async fn get_saved_posts(
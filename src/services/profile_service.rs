@@ -0,0 +1,315 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, Result},
+    models::{PostListResponse, ProfileSort, TimeRange, User, UserCommentResponse},
+    services::{
+        comment_service, post_service, post_service::USER_POSTS_VISIBILITY, user_service,
+        username_service, username_service::ResolvedUser,
+    },
+};
+
+/// What a user shares on their profile with everyone else.
+#[derive(Debug, Clone, Copy, Serialize, FromRow)]
+pub struct ProfilePrivacy {
+    pub show_profile_posts: bool,
+    pub show_profile_comments: bool,
+    /// Keeps both the follower and following lists to the user themselves
+    pub hide_followers: bool,
+}
+
+impl Default for ProfilePrivacy {
+    fn default() -> Self {
+        Self {
+            show_profile_posts: true,
+            show_profile_comments: true,
+            hide_followers: false,
+        }
+    }
+}
+
+/// A user's profile as one particular viewer sees it.
+#[derive(Debug)]
+pub struct Profile {
+    pub user: User,
+    pub privacy: ProfilePrivacy,
+    /// The viewer is the profile's owner
+    pub is_own: bool,
+    /// The viewer has blocked the profile's owner
+    pub viewer_blocked: bool,
+    /// The profile was looked up by a name its owner has since changed away
+    /// from
+    pub renamed: bool,
+}
+
+impl Profile {
+    pub fn can_view_posts(&self) -> bool {
+        self.is_own || (!self.viewer_blocked && self.privacy.show_profile_posts)
+    }
+
+    pub fn can_view_comments(&self) -> bool {
+        self.is_own || (!self.viewer_blocked && self.privacy.show_profile_comments)
+    }
+
+    pub fn can_view_followers(&self) -> bool {
+        self.is_own || (!self.viewer_blocked && !self.privacy.hide_followers)
+    }
+
+    pub fn ensure_can_view_posts(&self) -> Result<()> {
+        self.ensure_not_blocked()?;
+
+        if !self.can_view_posts() {
+            return Err(AppError::Authorization(
+                "This user's posts are private".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn ensure_can_view_comments(&self) -> Result<()> {
+        self.ensure_not_blocked()?;
+
+        if !self.can_view_comments() {
+            return Err(AppError::Authorization(
+                "This user's comments are private".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn ensure_can_view_activity(&self) -> Result<()> {
+        self.ensure_not_blocked()?;
+
+        if !self.can_view_posts() && !self.can_view_comments() {
+            return Err(AppError::Authorization(
+                "This user's activity is private".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn ensure_can_view_followers(&self) -> Result<()> {
+        self.ensure_not_blocked()?;
+
+        if !self.can_view_followers() {
+            return Err(AppError::Authorization(
+                "This user's followers are private".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn ensure_not_blocked(&self) -> Result<()> {
+        if self.viewer_blocked && !self.is_own {
+            return Err(AppError::Authorization(
+                "You have blocked this user".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Awards a user has received, one entry per kind of award.
+#[derive(Debug, Serialize, FromRow)]
+pub struct Trophy {
+    pub award_id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub icon_url: Option<String>,
+    pub count: i64,
+    pub last_received_at: Option<DateTime<Utc>>,
+}
+
+/// An entry in a profile's overview.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind")]
+pub enum ProfileItem {
+    Post(PostListResponse),
+    Comment(UserCommentResponse),
+}
+
+/// Look up a profile for `viewer_id`, by the owner's current or a previous
+/// username. A user who has blocked the viewer is reported as not found, so
+/// blocks can't be detected from the outside.
+pub async fn get_profile(db: &PgPool, username: &str, viewer_id: Option<Uuid>) -> Result<Profile> {
+    let ResolvedUser { user, renamed } = username_service::resolve(db, username)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    let is_own = viewer_id == Some(user.id);
+    let mut viewer_blocked = false;
+
+    if let Some(viewer_id) = viewer_id.filter(|_| !is_own) {
+        if user_service::is_blocked(db, user.id, viewer_id).await? {
+            return Err(AppError::NotFound("User not found".to_string()));
+        }
+
+        viewer_blocked = user_service::is_blocked(db, viewer_id, user.id).await?;
+    }
+
+    let privacy = get_privacy(db, user.id).await?;
+
+    Ok(Profile {
+        user,
+        privacy,
+        is_own,
+        viewer_blocked,
+        renamed,
+    })
+}
+
+pub async fn get_privacy(db: &PgPool, user_id: Uuid) -> Result<ProfilePrivacy> {
+    let privacy = sqlx::query_as::<_, ProfilePrivacy>(
+        r#"
+        SELECT show_profile_posts, show_profile_comments, hide_followers
+        FROM user_preferences WHERE user_id = $1
+        "#,
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?;
+
+    Ok(privacy.unwrap_or_default())
+}
+
+/// The user's trophy case, most received first.
+pub async fn get_trophies(db: &PgPool, user_id: Uuid) -> Result<Vec<Trophy>> {
+    let trophies = sqlx::query_as::<_, Trophy>(
+        r#"
+        SELECT
+            a.id as award_id, a.name, a.description, a.icon_url,
+            COUNT(*) as count, MAX(ua.created_at) as last_received_at
+        FROM user_awards ua
+        JOIN awards a ON ua.award_id = a.id
+        WHERE ua.recipient_id = $1
+        GROUP BY a.id
+        ORDER BY count DESC, a.name
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(trophies)
+}
+
+/// A profile's posts and comments merged into one list, leaving out whichever
+/// the viewer isn't allowed to see. Like the home feed, each side only reads
+/// its own first `offset + limit` entries before they're merged.
+pub async fn get_overview(
+    db: &PgPool,
+    profile: &Profile,
+    viewer_id: Option<Uuid>,
+    sort: ProfileSort,
+    time_range: Option<TimeRange>,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<ProfileItem>> {
+    let interval = match time_range {
+        Some(TimeRange::Hour) => Some("1 hour"),
+        Some(TimeRange::Day) => Some("1 day"),
+        Some(TimeRange::Week) => Some("1 week"),
+        Some(TimeRange::Month) => Some("1 month"),
+        Some(TimeRange::Year) => Some("1 year"),
+        Some(TimeRange::All) | None => None,
+    };
+
+    let time_filter = |alias: &str| match interval {
+        Some(interval) => format!(
+            "AND {}.created_at >= NOW() - INTERVAL '{}'",
+            alias, interval
+        ),
+        None => String::new(),
+    };
+
+    let order_clause = |alias: &str| match sort {
+        ProfileSort::New => format!("{0}.created_at DESC", alias),
+        ProfileSort::Top => format!("{0}.score DESC, {0}.created_at DESC", alias),
+    };
+
+    let query = format!(
+        r#"
+        WITH items AS (
+            (
+                SELECT 'post' as kind, p.id, p.score, p.created_at
+                FROM posts p
+                JOIN communities c ON p.community_id = c.id
+                WHERE $3 AND p.author_id = $2 AND p.status = 'active'
+                {post_time_filter} {visibility}
+                ORDER BY {post_order}
+                LIMIT $5
+            )
+            UNION ALL
+            (
+                SELECT 'comment' as kind, cm.id, cm.score, cm.created_at
+                FROM comments cm
+                JOIN posts p ON cm.post_id = p.id
+                JOIN communities c ON p.community_id = c.id
+                WHERE $4 AND cm.author_id = $2 AND cm.status = 'active'
+                {comment_time_filter} {visibility}
+                ORDER BY {comment_order}
+                LIMIT $5
+            )
+        )
+        SELECT kind, id FROM items i
+        ORDER BY {item_order}
+        LIMIT $6 OFFSET $7
+        "#,
+        post_time_filter = time_filter("p"),
+        comment_time_filter = time_filter("cm"),
+        visibility = USER_POSTS_VISIBILITY,
+        post_order = order_clause("p"),
+        comment_order = order_clause("cm"),
+        item_order = order_clause("i"),
+    );
+
+    let page = sqlx::query_as::<_, (String, Uuid)>(&query)
+        .bind(viewer_id)
+        .bind(profile.user.id)
+        .bind(profile.can_view_posts())
+        .bind(profile.can_view_comments())
+        .bind((offset + limit) as i64)
+        .bind(limit as i64)
+        .bind(offset as i64)
+        .fetch_all(db)
+        .await?;
+
+    let (post_ids, comment_ids): (Vec<_>, Vec<_>) =
+        page.iter().partition(|(kind, _)| kind == "post");
+    let post_ids: Vec<Uuid> = post_ids.into_iter().map(|(_, id)| *id).collect();
+    let comment_ids: Vec<Uuid> = comment_ids.into_iter().map(|(_, id)| *id).collect();
+
+    let mut posts: HashMap<Uuid, PostListResponse> =
+        post_service::get_posts_by_ids(db, &post_ids, viewer_id)
+            .await?
+            .into_iter()
+            .map(|post| (post.id, post))
+            .collect();
+
+    let mut comments: HashMap<Uuid, UserCommentResponse> =
+        comment_service::get_user_comments_by_ids(db, &comment_ids, viewer_id)
+            .await?
+            .into_iter()
+            .map(|comment| (comment.comment.id, comment))
+            .collect();
+
+    // Anything deleted between the two queries is just left out
+    let items = page
+        .into_iter()
+        .filter_map(|(kind, id)| match kind.as_str() {
+            "post" => posts.remove(&id).map(ProfileItem::Post),
+            _ => comments.remove(&id).map(ProfileItem::Comment),
+        })
+        .collect();
+
+    Ok(items)
+}
@@ -0,0 +1,473 @@
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool, types::ipnetwork::IpNetwork};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    auth::{Claims, ClientInfo, hash_token},
+    error::{AppError, Result},
+    models::{User, UserSession},
+    services::{oauth_provider_service::OAuthClient, suspension_service},
+};
+
+#[derive(Debug, Serialize)]
+pub struct SessionTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
+}
+
+/// Who a session was issued to when it isn't the user's own: a third-party
+/// application, limited to the scopes the user granted it.
+#[derive(Debug, Clone)]
+pub struct Delegation {
+    pub oauth_client_id: Uuid,
+    pub client_id: String,
+    pub scopes: Vec<String>,
+}
+
+/// A signed-in device as shown to the user. Rotations within a refresh token
+/// family are collapsed, so `id` is the family id.
+#[derive(Debug, Serialize, FromRow)]
+pub struct ActiveSession {
+    pub id: Uuid,
+    pub device_label: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub is_current: bool,
+}
+
+/// Rough human-readable device description from a user agent, e.g. "Chrome on Windows".
+pub fn device_label(user_agent: Option<&str>) -> Option<String> {
+    let ua = user_agent?;
+
+    let os = if ua.contains("iPhone") || ua.contains("iPad") {
+        "iOS"
+    } else if ua.contains("Android") {
+        "Android"
+    } else if ua.contains("Windows") {
+        "Windows"
+    } else if ua.contains("Mac OS X") || ua.contains("Macintosh") {
+        "macOS"
+    } else if ua.contains("Linux") {
+        "Linux"
+    } else {
+        return Some(ua.chars().take(100).collect());
+    };
+
+    // Order matters: Edge and Opera UAs also contain "Chrome", Chrome UAs contain "Safari"
+    let browser = if ua.contains("Edg/") {
+        Some("Edge")
+    } else if ua.contains("OPR/") {
+        Some("Opera")
+    } else if ua.contains("Firefox/") || ua.contains("FxiOS/") {
+        Some("Firefox")
+    } else if ua.contains("Chrome/") || ua.contains("CriOS/") {
+        Some("Chrome")
+    } else if ua.contains("Safari/") {
+        Some("Safari")
+    } else {
+        None
+    };
+
+    Some(match browser {
+        Some(browser) => format!("{} on {}", browser, os),
+        None => os.to_string(),
+    })
+}
+
+fn generate_refresh_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Start a new session (and refresh token family) for a freshly authenticated user.
+pub async fn create_session(
+    state: &AppState,
+    user: &User,
+    client: &ClientInfo,
+) -> Result<SessionTokens> {
+    issue_tokens(state, user, Uuid::new_v4(), client, None).await
+}
+
+/// Start a session for a third-party application the user has authorized.
+pub async fn create_delegated_session(
+    state: &AppState,
+    user: &User,
+    client: &ClientInfo,
+    delegation: &Delegation,
+) -> Result<SessionTokens> {
+    issue_tokens(state, user, Uuid::new_v4(), client, Some(delegation)).await
+}
+
+async fn issue_tokens(
+    state: &AppState,
+    user: &User,
+    family_id: Uuid,
+    client: &ClientInfo,
+    delegation: Option<&Delegation>,
+) -> Result<SessionTokens> {
+    let access_ttl = Duration::minutes(state.config.access_token_ttl_minutes);
+    let refresh_ttl = Duration::days(state.config.refresh_token_ttl_days);
+
+    let (access_token, claims) = Claims::new_delegated(
+        user.id,
+        user.username.clone(),
+        delegation.map(|delegation| (delegation.client_id.as_str(), delegation.scopes.as_slice())),
+        &state.signing_keys,
+        access_ttl,
+    )?;
+    let refresh_token = generate_refresh_token();
+
+    sqlx::query(
+        r#"
+        INSERT INTO user_sessions (
+            user_id, token_jti, refresh_token_hash, family_id, expires_at,
+            device_label, ip_address, user_agent, oauth_client_id, scopes
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        "#,
+    )
+    .bind(user.id)
+    .bind(&claims.jti)
+    .bind(hash_token(&refresh_token))
+    .bind(family_id)
+    .bind(Utc::now() + refresh_ttl)
+    .bind(device_label(client.user_agent.as_deref()))
+    .bind(client.ip_address.map(IpNetwork::from))
+    .bind(&client.user_agent)
+    .bind(delegation.map(|delegation| delegation.oauth_client_id))
+    .bind(delegation.map(|delegation| &delegation.scopes))
+    .execute(&state.db)
+    .await?;
+
+    state
+        .redis
+        .store_session(
+            &claims.jti,
+            &user.id.to_string(),
+            access_ttl.num_seconds() as usize,
+        )
+        .await?;
+
+    Ok(SessionTokens {
+        access_token,
+        refresh_token,
+        expires_in: access_ttl.num_seconds(),
+    })
+}
+
+/// Exchange a refresh token for a new access/refresh pair. Each refresh token
+/// is single use; presenting one that was already rotated means it leaked, so
+/// the whole family is revoked. Application refresh tokens are only accepted
+/// from the application they were issued to (`oauth_client`), and the user's
+/// own only without one.
+pub async fn refresh_session(
+    state: &AppState,
+    refresh_token: &str,
+    client: &ClientInfo,
+    oauth_client: Option<&OAuthClient>,
+) -> Result<(User, SessionTokens)> {
+    let session = sqlx::query_as::<_, UserSession>(
+        "SELECT * FROM user_sessions WHERE refresh_token_hash = $1",
+    )
+    .bind(hash_token(refresh_token))
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::Authentication("Invalid refresh token".to_string()))?;
+
+    if session.oauth_client_id != oauth_client.map(|oauth_client| oauth_client.id) {
+        return Err(AppError::Authentication(
+            "Invalid refresh token".to_string(),
+        ));
+    }
+
+    let user =
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1 AND status != 'deleted'")
+            .bind(session.user_id)
+            .fetch_optional(&state.db)
+            .await?;
+
+    let Some(user) = user else {
+        revoke_family(state, session.family_id).await?;
+        return Err(AppError::Authentication("User not found".to_string()));
+    };
+
+    // Ahead of the revocation check, so a suspended user is told why they were signed out
+    suspension_service::ensure_not_suspended(state, &user).await?;
+
+    if session.revoked_at.is_some() {
+        return Err(AppError::Authentication(
+            "Refresh token has been revoked".to_string(),
+        ));
+    }
+
+    if session.rotated_at.is_some() {
+        return Err(handle_refresh_token_reuse(state, &session).await);
+    }
+
+    if session.expires_at <= Utc::now() {
+        return Err(AppError::Authentication(
+            "Refresh token expired".to_string(),
+        ));
+    }
+
+    // Conditional update so two concurrent refreshes can't both rotate the same token
+    let rotated = sqlx::query(
+        "UPDATE user_sessions SET rotated_at = NOW() WHERE id = $1 AND rotated_at IS NULL",
+    )
+    .bind(session.id)
+    .execute(&state.db)
+    .await?;
+
+    if rotated.rows_affected() == 0 {
+        return Err(handle_refresh_token_reuse(state, &session).await);
+    }
+
+    // The access token paired with the rotated refresh token is retired with it
+    state.redis.delete_session(&session.token_jti).await?;
+
+    let delegation = oauth_client.map(|oauth_client| Delegation {
+        oauth_client_id: oauth_client.id,
+        client_id: oauth_client.client_id.clone(),
+        scopes: session.scopes.clone().unwrap_or_default(),
+    });
+
+    let tokens = issue_tokens(state, &user, session.family_id, client, delegation.as_ref()).await?;
+
+    Ok((user, tokens))
+}
+
+async fn handle_refresh_token_reuse(state: &AppState, session: &UserSession) -> AppError {
+    tracing::warn!(
+        "Refresh token reuse detected for user {} (session family {}), revoking family",
+        session.user_id,
+        session.family_id
+    );
+
+    if let Err(e) = revoke_family(state, session.family_id).await {
+        tracing::error!(
+            "Failed to revoke session family {}: {}",
+            session.family_id,
+            e
+        );
+    }
+
+    AppError::Authentication("Refresh token has already been used".to_string())
+}
+
+/// Revoke every session in a refresh token family and drop their access tokens.
+pub async fn revoke_family(state: &AppState, family_id: Uuid) -> Result<()> {
+    let jtis = sqlx::query_scalar::<_, String>(
+        r#"
+        UPDATE user_sessions
+        SET revoked_at = NOW()
+        WHERE family_id = $1 AND revoked_at IS NULL
+        RETURNING token_jti
+        "#,
+    )
+    .bind(family_id)
+    .fetch_all(&state.db)
+    .await?;
+
+    for jti in jtis {
+        state.redis.delete_session(&jti).await?;
+    }
+
+    Ok(())
+}
+
+/// Revoke the session an access token belongs to, including its refresh token.
+pub async fn revoke_session_by_jti(state: &AppState, jti: &str) -> Result<()> {
+    let family_id =
+        sqlx::query_scalar::<_, Uuid>("SELECT family_id FROM user_sessions WHERE token_jti = $1")
+            .bind(jti)
+            .fetch_optional(&state.db)
+            .await?;
+
+    match family_id {
+        Some(family_id) => revoke_family(state, family_id).await,
+        None => state.redis.delete_session(jti).await,
+    }
+}
+
+/// Revoke every session a user has, e.g. after a password reset.
+pub async fn revoke_all_sessions(state: &AppState, user_id: Uuid) -> Result<()> {
+    let jtis = sqlx::query_scalar::<_, String>(
+        r#"
+        UPDATE user_sessions
+        SET revoked_at = NOW()
+        WHERE user_id = $1 AND revoked_at IS NULL
+        RETURNING token_jti
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(&state.db)
+    .await?;
+
+    for jti in jtis {
+        state.redis.delete_session(&jti).await?;
+    }
+
+    Ok(())
+}
+
+/// Revoke every session an application holds for a user, e.g. when the user
+/// removes its access.
+pub async fn revoke_client_sessions(
+    state: &AppState,
+    user_id: Uuid,
+    oauth_client_id: Uuid,
+) -> Result<()> {
+    let jtis = sqlx::query_scalar::<_, String>(
+        r#"
+        UPDATE user_sessions
+        SET revoked_at = NOW()
+        WHERE user_id = $1 AND oauth_client_id = $2 AND revoked_at IS NULL
+        RETURNING token_jti
+        "#,
+    )
+    .bind(user_id)
+    .bind(oauth_client_id)
+    .fetch_all(&state.db)
+    .await?;
+
+    for jti in jtis {
+        state.redis.delete_session(&jti).await?;
+    }
+
+    Ok(())
+}
+
+/// Record that an access token was used. Throttled to one write per few
+/// minutes per session so authenticated requests don't each hit the database.
+pub async fn touch_session(db: &PgPool, jti: &str) -> Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE user_sessions
+        SET last_used_at = NOW()
+        WHERE token_jti = $1
+        AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '5 minutes')
+        "#,
+    )
+    .bind(jti)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// The user's live sessions, one per refresh token family, most recently used
+/// first. Sessions held by applications are listed under authorized apps instead.
+pub async fn list_sessions(
+    db: &PgPool,
+    user_id: Uuid,
+    current_jti: &str,
+) -> Result<Vec<ActiveSession>> {
+    let sessions = sqlx::query_as::<_, ActiveSession>(
+        r#"
+        SELECT
+            s.family_id AS id,
+            s.device_label,
+            host(s.ip_address) AS ip_address,
+            s.user_agent,
+            (SELECT MIN(f.created_at) FROM user_sessions f WHERE f.family_id = s.family_id) AS created_at,
+            s.last_used_at,
+            s.token_jti = $2 AS is_current
+        FROM user_sessions s
+        WHERE s.user_id = $1
+        AND s.oauth_client_id IS NULL
+        AND s.rotated_at IS NULL
+        AND s.revoked_at IS NULL
+        AND s.expires_at > NOW()
+        ORDER BY s.last_used_at DESC NULLS LAST
+        "#,
+    )
+    .bind(user_id)
+    .bind(current_jti)
+    .fetch_all(db)
+    .await?;
+
+    Ok(sessions)
+}
+
+/// Revoke one of the user's sessions by family id. Returns false if the user
+/// has no such session.
+pub async fn revoke_user_session(state: &AppState, user_id: Uuid, family_id: Uuid) -> Result<bool> {
+    let owned = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM user_sessions WHERE family_id = $1 AND user_id = $2)",
+    )
+    .bind(family_id)
+    .bind(user_id)
+    .fetch_one(&state.db)
+    .await?;
+
+    if !owned {
+        return Ok(false);
+    }
+
+    revoke_family(state, family_id).await?;
+
+    Ok(true)
+}
+
+/// Revoke every session except the one the given access token belongs to.
+/// Third-party app sessions are left alone; they're managed as authorized apps.
+pub async fn revoke_other_sessions(
+    state: &AppState,
+    user_id: Uuid,
+    current_jti: &str,
+) -> Result<u64> {
+    let revoked_sessions = sqlx::query_as::<_, (String, bool)>(
+        r#"
+        UPDATE user_sessions
+        SET revoked_at = NOW()
+        WHERE user_id = $1
+        AND oauth_client_id IS NULL
+        AND revoked_at IS NULL
+        AND expires_at > NOW()
+        AND family_id IS DISTINCT FROM (
+            SELECT family_id FROM user_sessions WHERE token_jti = $2
+        )
+        RETURNING token_jti, rotated_at IS NULL AS is_live
+        "#,
+    )
+    .bind(user_id)
+    .bind(current_jti)
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut revoked = 0;
+    for (jti, is_live) in revoked_sessions {
+        state.redis.delete_session(&jti).await?;
+        if is_live {
+            revoked += 1;
+        }
+    }
+
+    Ok(revoked)
+}
+
+/// Whether the session an access token belongs to was signed into within
+/// `within`, i.e. the user authenticated recently rather than via an old
+/// refreshed session.
+pub async fn is_recent_sign_in(db: &PgPool, jti: &str, within: Duration) -> Result<bool> {
+    let recent = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT COALESCE(MIN(f.created_at) > $2, false)
+        FROM user_sessions s
+        JOIN user_sessions f ON f.family_id = s.family_id
+        WHERE s.token_jti = $1
+        "#,
+    )
+    .bind(jti)
+    .bind(Utc::now() - within)
+    .fetch_one(db)
+    .await?;
+
+    Ok(recent)
+}
@@ -0,0 +1,289 @@
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, decode_header, encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    },
+};
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use sqlx::{FromRow, PgConnection, PgPool};
+use std::sync::RwLock;
+use uuid::Uuid;
+
+use crate::error::{AppError, Result};
+
+/// New keys are published this long before they start signing, so every
+/// instance and every JWKS consumer has fetched them before tokens use them.
+const KEY_PUBLISH_DELAY_MINUTES: i64 = 15;
+
+/// Advisory lock id serialising key creation across instances.
+const KEY_ROTATION_LOCK_ID: i64 = 0x6a77_6b73;
+
+#[derive(Debug, FromRow)]
+struct SigningKeyRow {
+    id: Uuid,
+    private_key: Vec<u8>,
+    public_key: String,
+    activates_at: DateTime<Utc>,
+    retires_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+struct LoadedKey {
+    kid: String,
+    activates_at: DateTime<Utc>,
+    retires_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    jwk: Jwk,
+}
+
+impl LoadedKey {
+    fn from_row(row: SigningKeyRow) -> Result<Self> {
+        let kid = row.id.to_string();
+
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(KeyAlgorithm::EdDSA),
+                key_id: Some(kid.clone()),
+                ..Default::default()
+            },
+            algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: row.public_key.clone(),
+            }),
+        };
+
+        Ok(Self {
+            kid,
+            activates_at: row.activates_at,
+            retires_at: row.retires_at,
+            expires_at: row.expires_at,
+            encoding_key: EncodingKey::from_ed_der(&row.private_key),
+            decoding_key: DecodingKey::from_ed_components(&row.public_key)?,
+            jwk,
+        })
+    }
+
+    fn can_sign(&self, now: DateTime<Utc>) -> bool {
+        self.activates_at <= now && self.retires_at.is_none_or(|retires_at| retires_at > now)
+    }
+
+    fn can_verify(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
+
+/// In-memory copy of the access token signing keys, refreshed from the
+/// database by a background job so every instance sees rotations.
+pub struct SigningKeys {
+    // Newest activation first
+    keys: RwLock<Vec<LoadedKey>>,
+}
+
+impl SigningKeys {
+    pub async fn load(db: &PgPool) -> Result<Self> {
+        let signing_keys = Self {
+            keys: RwLock::new(Vec::new()),
+        };
+        signing_keys.reload(db).await?;
+
+        Ok(signing_keys)
+    }
+
+    pub async fn reload(&self, db: &PgPool) -> Result<()> {
+        let rows = sqlx::query_as::<_, SigningKeyRow>(
+            r#"
+            SELECT id, private_key, public_key, activates_at, retires_at, expires_at
+            FROM jwt_signing_keys
+            WHERE expires_at IS NULL OR expires_at > NOW()
+            ORDER BY activates_at DESC
+            "#,
+        )
+        .fetch_all(db)
+        .await?;
+
+        let keys = rows
+            .into_iter()
+            .map(LoadedKey::from_row)
+            .collect::<Result<Vec<_>>>()?;
+
+        *self.keys.write().expect("signing key lock poisoned") = keys;
+
+        Ok(())
+    }
+
+    /// Sign claims with the current key, recording its id in the `kid` header.
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String> {
+        let keys = self.keys.read().expect("signing key lock poisoned");
+        let now = Utc::now();
+
+        let key = keys
+            .iter()
+            .find(|key| key.can_sign(now))
+            .ok_or_else(|| AppError::Internal("No active token signing key".to_string()))?;
+
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(key.kid.clone());
+
+        Ok(encode(&header, claims, &key.encoding_key)?)
+    }
+
+    /// Verify a token against the key named by its `kid` header.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<T> {
+        let kid = decode_header(token)?
+            .kid
+            .ok_or_else(|| AppError::Authentication("Invalid token".to_string()))?;
+
+        let keys = self.keys.read().expect("signing key lock poisoned");
+        let now = Utc::now();
+
+        let key = keys
+            .iter()
+            .find(|key| key.kid == kid && key.can_verify(now))
+            .ok_or_else(|| AppError::Authentication("Invalid token".to_string()))?;
+
+        let token_data = decode::<T>(token, &key.decoding_key, &Validation::new(Algorithm::EdDSA))?;
+
+        Ok(token_data.claims)
+    }
+
+    /// Public keys other services can verify our tokens with, including keys
+    /// that are about to be activated and retired keys still in their grace window.
+    pub fn jwks(&self) -> JwkSet {
+        let keys = self.keys.read().expect("signing key lock poisoned");
+        let now = Utc::now();
+
+        JwkSet {
+            keys: keys
+                .iter()
+                .filter(|key| key.can_verify(now))
+                .map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+}
+
+fn generate_key_pair() -> Result<(Vec<u8>, String)> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| AppError::Internal("Failed to generate signing key".to_string()))?;
+    let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+        .map_err(|_| AppError::Internal("Failed to generate signing key".to_string()))?;
+
+    let public_key =
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref());
+
+    Ok((pkcs8.as_ref().to_vec(), public_key))
+}
+
+async fn insert_key(conn: &mut PgConnection, activates_at: DateTime<Utc>) -> Result<Uuid> {
+    let (private_key, public_key) = generate_key_pair()?;
+
+    let id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        INSERT INTO jwt_signing_keys (private_key, public_key, activates_at)
+        VALUES ($1, $2, $3)
+        RETURNING id
+        "#,
+    )
+    .bind(private_key)
+    .bind(public_key)
+    .bind(activates_at)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(id)
+}
+
+/// Make sure there is a key to sign with, creating one on first start.
+pub async fn ensure_signing_key(db: &PgPool) -> Result<()> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(KEY_ROTATION_LOCK_ID)
+        .execute(&mut *tx)
+        .await?;
+
+    let has_active_key = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM jwt_signing_keys
+            WHERE activates_at <= NOW() AND (retires_at IS NULL OR retires_at > NOW())
+        )
+        "#,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if !has_active_key {
+        insert_key(&mut tx, Utc::now()).await?;
+        tracing::info!("Created initial token signing key");
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Start rotating to a new key once the newest one is older than `rotation`.
+/// The new key is published straight away and takes over signing after
+/// `KEY_PUBLISH_DELAY_MINUTES`; older keys stay valid for verification for
+/// `grace` after that. Returns true if a rotation was started.
+pub async fn rotate_if_due(db: &PgPool, rotation: Duration, grace: Duration) -> Result<bool> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(KEY_ROTATION_LOCK_ID)
+        .execute(&mut *tx)
+        .await?;
+
+    let newest_activation = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+        "SELECT MAX(activates_at) FROM jwt_signing_keys",
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let now = Utc::now();
+    if newest_activation.is_some_and(|activates_at| activates_at > now - rotation) {
+        return Ok(false);
+    }
+
+    let activates_at = now + Duration::minutes(KEY_PUBLISH_DELAY_MINUTES);
+    let new_key_id = insert_key(&mut tx, activates_at).await?;
+
+    sqlx::query(
+        r#"
+        UPDATE jwt_signing_keys
+        SET retires_at = $2, expires_at = $3
+        WHERE id != $1 AND retires_at IS NULL
+        "#,
+    )
+    .bind(new_key_id)
+    .bind(activates_at)
+    .bind(activates_at + grace)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM jwt_signing_keys WHERE expires_at < NOW()")
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    tracing::info!(
+        "Rotating token signing key, new key {} activates at {}",
+        new_key_id,
+        activates_at
+    );
+
+    Ok(true)
+}
@@ -0,0 +1,286 @@
+use axum::http::{HeaderMap, header::COOKIE};
+use base64::Engine;
+use oauth2::url::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    auth::hash_token,
+    error::{AppError, Result},
+    redis::RedisClient,
+};
+
+const PENDING_LOGIN_TTL_SECONDS: usize = 10 * 60;
+const LOGIN_CODE_TTL_SECONDS: usize = 60;
+
+/// HttpOnly cookie holding the secret the sign-in nonce is derived from, so a
+/// provider response is only accepted in the browser that started the sign-in.
+const BINDING_COOKIE: &str = "oauth_binding";
+
+/// A Google or Apple sign-in in progress, kept until the provider sends the
+/// user back.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingLogin {
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    /// Client page or deep link to hand the result to, instead of JSON
+    pub redirect_uri: Option<String>,
+    /// Used if the sign-in creates a new account
+    pub invite_code: Option<String>,
+}
+
+/// A sign-in tied to the browser that started it: the nonce sent to the
+/// provider is the hash of a secret only that browser holds, in a cookie.
+#[derive(Debug)]
+pub struct BrowserBinding {
+    pub nonce: String,
+    /// `Set-Cookie` value holding the secret
+    pub cookie: String,
+}
+
+/// What a handler needs to send the user to the provider.
+#[derive(Debug)]
+pub struct LoginStart {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    /// `Set-Cookie` value binding the sign-in to this browser
+    pub cookie: String,
+}
+
+fn random_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn pending_login_key(provider: &str, state: &str) -> String {
+    format!("social-login-{}-{}", provider, state)
+}
+
+fn apple_link_key(user_id: Uuid) -> String {
+    format!("apple-link-nonce-{}", user_id)
+}
+
+/// Start a sign-in with `provider`. `cross_site_callback` is for providers
+/// that POST back to us (Apple's form_post), which only carry the cookie if
+/// it's `SameSite=None`.
+pub async fn start_login(
+    state: &AppState,
+    provider: &str,
+    redirect_uri: Option<&str>,
+    invite_code: Option<&str>,
+    cross_site_callback: bool,
+) -> Result<LoginStart> {
+    let allowed_redirect = redirect_uri.is_none_or(|redirect_uri| {
+        state
+            .config
+            .oauth_client_redirect_uris
+            .iter()
+            .any(|allowed| allowed == redirect_uri)
+    });
+
+    if !allowed_redirect {
+        return Err(AppError::BadRequest(
+            "redirect_uri is not an allowed client redirect".to_string(),
+        ));
+    }
+
+    let binding = bind_to_browser(
+        provider,
+        cross_site_callback,
+        state.config.base_url.starts_with("https://"),
+    );
+    let login = LoginStart {
+        state: random_token(),
+        nonce: binding.nonce,
+        code_verifier: random_token(),
+        cookie: binding.cookie,
+    };
+
+    let pending = PendingLogin {
+        provider: provider.to_string(),
+        nonce: login.nonce.clone(),
+        code_verifier: login.code_verifier.clone(),
+        redirect_uri: redirect_uri.map(str::to_string),
+        invite_code: invite_code.map(str::to_string),
+    };
+
+    let pending = serde_json::to_string(&pending)
+        .map_err(|e| AppError::Internal(format!("Failed to serialize sign-in state: {}", e)))?;
+
+    state
+        .redis
+        .cache_set(
+            &pending_login_key(provider, &login.state),
+            &pending,
+            PENDING_LOGIN_TTL_SECONDS,
+        )
+        .await?;
+
+    Ok(login)
+}
+
+/// Take the sign-in a provider redirected back with. Each `state` can only be
+/// used once, and only from the browser holding the matching cookie.
+pub async fn take_pending_login(
+    redis: &RedisClient,
+    provider: &str,
+    state: &str,
+    headers: &HeaderMap,
+) -> Result<PendingLogin> {
+    let key = pending_login_key(provider, state);
+    let invalid = || AppError::Authorization("Invalid OAuth state".to_string());
+
+    let pending = redis.cache_take(&key).await?.ok_or_else(invalid)?;
+
+    let pending: PendingLogin = serde_json::from_str(&pending).map_err(|_| invalid())?;
+
+    if pending.provider != provider {
+        return Err(invalid());
+    }
+
+    verify_binding(headers, &pending.nonce)?;
+
+    Ok(pending)
+}
+
+/// Bind a sign-in with `provider` to the browser starting it. The cookie is
+/// scoped to the provider's routes under `/api/auth/oauth/`.
+/// `cross_site_callback` is for providers that POST back to us.
+pub fn bind_to_browser(provider: &str, cross_site_callback: bool, https: bool) -> BrowserBinding {
+    let secret = random_token();
+
+    BrowserBinding {
+        nonce: hash_token(&secret),
+        cookie: binding_cookie(provider, &secret, cross_site_callback, https),
+    }
+}
+
+/// Check a provider callback comes from the browser holding the secret
+/// behind `nonce`, so nobody can finish a sign-in or link they didn't start.
+pub fn verify_binding(headers: &HeaderMap, nonce: &str) -> Result<()> {
+    let binding = read_cookie(headers, BINDING_COOKIE).ok_or_else(|| {
+        AppError::Authorization(
+            "Sign-in has to be finished in the browser it was started in".to_string(),
+        )
+    })?;
+
+    if hash_token(&binding) != nonce {
+        return Err(AppError::Authorization("Invalid OAuth state".to_string()));
+    }
+
+    Ok(())
+}
+
+fn binding_cookie(provider: &str, value: &str, cross_site: bool, https: bool) -> String {
+    // SameSite=None is only honoured on Secure cookies
+    let same_site = if cross_site && https { "None" } else { "Lax" };
+
+    format!(
+        "{}={}; Path=/api/auth/oauth/{}; Max-Age={}; HttpOnly; SameSite={}{}",
+        BINDING_COOKIE,
+        value,
+        provider,
+        PENDING_LOGIN_TTL_SECONDS,
+        same_site,
+        if https { "; Secure" } else { "" }
+    )
+}
+
+/// `Set-Cookie` value removing the binding cookie once the sign-in is over.
+pub fn clear_binding_cookie(provider: &str) -> String {
+    format!(
+        "{}=; Path=/api/auth/oauth/{}; Max-Age=0; HttpOnly",
+        BINDING_COOKIE, provider
+    )
+}
+
+fn read_cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Start linking Apple from a client that talks to Apple itself (Sign in
+/// with Apple JS or the native SDK). Returns the nonce the client has to put
+/// in its Apple request, so only an ID token obtained for this link is
+/// accepted.
+pub async fn start_apple_link(redis: &RedisClient, user_id: Uuid) -> Result<String> {
+    let nonce = random_token();
+
+    redis
+        .cache_set(&apple_link_key(user_id), &nonce, PENDING_LOGIN_TTL_SECONDS)
+        .await?;
+
+    Ok(nonce)
+}
+
+/// The nonce from `start_apple_link`. Each one can only be used once.
+pub async fn take_apple_link_nonce(redis: &RedisClient, user_id: Uuid) -> Result<String> {
+    redis
+        .cache_take(&apple_link_key(user_id))
+        .await?
+        .ok_or_else(|| AppError::BadRequest("No Apple account link in progress".to_string()))
+}
+
+/// Park a sign-in result behind a short-lived, single-use code, so tokens
+/// never appear in a redirect URL.
+pub async fn create_login_code(redis: &RedisClient, result: &Value) -> Result<String> {
+    let code = random_token();
+
+    redis
+        .cache_set(
+            &format!("social-login-code-{}", hash_token(&code)),
+            &result.to_string(),
+            LOGIN_CODE_TTL_SECONDS,
+        )
+        .await?;
+
+    Ok(code)
+}
+
+/// Redeem a code from `create_login_code` for the sign-in result.
+pub async fn take_login_code(redis: &RedisClient, code: &str) -> Result<Value> {
+    let key = format!("social-login-code-{}", hash_token(code));
+    let invalid = || AppError::Authentication("Invalid or expired login code".to_string());
+
+    let result = redis.cache_take(&key).await?.ok_or_else(invalid)?;
+
+    serde_json::from_str(&result).map_err(|_| invalid())
+}
+
+/// The client redirect URI with `params` added to its query.
+pub fn client_redirect(redirect_uri: &str, params: &[(&str, &str)]) -> Result<String> {
+    let mut url = Url::parse(redirect_uri)
+        .map_err(|e| AppError::Internal(format!("Invalid client redirect URI: {}", e)))?;
+
+    url.query_pairs_mut().extend_pairs(params);
+
+    Ok(url.to_string())
+}
+
+/// Message safe to put in a client redirect for a failed sign-in; internal
+/// errors are logged and replaced with a generic one.
+pub fn client_error_message(error: &AppError) -> String {
+    match error {
+        AppError::Authentication(message)
+        | AppError::Authorization(message)
+        | AppError::BadRequest(message)
+        | AppError::Conflict(message)
+        | AppError::Validation(message) => message.clone(),
+        AppError::RateLimit => "Rate limit exceeded".to_string(),
+        e => {
+            tracing::error!("Social sign-in failed: {}", e);
+            "Sign-in failed, please try again".to_string()
+        }
+    }
+}
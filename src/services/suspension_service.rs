@@ -0,0 +1,273 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    error::{AppError, Result},
+    models::{NotificationType, User, UserStatus},
+    services::{email_service::EmailService, notification_service::NotificationService},
+};
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct UserSuspension {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub issued_by: Uuid,
+    pub issued_by_username: Option<String>,
+    pub reason: String,
+    pub ends_at: Option<DateTime<Utc>>,
+    pub lifted_at: Option<DateTime<Utc>>,
+    pub lifted_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl UserSuspension {
+    fn has_lapsed(&self) -> bool {
+        self.ends_at.is_some_and(|ends_at| ends_at <= Utc::now())
+    }
+
+    /// The message shown when the suspended user tries to sign in.
+    fn sign_in_error(&self) -> AppError {
+        let until = match self.ends_at {
+            Some(ends_at) => format!("until {}", ends_at.format("%Y-%m-%d %H:%M UTC")),
+            None => "indefinitely".to_string(),
+        };
+
+        AppError::Authorization(format!(
+            "Your account has been suspended {}. Reason: {}",
+            until, self.reason
+        ))
+    }
+}
+
+const SUSPENSION_COLUMNS: &str = r#"
+    s.id, s.user_id, s.issued_by, u.username AS issued_by_username, s.reason,
+    s.ends_at, s.lifted_at, s.lifted_by, s.created_at
+"#;
+
+pub async fn get_active_suspension(db: &PgPool, user_id: Uuid) -> Result<Option<UserSuspension>> {
+    let suspension = sqlx::query_as::<_, UserSuspension>(&format!(
+        r#"
+        SELECT {}
+        FROM user_suspensions s
+        LEFT JOIN users u ON u.id = s.issued_by
+        WHERE s.user_id = $1 AND s.lifted_at IS NULL
+        "#,
+        SUSPENSION_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?;
+
+    Ok(suspension)
+}
+
+/// Every suspension the user has had, newest first.
+pub async fn list_suspensions(db: &PgPool, user_id: Uuid) -> Result<Vec<UserSuspension>> {
+    let suspensions = sqlx::query_as::<_, UserSuspension>(&format!(
+        r#"
+        SELECT {}
+        FROM user_suspensions s
+        LEFT JOIN users u ON u.id = s.issued_by
+        WHERE s.user_id = $1
+        ORDER BY s.created_at DESC
+        "#,
+        SUSPENSION_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(suspensions)
+}
+
+/// Mark the user suspended and open a suspension record. Runs on the caller's
+/// connection so it can share a transaction with the audit log entry.
+pub async fn suspend(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    issued_by: Uuid,
+    reason: &str,
+    ends_at: Option<DateTime<Utc>>,
+) -> Result<()> {
+    let updated = sqlx::query("UPDATE users SET status = $2 WHERE id = $1 AND status = 'active'")
+        .bind(user_id)
+        .bind(UserStatus::Suspended)
+        .execute(&mut *conn)
+        .await?;
+
+    if updated.rows_affected() == 0 {
+        return Err(AppError::Conflict("User is already suspended".to_string()));
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO user_suspensions (user_id, issued_by, reason, ends_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(user_id)
+    .bind(issued_by)
+    .bind(reason.trim())
+    .bind(ends_at)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Reinstate a suspended user. `lifted_by` is the staff member who lifted the
+/// suspension, or `None` when it ran out. Returns false if the user wasn't
+/// suspended.
+pub async fn lift(conn: &mut PgConnection, user_id: Uuid, lifted_by: Option<Uuid>) -> Result<bool> {
+    // Closed even if the account has since been deleted, so it isn't picked up again
+    sqlx::query(
+        r#"
+        UPDATE user_suspensions
+        SET lifted_at = NOW(), lifted_by = $2
+        WHERE user_id = $1 AND lifted_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(lifted_by)
+    .execute(&mut *conn)
+    .await?;
+
+    let updated =
+        sqlx::query("UPDATE users SET status = $2 WHERE id = $1 AND status = 'suspended'")
+            .bind(user_id)
+            .bind(UserStatus::Active)
+            .execute(&mut *conn)
+            .await?;
+
+    Ok(updated.rows_affected() > 0)
+}
+
+/// Users whose suspension has run out but who haven't been reinstated yet.
+pub async fn get_expired_suspensions(db: &PgPool, limit: i64) -> Result<Vec<Uuid>> {
+    let user_ids = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        SELECT user_id FROM user_suspensions
+        WHERE lifted_at IS NULL AND ends_at <= NOW()
+        ORDER BY ends_at
+        LIMIT $1
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(db)
+    .await?;
+
+    Ok(user_ids)
+}
+
+/// Reinstate a user whose suspension has run out and let them know.
+pub async fn reinstate_expired(
+    db: &PgPool,
+    notification_service: &NotificationService,
+    email_service: &EmailService,
+    user_id: Uuid,
+) -> Result<bool> {
+    let mut tx = db.begin().await?;
+    let reinstated = lift(&mut tx, user_id, None).await?;
+    tx.commit().await?;
+
+    if !reinstated {
+        return Ok(false);
+    }
+
+    notify_reinstated(db, notification_service, email_service, user_id).await;
+
+    Ok(true)
+}
+
+/// Tell a user their suspension is over, in the app and by email. Failures are
+/// only logged; the reinstatement itself has already happened.
+pub async fn notify_reinstated(
+    db: &PgPool,
+    notification_service: &NotificationService,
+    email_service: &EmailService,
+    user_id: Uuid,
+) {
+    if let Err(e) = notification_service
+        .create_notification(
+            user_id,
+            None,
+            NotificationType::SystemAnnouncement,
+            "Your account has been reinstated".to_string(),
+            Some("Your suspension has ended. You can post, comment and vote again.".to_string()),
+            None,
+            None,
+            None,
+        )
+        .await
+    {
+        tracing::warn!("Failed to notify user {} of reinstatement: {}", user_id, e);
+    }
+
+    let recipient = sqlx::query_as::<_, (String, Option<String>)>(
+        "SELECT username, email FROM users WHERE id = $1 AND email_verified = true",
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await;
+
+    match recipient {
+        Ok(Some((username, Some(email)))) => {
+            if let Err(e) = email_service
+                .send_suspension_lifted_email(&email, &username)
+                .await
+            {
+                tracing::warn!(
+                    "Failed to email user {} about reinstatement: {}",
+                    user_id,
+                    e
+                );
+            }
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!(
+            "Failed to look up user {} for reinstatement email: {}",
+            user_id,
+            e
+        ),
+    }
+}
+
+/// Reject a sign-in or token refresh by a suspended user with the reason and
+/// end date. A suspension that has run out but hasn't been picked up by the
+/// background job yet is lifted on the spot.
+pub async fn ensure_not_suspended(state: &AppState, user: &User) -> Result<()> {
+    if !matches!(user.status, UserStatus::Suspended) {
+        return Ok(());
+    }
+
+    let Some(suspension) = get_active_suspension(&state.db, user.id).await? else {
+        // Suspended before suspensions were recorded
+        return Err(AppError::Authorization(
+            "Your account has been suspended".to_string(),
+        ));
+    };
+
+    if !suspension.has_lapsed() {
+        return Err(suspension.sign_in_error());
+    }
+
+    let notification_service = NotificationService::new(
+        state.db.clone(),
+        state.redis.clone(),
+        state.email_service.clone(),
+        state.sms_service.clone(),
+    );
+
+    reinstate_expired(
+        &state.db,
+        &notification_service,
+        &state.email_service,
+        user.id,
+    )
+    .await?;
+
+    Ok(())
+}
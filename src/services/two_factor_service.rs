@@ -0,0 +1,290 @@
+use rand::Rng;
+use sqlx::PgPool;
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
+
+use crate::{
+    auth::hash_token,
+    error::{AppError, Result},
+    models::UserTotpSecret,
+    redis::RedisClient,
+};
+
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP_SECONDS: u64 = 30;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+const LOGIN_CHALLENGE_TTL_SECONDS: usize = 300;
+
+/// Generate a new 160-bit TOTP secret, base32 encoded.
+pub fn generate_secret() -> String {
+    let bytes: [u8; 20] = rand::random();
+    Secret::Raw(bytes.to_vec()).to_encoded().to_string()
+}
+
+fn build_totp(secret: &str, issuer: Option<String>, account_name: String) -> Result<TOTP> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| AppError::Internal(format!("Invalid TOTP secret: {}", e)))?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        0,
+        TOTP_STEP_SECONDS,
+        secret,
+        issuer,
+        account_name,
+    )
+    .map_err(|e| AppError::Internal(format!("Failed to build TOTP: {}", e)))
+}
+
+/// Build the `otpauth://` URI that authenticator apps scan as a QR code.
+pub fn otpauth_uri(secret: &str, issuer: &str, account_name: &str) -> Result<String> {
+    // The otpauth label uses ':' as the issuer/account separator
+    let totp = build_totp(
+        secret,
+        Some(issuer.replace(':', "")),
+        account_name.replace(':', ""),
+    )?;
+
+    Ok(totp.get_url())
+}
+
+pub async fn get_totp_secret(db: &PgPool, user_id: Uuid) -> Result<Option<UserTotpSecret>> {
+    let secret =
+        sqlx::query_as::<_, UserTotpSecret>("SELECT * FROM user_totp_secrets WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(db)
+            .await?;
+
+    Ok(secret)
+}
+
+pub async fn is_enabled(db: &PgPool, user_id: Uuid) -> Result<bool> {
+    let enabled = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM user_totp_secrets WHERE user_id = $1 AND confirmed_at IS NOT NULL)",
+    )
+    .bind(user_id)
+    .fetch_one(db)
+    .await?;
+
+    Ok(enabled)
+}
+
+/// Store a fresh, unconfirmed secret for the user, replacing any earlier
+/// enrollment attempt that was never confirmed.
+pub async fn begin_enrollment(db: &PgPool, user_id: Uuid) -> Result<String> {
+    let secret = generate_secret();
+
+    let result = sqlx::query(
+        r#"
+        INSERT INTO user_totp_secrets (user_id, secret)
+        VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE
+        SET secret = EXCLUDED.secret, last_used_step = NULL
+        WHERE user_totp_secrets.confirmed_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(&secret)
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::Conflict(
+            "Two-factor authentication is already enabled".to_string(),
+        ));
+    }
+
+    Ok(secret)
+}
+
+pub async fn confirm_enrollment(db: &PgPool, user_id: Uuid) -> Result<()> {
+    sqlx::query(
+        "UPDATE user_totp_secrets SET confirmed_at = NOW() WHERE user_id = $1 AND confirmed_at IS NULL",
+    )
+    .bind(user_id)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn disable(db: &PgPool, user_id: Uuid) -> Result<()> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query("DELETE FROM user_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM user_totp_secrets WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Check a TOTP code against the user's secret, allowing one step of clock
+/// drift either way. A code is only accepted once: the matching time step is
+/// recorded and any code from that step or earlier is rejected afterwards.
+pub async fn verify_totp(db: &PgPool, totp_secret: &UserTotpSecret, code: &str) -> Result<bool> {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS || !code.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(false);
+    }
+
+    let totp = build_totp(&totp_secret.secret, None, String::new())?;
+    let current_step = (chrono::Utc::now().timestamp() as u64) / TOTP_STEP_SECONDS;
+
+    let matched_step = [current_step - 1, current_step, current_step + 1]
+        .into_iter()
+        .find(|step| totp.check(code, step * TOTP_STEP_SECONDS));
+
+    let Some(step) = matched_step else {
+        return Ok(false);
+    };
+
+    // Conditional update so two concurrent requests can't both spend the same code
+    let result = sqlx::query(
+        r#"
+        UPDATE user_totp_secrets
+        SET last_used_step = $2
+        WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+        "#,
+    )
+    .bind(totp_secret.user_id)
+    .bind(step as i64)
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+fn generate_recovery_code() -> String {
+    let mut rng = rand::rng();
+    let code: String = (0..10)
+        .map(|_| {
+            let idx = rng.random_range(0..RECOVERY_CODE_ALPHABET.len());
+            RECOVERY_CODE_ALPHABET[idx] as char
+        })
+        .collect();
+
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Replace the user's recovery codes with a new set. The plaintext codes are
+/// returned once and only their hashes are stored.
+pub async fn generate_recovery_codes(db: &PgPool, user_id: Uuid) -> Result<Vec<String>> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+
+    let mut tx = db.begin().await?;
+
+    sqlx::query("DELETE FROM user_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    for code in &codes {
+        sqlx::query("INSERT INTO user_recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(hash_token(&normalize_recovery_code(code)))
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(codes)
+}
+
+pub async fn count_unused_recovery_codes(db: &PgPool, user_id: Uuid) -> Result<i64> {
+    let count = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM user_recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .fetch_one(db)
+    .await?;
+
+    Ok(count)
+}
+
+/// Spend a recovery code. Returns false if it doesn't exist or was already used.
+pub async fn use_recovery_code(db: &PgPool, user_id: Uuid, code: &str) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE user_recovery_codes
+        SET used_at = NOW()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(hash_token(&normalize_recovery_code(code)))
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Verify either a TOTP code or a recovery code, whichever was supplied.
+pub async fn verify_second_factor(
+    db: &PgPool,
+    totp_secret: &UserTotpSecret,
+    code: Option<&str>,
+    recovery_code: Option<&str>,
+) -> Result<bool> {
+    match (code, recovery_code) {
+        (Some(code), _) => verify_totp(db, totp_secret, code).await,
+        (None, Some(recovery_code)) => {
+            use_recovery_code(db, totp_secret.user_id, recovery_code).await
+        }
+        (None, None) => Err(AppError::BadRequest(
+            "A two-factor code or recovery code is required".to_string(),
+        )),
+    }
+}
+
+// Login challenges: issued after a correct password when 2FA is enabled and
+// exchanged for a session once the second factor is verified.
+pub async fn create_login_challenge(redis: &RedisClient, user_id: Uuid) -> Result<String> {
+    let challenge_token = Uuid::new_v4().to_string();
+    let key = format!("2fa_challenge:{}", challenge_token);
+
+    redis
+        .cache_set(&key, &user_id.to_string(), LOGIN_CHALLENGE_TTL_SECONDS)
+        .await?;
+
+    Ok(challenge_token)
+}
+
+pub async fn get_login_challenge(
+    redis: &RedisClient,
+    challenge_token: &str,
+) -> Result<Option<Uuid>> {
+    let key = format!("2fa_challenge:{}", challenge_token);
+
+    let user_id = redis
+        .cache_get(&key)
+        .await?
+        .and_then(|id| Uuid::parse_str(&id).ok());
+
+    Ok(user_id)
+}
+
+pub async fn clear_login_challenge(redis: &RedisClient, challenge_token: &str) -> Result<()> {
+    let key = format!("2fa_challenge:{}", challenge_token);
+    redis.cache_delete(&key).await
+}
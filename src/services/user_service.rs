@@ -3,9 +3,8 @@ use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::{
-    AppState,
-    error::{AppError, Result},
-    models::{NotificationType, User},
+    error::Result,
+    models::{SiteRole, User},
 };
 
 #[derive(Debug, Serialize)]
@@ -37,14 +36,26 @@ pub async fn get_user_by_username(db: &PgPool, username: &str) -> Result<Option<
     Ok(user)
 }
 
+/// Site role of an active account, or `None` if the account is suspended or gone.
+pub async fn get_active_site_role(db: &PgPool, user_id: Uuid) -> Result<Option<SiteRole>> {
+    let site_role = sqlx::query_scalar::<_, SiteRole>(
+        "SELECT site_role FROM users WHERE id = $1 AND status = 'active'",
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?;
+
+    Ok(site_role)
+}
+
 pub async fn get_user_stats(db: &PgPool, user_id: Uuid) -> Result<UserStats> {
     let stats = sqlx::query!(
         r#"
         SELECT 
             (SELECT COUNT(*)::int FROM posts WHERE author_id = $1 AND status != 'deleted') as post_count,
             (SELECT COUNT(*)::int FROM comments WHERE author_id = $1 AND status != 'deleted') as comment_count,
-            (SELECT COUNT(*)::int FROM user_follows WHERE following_id = $1) as follower_count,
-            (SELECT COUNT(*)::int FROM user_follows WHERE follower_id = $1) as following_count
+            (SELECT follower_count FROM users WHERE id = $1) as follower_count,
+            (SELECT following_count FROM users WHERE id = $1) as following_count
         "#,
         user_id
     )
@@ -82,54 +93,3 @@ pub async fn is_blocked(db: &PgPool, blocker_id: Uuid, blocked_id: Uuid) -> Resu
 
     Ok(exists.exists.unwrap_or(false))
 }
-
-pub async fn create_follow_notification(
-    state: &AppState,
-    follower_id: Uuid,
-    following_id: Uuid,
-) -> Result<()> {
-    // Get follower info
-    let follower = get_user_by_id(&state.db, follower_id)
-        .await?
-        .ok_or_else(|| AppError::NotFound("Follower not found".to_string()))?;
-
-    // Create notification
-    sqlx::query(
-        r#"
-        INSERT INTO notifications (
-            id, user_id, notification_type, title, message, 
-            related_user_id, created_at
-        )
-        VALUES ($1, $2, $3, $4, $5, $6, $7)
-        "#,
-    )
-    .bind(Uuid::new_v4())
-    .bind(following_id)
-    .bind(NotificationType::Follow)
-    .bind("New Follower")
-    .bind(format!("{} started following you", follower.username))
-    .bind(follower_id)
-    .bind(chrono::Utc::now())
-    .execute(&state.db)
-    .await?;
-
-    // Send real-time notification
-    let notification_data = serde_json::json!({
-        "type": "follow",
-        "follower": {
-            "id": follower.id,
-            "username": follower.username,
-            "avatar_url": follower.avatar_url
-        }
-    });
-
-    state
-        .redis
-        .publish(
-            &format!("user_notifications:{}", following_id),
-            &notification_data.to_string(),
-        )
-        .await?;
-
-    Ok(())
-}
@@ -0,0 +1,269 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{AppError, Result},
+    models::User,
+    services::user_service,
+};
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct UsernameChange {
+    pub old_username: String,
+    pub new_username: String,
+    /// Until then nobody else can take `old_username`
+    pub reserved_until: DateTime<Utc>,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// How many more times a user can change their username right now.
+#[derive(Debug, Serialize)]
+pub struct UsernameAllowance {
+    pub changes_remaining: i64,
+    /// When the oldest change in the current period stops counting, if the
+    /// user has none left
+    pub next_change_at: Option<DateTime<Utc>>,
+}
+
+/// A user looked up by a name they have now or had before.
+#[derive(Debug)]
+pub struct ResolvedUser {
+    pub user: User,
+    /// The name looked up is one the user has since changed away from
+    pub renamed: bool,
+}
+
+/// Look up a user by username, falling back to whoever most recently changed
+/// away from it. A current username always wins, so once a reservation has
+/// run out and someone else takes the name, it stops resolving to the old
+/// owner.
+pub async fn resolve(db: &PgPool, username: &str) -> Result<Option<ResolvedUser>> {
+    if let Some(user) = user_service::get_user_by_username(db, username).await? {
+        return Ok(Some(ResolvedUser {
+            user,
+            renamed: false,
+        }));
+    }
+
+    let user = sqlx::query_as::<_, User>(
+        r#"
+        SELECT u.* FROM username_history h
+        JOIN users u ON h.user_id = u.id
+        WHERE h.old_username = $1 AND u.status != 'deleted'
+        ORDER BY h.changed_at DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(username)
+    .fetch_optional(db)
+    .await?;
+
+    Ok(user.map(|user| ResolvedUser {
+        user,
+        renamed: true,
+    }))
+}
+
+/// Whether a name someone changed away from is still held for them.
+/// `except_user_id` ignores that user's own reservations, so they can take
+/// an old name back.
+pub async fn is_reserved(
+    conn: &mut PgConnection,
+    username: &str,
+    except_user_id: Option<Uuid>,
+) -> Result<bool> {
+    let reserved = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM username_history
+            WHERE old_username = $1 AND reserved_until > NOW()
+                AND ($2::uuid IS NULL OR user_id != $2)
+        )
+        "#,
+    )
+    .bind(username)
+    .bind(except_user_id)
+    .fetch_one(conn)
+    .await?;
+
+    Ok(reserved)
+}
+
+/// Whether a new account can be given this username.
+pub async fn is_available(db: &PgPool, username: &str) -> Result<bool> {
+    if user_service::get_user_by_username(db, username)
+        .await?
+        .is_some()
+    {
+        return Ok(false);
+    }
+
+    let mut conn = db.acquire().await?;
+    Ok(!is_reserved(&mut conn, username, None).await?)
+}
+
+/// The user's past usernames, most recent change first.
+pub async fn get_history(db: &PgPool, user_id: Uuid) -> Result<Vec<UsernameChange>> {
+    let history = sqlx::query_as::<_, UsernameChange>(
+        r#"
+        SELECT old_username, new_username, reserved_until, changed_at
+        FROM username_history
+        WHERE user_id = $1
+        ORDER BY changed_at DESC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(history)
+}
+
+pub async fn get_allowance(
+    db: &PgPool,
+    config: &Config,
+    user_id: Uuid,
+) -> Result<UsernameAllowance> {
+    let mut conn = db.acquire().await?;
+    let recent = get_recent_changes(&mut conn, config, user_id).await?;
+
+    Ok(allowance(config, &recent))
+}
+
+/// Rename a user, holding on to their old name for them for the configured
+/// reservation period. Only the configured number of changes are allowed per
+/// period.
+pub async fn change_username(
+    db: &PgPool,
+    config: &Config,
+    user_id: Uuid,
+    new_username: &str,
+) -> Result<UsernameChange> {
+    if config.username_change_limit <= 0 {
+        return Err(AppError::Authorization(
+            "Usernames can't be changed".to_string(),
+        ));
+    }
+
+    let mut tx = db.begin().await?;
+
+    // Serialises concurrent requests from the same user so the limit holds
+    let old_username = sqlx::query_scalar::<_, String>(
+        "SELECT username FROM users WHERE id = $1 AND status != 'deleted' FOR UPDATE",
+    )
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    if old_username == new_username {
+        return Err(AppError::BadRequest(
+            "This is already your username".to_string(),
+        ));
+    }
+
+    let recent = get_recent_changes(&mut tx, config, user_id).await?;
+    if let Some(next_change_at) = allowance(config, &recent).next_change_at {
+        return Err(AppError::BadRequest(format!(
+            "You can change your username {} times every {} days. Your next change is available at {}",
+            config.username_change_limit,
+            config.username_change_period_days,
+            next_change_at.to_rfc3339()
+        )));
+    }
+
+    let taken = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM users WHERE username = $1 AND status != 'deleted')",
+    )
+    .bind(new_username)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if taken {
+        return Err(AppError::Conflict("Username already exists".to_string()));
+    }
+
+    if is_reserved(&mut tx, new_username, Some(user_id)).await? {
+        return Err(AppError::Conflict(
+            "This username was recently used by someone else and is reserved".to_string(),
+        ));
+    }
+
+    // Another account may take the name between the check and the update
+    sqlx::query("UPDATE users SET username = $2, updated_at = NOW() WHERE id = $1")
+        .bind(user_id)
+        .bind(new_username)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(ref db_error) if db_error.is_unique_violation() => {
+                AppError::Conflict("Username already exists".to_string())
+            }
+            e => e.into(),
+        })?;
+
+    let change = sqlx::query_as::<_, UsernameChange>(
+        r#"
+        INSERT INTO username_history (user_id, old_username, new_username, reserved_until)
+        VALUES ($1, $2, $3, NOW() + make_interval(days => $4))
+        RETURNING old_username, new_username, reserved_until, changed_at
+        "#,
+    )
+    .bind(user_id)
+    .bind(&old_username)
+    .bind(new_username)
+    .bind(config.username_reservation_days as i32)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(change)
+}
+
+/// When each of the user's changes within the current period was made,
+/// oldest first.
+async fn get_recent_changes(
+    conn: &mut PgConnection,
+    config: &Config,
+    user_id: Uuid,
+) -> Result<Vec<DateTime<Utc>>> {
+    let since = Utc::now() - Duration::days(config.username_change_period_days);
+
+    let changes = sqlx::query_scalar::<_, DateTime<Utc>>(
+        r#"
+        SELECT changed_at FROM username_history
+        WHERE user_id = $1 AND changed_at > $2
+        ORDER BY changed_at
+        "#,
+    )
+    .bind(user_id)
+    .bind(since)
+    .fetch_all(conn)
+    .await?;
+
+    Ok(changes)
+}
+
+fn allowance(config: &Config, recent: &[DateTime<Utc>]) -> UsernameAllowance {
+    let limit = config.username_change_limit.max(0);
+    let changes_remaining = (limit - recent.len() as i64).max(0);
+
+    // The change that has to age out before another is allowed
+    let next_change_at = if changes_remaining > 0 {
+        None
+    } else {
+        let blocking = recent.len().saturating_sub(limit as usize);
+        recent
+            .get(blocking)
+            .map(|changed_at| *changed_at + Duration::days(config.username_change_period_days))
+    };
+
+    UsernameAllowance {
+        changes_remaining,
+        next_change_at,
+    }
+}